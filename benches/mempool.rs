@@ -1,8 +1,5 @@
-#![feature(test)]
 //#[cfg(feature = "benchmark-internals")]
 
-extern crate test;
-
 // use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
 
 // use rustorrent::cache_line::CacheAligned;
@@ -1,15 +1,17 @@
 
 use std::mem::MaybeUninit;
 use std::ptr::NonNull;
-use std::sync::atomic::Ordering::*;
-use std::sync::atomic::AtomicPtr;
+use std::pin::Pin;
+use crate::sync::atomic::Ordering::*;
+use crate::sync::atomic::AtomicPtr;
 use std::sync::Arc;
 use std::cell::Cell;
 
 use crate::block::Block;
 use crate::page::arena::{PageArena, drop_page};
 use crate::common::{Pointer, BLOCK_PER_PAGE};
-use crate::{ArenaRc, ArenaBox, ArenaArc};
+use crate::{ArenaRc, ArenaBox, ArenaArc, AutoShrink, ShrinkReport};
+use crate::shrink::{AUTO_SHRINK_BUDGET, ShrinkAction, ShrinkState};
 
 /// An arena
 ///
@@ -38,6 +40,11 @@ pub struct Arena<T: Sized> {
     pending_free_list: Arc<AtomicPtr<PageArena<T>>>,
     full_list: AtomicPtr<PageArena<T>>,
     npages: Cell<usize>,
+    /// State of the `AutoShrink` policy, shared with the pages
+    shrink: Option<Arc<ShrinkState>>,
+    /// Page of the free list where the next step of `AutoShrink`
+    /// resumes, null to start from the beginning
+    shrink_cursor: Pointer<PageArena<T>>,
 }
 
 unsafe impl<T: Sized> Send for Arena<T> {}
@@ -46,12 +53,12 @@ impl<T: Sized> Arena<T> {
     fn alloc_new_page(&self) {
         let to_allocate = self.npages
                               .get()
-                              .max(1)
-                              .min(900_000);
+                              .clamp(1, 900_000);
 
-        let (first, mut last) = PageArena::make_list(to_allocate, &self.pending_free_list);
+        let (first, mut last) = PageArena::make_list(to_allocate, &self.pending_free_list, &self.shrink);
 
         let first_ptr = first.as_ptr();
+        let last_ptr = last.as_ptr();
         let last_ref = unsafe { last.as_mut() };
 
         // We have to touch self.page_list before self.free because
@@ -62,6 +69,9 @@ impl<T: Sized> Arena<T> {
 
         let current = self.full_list.load(Relaxed);
         last_ref.next.store(current, Relaxed);
+        if let Some(current) = unsafe { current.as_ref() } {
+            current.prev.set(last_ptr);
+        }
         self.full_list.swap(first_ptr, AcqRel);
 
         let current = self.free_list.get();
@@ -71,9 +81,37 @@ impl<T: Sized> Arena<T> {
 
         // self.npages.fetch_add(to_allocate, Relaxed);
         self.npages.set(self.npages.get() + to_allocate);
+        if let Some(state) = &self.shrink {
+            state.add_pages(to_allocate);
+        }
     }
 
     fn find_place(&self) -> NonNull<Block<T>> {
+        let block = self.acquire_place();
+
+        if let Some(action) = self.shrink.as_ref().and_then(|state| state.allocated()) {
+            self.auto_shrink_step(action);
+        }
+
+        block
+    }
+
+    /// Takes a step of the `AutoShrink` policy, examining a few pages
+    fn auto_shrink_step(&self, action: ShrinkAction) {
+        let Some(state) = &self.shrink else { return };
+
+        match action {
+            ShrinkAction::All => {
+                let report = self.shrink_budget(AUTO_SHRINK_BUDGET, |_, free| free);
+                state.shrunk(&report);
+            }
+            ShrinkAction::Idle => {
+                self.shrink_budget(AUTO_SHRINK_BUDGET, |page, free| page.idle.replace(free));
+            }
+        }
+    }
+
+    fn acquire_place(&self) -> NonNull<Block<T>> {
         loop {
             while let Some(page) = unsafe { self.free_list.get().as_mut() } {
 
@@ -85,6 +123,11 @@ impl<T: Sized> Arena<T> {
                 let next = page.next_free.load(Acquire);
                 self.free_list.set(next);
                 page.in_free_list.store(false, Release);
+                page.idle.set(false);
+
+                if std::ptr::eq(self.shrink_cursor.get(), page) {
+                    self.shrink_cursor.set(std::ptr::null_mut());
+                }
             }
 
             let pending = self.pending_free_list.load(Relaxed);
@@ -117,16 +160,37 @@ impl<T: Sized> Arena<T> {
     /// # arena.alloc(1);
     /// ```
     pub fn with_capacity(cap: usize) -> Arena<T> {
+        Arena::with_capacity_and_shrink(cap, AutoShrink::new())
+    }
+
+    /// Constructs a new `Arena` capable of holding at least `cap` elements,
+    /// and shrinking itself with the policy `auto_shrink`
+    ///
+    /// See [`AutoShrink`] for the policy.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{Arena, AutoShrink};
+    /// let arena = Arena::with_capacity_and_shrink(2048, AutoShrink::new().free_ratio(75));
+    /// # arena.alloc(1);
+    /// ```
+    ///
+    /// [`AutoShrink`]: ./struct.AutoShrink.html
+    pub fn with_capacity_and_shrink(cap: usize, auto_shrink: AutoShrink) -> Arena<T> {
         let npages = ((cap.max(1) - 1) / BLOCK_PER_PAGE) + 1;
         let pending_free = Arc::new(AtomicPtr::new(std::ptr::null_mut()));
 
-        let (first, _) = PageArena::make_list(npages, &pending_free);
+        let shrink = ShrinkState::new(auto_shrink, npages);
+        let (first, _) = PageArena::make_list(npages, &pending_free, &shrink);
 
         Arena {
             npages: Cell::new(npages),
             free_list: Cell::new(first.as_ptr()),
             pending_free_list: pending_free,
             full_list: AtomicPtr::new(first.as_ptr()),
+            shrink,
+            shrink_cursor: Cell::new(std::ptr::null_mut()),
         }
     }
 
@@ -178,14 +242,16 @@ impl<T: Sized> Arena<T> {
     /// The difference with [`alloc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When the [`ArenaBox`] is dropped, the value is also
     /// dropped. If the value is not initialized correctly, it will
@@ -223,22 +289,60 @@ impl<T: Sized> Arena<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_with<F>(&self, initializer: F) -> ArenaBox<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = ArenaBox::new(block);
-
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
+        ArenaBox::new(Block::init(self.find_place(), initializer))
+    }
 
-        result
+    /// Finds an empty space in the arena and calls the function
+    /// `initializer` with its argument pointing to that space.
+    /// It returns a [`ArenaBox`] pointing to the newly initialized value,
+    /// or the error returned by `initializer`.
+    ///
+    /// On error, or if `initializer` panics, the space is given back
+    /// to the arena and the value is not dropped.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of `initializer`.
+    ///
+    /// ## Panics
+    ///
+    /// Like [`alloc_with`], if `initializer` returns a reference
+    /// different from its parameter.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::Arena;
+    /// # use std::io::Read;
+    /// let arena = Arena::<[u8; 4]>::new();
+    ///
+    /// let mut source: &[u8] = b"abcd";
+    /// let data = arena.try_alloc_with(|uninit| {
+    ///     let buffer = uninit.write([0; 4]);
+    ///     source.read_exact(buffer)?;
+    ///     Ok::<_, std::io::Error>(&*buffer)
+    /// }).unwrap();
+    /// assert_eq!(&*data, b"abcd");
+    ///
+    /// // The source is empty, the space is given back to the arena
+    /// let result = arena.try_alloc_with(|uninit| {
+    ///     let buffer = uninit.write([0; 4]);
+    ///     source.read_exact(buffer)?;
+    ///     Ok::<_, std::io::Error>(&*buffer)
+    /// });
+    /// assert!(result.is_err());
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`ArenaBox`]: ./struct.ArenaBox.html
+    /// [`alloc_with`]: #method.alloc_with
+    pub fn try_alloc_with<F, E>(&self, initializer: F) -> Result<ArenaBox<T>, E>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> Result<&T, E>
+    {
+        Block::try_init(self.find_place(), initializer).map(ArenaBox::new)
     }
 
     /// Writes a value in the arena, and returns an [`ArenaArc`]
@@ -273,14 +377,16 @@ impl<T: Sized> Arena<T> {
     /// The difference with [`alloc_arc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When all [`ArenaArc`] pointing that value are dropped, the value
     /// is also dropped. If the value is not initialized correctly, it will
@@ -318,22 +424,114 @@ impl<T: Sized> Arena<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_arc_with<F>(&self, initializer: F) -> ArenaArc<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = ArenaArc::new(block);
+        ArenaArc::new(Block::init(self.find_place(), initializer))
+    }
 
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
+    /// Writes a value in the arena, and returns a pinned [`ArenaBox`]
+    /// pointing to that value.
+    ///
+    /// The value never moves in the arena, so it can be used for
+    /// self-referential values and futures.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, Arena};
+    /// # use std::pin::Pin;
+    /// let arena = Arena::new();
+    /// let my_num: Pin<ArenaBox<u8>> = arena.alloc_pin(0xFF);
+    ///
+    /// assert_eq!(*my_num, 255);
+    /// ```
+    ///
+    /// [`ArenaBox`]: ./struct.ArenaBox.html
+    pub fn alloc_pin(&self, value: T) -> Pin<ArenaBox<T>> {
+        ArenaBox::into_pin(self.alloc(value))
+    }
 
-        result
+    /// Writes a value in the arena, and returns a pinned [`ArenaArc`]
+    /// pointing to that value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, Arena};
+    /// # use std::pin::Pin;
+    /// let arena = Arena::new();
+    /// let my_num: Pin<ArenaArc<u8>> = arena.alloc_arc_pin(0xFF);
+    ///
+    /// assert_eq!(*my_num.clone(), 255);
+    /// ```
+    ///
+    /// [`ArenaArc`]: ./struct.ArenaArc.html
+    pub fn alloc_arc_pin(&self, value: T) -> Pin<ArenaArc<T>> {
+        ArenaArc::into_pin(self.alloc_arc(value))
+    }
+
+    /// Finds an empty space in the arena and returns an [`ArenaBox`]
+    /// pointing to it, without initializing the value.
+    ///
+    /// The value can be initialized in several steps through the
+    /// `MaybeUninit`, then converted to an `ArenaBox<T>` with
+    /// [`ArenaBox::assume_init`], without copy.  
+    /// Dropping the `ArenaBox<MaybeUninit<T>>` releases the space
+    /// without dropping the value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, Arena};
+    /// # use std::ptr::addr_of_mut;
+    /// struct Header {
+    ///     id: u32,
+    ///     len: u16,
+    /// }
+    ///
+    /// let arena = Arena::<Header>::new();
+    /// let mut header = arena.alloc_uninit();
+    ///
+    /// unsafe {
+    ///     let ptr = header.as_mut_ptr();
+    ///     addr_of_mut!((*ptr).id).write(1);
+    ///     addr_of_mut!((*ptr).len).write(64);
+    /// }
+    ///
+    /// let header: ArenaBox<Header> = unsafe { header.assume_init() };
+    /// assert_eq!((header.id, header.len), (1, 64));
+    /// ```
+    ///
+    /// [`ArenaBox`]: ./struct.ArenaBox.html
+    /// [`ArenaBox::assume_init`]: ./struct.ArenaBox.html#method.assume_init
+    pub fn alloc_uninit(&self) -> ArenaBox<MaybeUninit<T>> {
+        // MaybeUninit<T> has the same layout as T
+        ArenaBox::new(self.find_place().cast())
+    }
+
+    /// Finds an empty space in the arena and returns an [`ArenaArc`]
+    /// pointing to it, without initializing the value.
+    ///
+    /// See [`alloc_uninit`] and [`ArenaArc::assume_init`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, Arena};
+    /// let arena = Arena::new();
+    /// let mut my_num = arena.alloc_arc_uninit();
+    ///
+    /// ArenaArc::get_mut(&mut my_num).unwrap().write(10);
+    ///
+    /// let my_num: ArenaArc<i32> = unsafe { my_num.assume_init() };
+    /// assert_eq!(*my_num.clone(), 10);
+    /// ```
+    ///
+    /// [`ArenaArc`]: ./struct.ArenaArc.html
+    /// [`alloc_uninit`]: #method.alloc_uninit
+    /// [`ArenaArc::assume_init`]: ./struct.ArenaArc.html#method.assume_init
+    pub fn alloc_arc_uninit(&self) -> ArenaArc<MaybeUninit<T>> {
+        ArenaArc::new(self.find_place().cast())
     }
 
     /// Writes a value in the arena, and returns an [`ArenaRc`]
@@ -368,14 +566,16 @@ impl<T: Sized> Arena<T> {
     /// The difference with [`alloc_rc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When all [`ArenaRc`] pointing that value are dropped, the value
     /// is also dropped. If the value is not initialized correctly, it will
@@ -413,22 +613,9 @@ impl<T: Sized> Arena<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_rc_with<F>(&self, initializer: F) -> ArenaRc<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = ArenaRc::new(block);
-
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
-
-        result
+        ArenaRc::new(Block::init(self.find_place(), initializer))
     }
 
     /// Shrinks the capacity of the arena as much as possible.
@@ -441,7 +628,8 @@ impl<T: Sized> Arena<T> {
     /// This is a slow function and it should not be called in a hot
     /// path.
     ///
-    /// The dedicated memory will be deallocated during this call.
+    /// The dedicated memory will be deallocated during this call.  
+    /// It returns a [`ShrinkReport`] of the pages freed.
     ///
     /// ## Example
     ///
@@ -462,13 +650,24 @@ impl<T: Sized> Arena<T> {
     /// assert!(used == 80, free == 46);
     ///
     /// ```
-    pub fn shrink_to_fit(&self) -> bool {
+    ///
+    /// [`ShrinkReport`]: ./struct.ShrinkReport.html
+    pub fn shrink_to_fit(&self) -> ShrinkReport {
+        self.shrink_pages(|_, free| free)
+    }
+
+    /// Drops the fully free pages of the free list for which `release`
+    /// returns `true`
+    ///
+    /// `release` is called on each page of the free list, with whether
+    /// it is fully free.
+    fn shrink_pages(&self, mut release: impl FnMut(&PageArena<T>, bool) -> bool) -> ShrinkReport {
         let mut current: &AtomicPtr<PageArena<T>> = &AtomicPtr::new(self.free_list.get());
         self.free_list.set(std::ptr::null_mut());
 
         let start = current;
 
-        let mut to_drop = Vec::with_capacity(self.npages.get());
+        let mut to_drop = Vec::new();
 
         // We loop on the free list to get all pages that have 0 reference to
         // them and remove them from the free list
@@ -476,7 +675,9 @@ impl<T: Sized> Arena<T> {
             let next = &current_value.next_free;
             let next_value = next.load(Relaxed);
 
-            if current_value.bitfield.get() | current_value.bitfield_atomic.load(Acquire) == !0 {
+            let free = current_value.bitfield.get() | current_value.bitfield_atomic.load(Acquire) == !0;
+
+            if release(current_value, free) && free {
                 if current.compare_exchange(
                     current_value as *const _ as *mut _, next_value, AcqRel, Relaxed
                 ).is_ok() {
@@ -490,36 +691,98 @@ impl<T: Sized> Arena<T> {
         // Now we are 100% sure that pages in to_drop are/will not be
         // referenced anymore
 
-        let mut current: &AtomicPtr<PageArena<T>> = &self.full_list;
-
-        // Loop on the full list
-        // We remove the pages from it
-        while let Some(current_value) = unsafe { current.load(Relaxed).as_mut() } {
-            let next = &current_value.next;
-            let next_value = next.load(Relaxed);
-
-            if to_drop.contains(&(current_value as *const _ as *mut PageArena<T>)) {
-                current.compare_exchange(
-                    current_value as *const _ as *mut _, next_value, AcqRel, Relaxed
-                ).expect("Something went wrong in shrinking.");
-            } else {
-                current = next;
-            }
-        }
-
         for page in &to_drop {
             let page_ref = unsafe { page.as_ref().unwrap() };
 
             assert!(page_ref.bitfield.get() | page_ref.bitfield_atomic.load(Acquire) == !0);
 
+            self.unlink_page(page_ref);
             drop_page(*page);
         }
 
         self.free_list.set(start.load(Relaxed));
+        // The page might have been dropped
+        self.shrink_cursor.set(std::ptr::null_mut());
+
+        self.remove_pages(to_drop.len());
+
+        ShrinkReport::pages::<PageArena<T>>(to_drop.len())
+    }
+
+    /// Drops the fully free pages of the free list for which `release`
+    /// returns `true`, examining at most `budget` pages
+    ///
+    /// It resumes after the page where the previous call stopped. At
+    /// the end of the free list, the pages of `pending_free_list` are
+    /// appended to it: they might be fully free.
+    fn shrink_budget(&self, budget: usize, mut release: impl FnMut(&PageArena<T>, bool) -> bool) -> ShrinkReport {
+        let start = &AtomicPtr::new(self.free_list.get());
+        let mut cursor = self.shrink_cursor.get();
+
+        let mut current: &AtomicPtr<PageArena<T>> = match unsafe { cursor.as_ref() } {
+            Some(page) => &page.next_free,
+            None => start,
+        };
+
+        let mut nfreed = 0;
+
+        for _ in 0..budget {
+            let page = match unsafe { current.load(Relaxed).as_ref() } {
+                Some(page) => page,
+                None => {
+                    let pending = self.pending_free_list.swap(std::ptr::null_mut(), AcqRel);
+                    if pending.is_null() {
+                        // The next call starts from the beginning
+                        cursor = std::ptr::null_mut();
+                        break;
+                    }
+                    current.store(pending, Relaxed);
+                    continue;
+                }
+            };
+
+            let next = page.next_free.load(Acquire);
+            let free = page.bitfield.get() | page.bitfield_atomic.load(Acquire) == !0;
+
+            if release(page, free) && free {
+                current.store(next, Relaxed);
+                self.unlink_page(page);
+                drop_page(page as *const _ as *mut PageArena<T>);
+                nfreed += 1;
+            } else {
+                cursor = page as *const _ as *mut _;
+                current = &page.next_free;
+            }
+        }
+
+        self.free_list.set(start.load(Relaxed));
+        self.shrink_cursor.set(cursor);
+
+        self.remove_pages(nfreed);
+
+        ShrinkReport::pages::<PageArena<T>>(nfreed)
+    }
+
+    /// Removes `page` from the list of all pages
+    fn unlink_page(&self, page: &PageArena<T>) {
+        let prev = page.prev.get();
+        let next = page.next.load(Relaxed);
 
-        self.npages.set(self.npages.get() - to_drop.len());
+        match unsafe { prev.as_ref() } {
+            Some(prev) => prev.next.store(next, Relaxed),
+            None => self.full_list.store(next, Relaxed),
+        }
 
-        true
+        if let Some(next) = unsafe { next.as_ref() } {
+            next.prev.set(prev);
+        }
+    }
+
+    fn remove_pages(&self, npages: usize) {
+        self.npages.set(self.npages.get() - npages);
+        if let Some(state) = &self.shrink {
+            state.remove_pages(npages);
+        }
     }
 
     /// Returns a tuple of non-free and free spaces in the arena
@@ -742,6 +1005,33 @@ mod tests {
         assert_eq!(arena.stats(), (0, 0));
     }
 
+    #[test]
+    fn arena_auto_shrink() {
+        use crate::AutoShrink;
+
+        let arena = Arena::<usize>::with_capacity_and_shrink(63, AutoShrink::new().free_ratio(50));
+        let values: Vec<_> = (0..63 * 40).map(|n| arena.alloc(n)).collect();
+        let npages = arena.npages.get();
+
+        // Dropped in another thread, the arena is shrunk at its next
+        // allocation
+        std::thread::spawn(move || std::mem::drop(values)).join().unwrap();
+        assert_eq!(arena.npages.get(), npages);
+
+        // A few pages by allocation
+        let value = arena.alloc(1);
+        let freed = npages - arena.npages.get();
+        assert!(freed > 0 && freed <= 16, "freed={}", freed);
+
+        for _ in 0..10 {
+            arena.alloc(2);
+        }
+
+        // A page of free blocks is kept
+        assert_eq!(arena.stats(), (1, 62));
+        assert_eq!(*value, 1);
+    }
+
     #[cfg(target_pointer_width = "64") ]
     #[test]
     fn arena_shrink2() {
@@ -999,6 +1289,7 @@ mod tests {
     }
 
     // #[test]
+    #[allow(clippy::len_zero, clippy::manual_is_multiple_of)]
     fn test_with_threads(nthreads: usize, nallocs: usize, with_shrink: bool) {
     // fn test_with_threads() {
         use std::sync::{Arc, Barrier};
@@ -1053,7 +1344,7 @@ mod tests {
 
             if with_shrink && rand % 200 == 0 {
                 // println!("SHRINKING", );
-                if arena.shrink_to_fit() {
+                if arena.shrink_to_fit().completed {
                     // nshrink += 1;
                 }
             }
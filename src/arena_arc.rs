@@ -1,9 +1,15 @@
 
 
-use std::sync::atomic::Ordering::*;
+use crate::sync::atomic::Ordering::*;
 use std::ptr::NonNull;
+use std::pin::Pin;
+use std::any::Any;
+use std::mem::MaybeUninit;
+use std::marker::PhantomData;
 
 use crate::block::Block;
+use crate::kind::{DynamicKind, Kind};
+use crate::{ArenaBox, SharedArena, SharedArenaHandle};
 
 /// A reference-counting pointer to `T` in the arena
 ///
@@ -51,14 +57,20 @@ use crate::block::Block;
 /// [`ArenaBox`]: ./struct.ArenaBox.html
 /// [`Clone`]: https://doc.rust-lang.org/std/clone/trait.Clone.html#tymethod.clone
 ///
-pub struct ArenaArc<T> {
+/// The parameter `K` is the [`Kind`] of the arena, see
+/// [`ArenaArc::try_into_kind`].
+///
+/// [`Kind`]: ./trait.Kind.html
+/// [`ArenaArc::try_into_kind`]: #method.try_into_kind
+pub struct ArenaArc<T: ?Sized, K: Kind<T> = DynamicKind> {
     block: NonNull<Block<T>>,
+    _kind: PhantomData<K>,
 }
 
-unsafe impl<T: Send> Send for ArenaArc<T> {}
-unsafe impl<T: Send + Sync> Sync for ArenaArc<T> {}
+unsafe impl<T: ?Sized + Send, K: Kind<T>> Send for ArenaArc<T, K> {}
+unsafe impl<T: ?Sized + Send + Sync, K: Kind<T>> Sync for ArenaArc<T, K> {}
 
-impl<T: std::fmt::Display> std::fmt::Display for ArenaArc<T> {
+impl<T: ?Sized + std::fmt::Display, K: Kind<T>> std::fmt::Display for ArenaArc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaArc, SharedArena};
     /// let arena = SharedArena::new();
@@ -71,7 +83,7 @@ impl<T: std::fmt::Display> std::fmt::Display for ArenaArc<T> {
     }
 }
 
-impl<T: std::fmt::Debug> std::fmt::Debug for ArenaArc<T> {
+impl<T: ?Sized + std::fmt::Debug, K: Kind<T>> std::fmt::Debug for ArenaArc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaArc, SharedArena};
     /// let arena = SharedArena::new();
@@ -84,7 +96,7 @@ impl<T: std::fmt::Debug> std::fmt::Debug for ArenaArc<T> {
     }
 }
 
-impl<T> std::fmt::Pointer for ArenaArc<T> {
+impl<T: ?Sized, K: Kind<T>> std::fmt::Pointer for ArenaArc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaArc, SharedArena};
     /// let arena = SharedArena::new();
@@ -98,7 +110,7 @@ impl<T> std::fmt::Pointer for ArenaArc<T> {
     }
 }
 
-impl<T> ArenaArc<T> {
+impl<T: ?Sized> ArenaArc<T> {
     pub(crate) fn new(block: NonNull<Block<T>>) -> ArenaArc<T> {
         let counter_ref = &unsafe { block.as_ref() }.counter;
 
@@ -113,11 +125,498 @@ impl<T> ArenaArc<T> {
 
         counter_ref.store(1, Relaxed);
 
-        ArenaArc { block }
+        ArenaArc { block, _kind: PhantomData }
+    }
+
+    /// Makes an ArenaArc from a block that already holds a reference
+    /// for it.
+    /// The reference counter is not incremented.
+    pub(crate) unsafe fn from_block(block: NonNull<Block<T>>) -> ArenaArc<T> {
+        ArenaArc { block, _kind: PhantomData }
+    }
+
+    /// Consumes the ArenaArc and returns its block.
+    /// The reference counter is not decremented.
+    pub(crate) fn into_block(this: ArenaArc<T>) -> NonNull<Block<T>> {
+        let block = this.block;
+        std::mem::forget(this);
+        block
+    }
+
+    /// Returns `true` if the two `ArenaArc` point to the same value
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let my_num = arena.alloc_arc(10);
+    ///
+    /// assert!(ArenaArc::ptr_eq(&my_num, &my_num.clone()));
+    /// assert!(!ArenaArc::ptr_eq(&my_num, &arena.alloc_arc(10)));
+    /// ```
+    pub fn ptr_eq(this: &ArenaArc<T>, other: &ArenaArc<T>) -> bool {
+        std::ptr::addr_eq(this.block.as_ptr(), other.block.as_ptr())
+    }
+
+    /// Consumes the `ArenaArc`, returning a raw pointer to the value
+    ///
+    /// The reference counter is not decremented: to avoid a leak,
+    /// the pointer has to be converted back to an `ArenaArc` with
+    /// [`ArenaArc::from_raw`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let ptr = ArenaArc::into_raw(arena.alloc_arc(10));
+    ///
+    /// assert_eq!(unsafe { *ptr }, 10);
+    ///
+    /// let my_num = unsafe { ArenaArc::from_raw(ptr) };
+    /// assert_eq!(*my_num, 10);
+    /// ```
+    ///
+    /// [`ArenaArc::from_raw`]: #method.from_raw
+    pub fn into_raw(this: ArenaArc<T>) -> *const T {
+        Block::value_ptr(Self::into_block(this))
+    }
+
+    /// Constructs an `ArenaArc` from a raw pointer
+    ///
+    /// The reference counter is not incremented: the `ArenaArc`
+    /// takes the reference owned by the pointer.
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`ArenaArc::into_raw`].  
+    /// Each call to `from_raw` must match a call to `into_raw`, or
+    /// to [`ArenaArc::increment_strong_count`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let ptr = ArenaArc::into_raw(arena.alloc_arc(10));
+    ///
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// std::mem::drop(unsafe { ArenaArc::from_raw(ptr) });
+    /// assert_eq!(arena.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`ArenaArc::into_raw`]: #method.into_raw
+    /// [`ArenaArc::increment_strong_count`]: #method.increment_strong_count
+    pub unsafe fn from_raw(ptr: *const T) -> ArenaArc<T> {
+        ArenaArc { block: Block::from_value_ptr(ptr), _kind: PhantomData }
+    }
+
+    /// Increments the reference counter of the value pointed by `ptr`
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`ArenaArc::into_raw`] and
+    /// the value must still be referenced.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let ptr = ArenaArc::into_raw(arena.alloc_arc(10));
+    ///
+    /// unsafe {
+    ///     ArenaArc::increment_strong_count(ptr);
+    ///
+    ///     let first = ArenaArc::from_raw(ptr);
+    ///     let second = ArenaArc::from_raw(ptr);
+    ///     assert_eq!(*first, *second);
+    /// }
+    /// ```
+    ///
+    /// [`ArenaArc::into_raw`]: #method.into_raw
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        let block = Block::from_value_ptr(ptr);
+        let old = block.as_ref().counter.fetch_add(1, Relaxed);
+
+        assert!(old < isize::MAX as usize);
+    }
+
+    /// Decrements the reference counter of the value pointed by `ptr`
+    ///
+    /// If it was the last reference, the value is dropped.
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`ArenaArc::into_raw`] and
+    /// must own a reference to the value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let ptr = ArenaArc::into_raw(arena.alloc_arc(10));
+    ///
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// unsafe { ArenaArc::decrement_strong_count(ptr) };
+    /// assert_eq!(arena.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`ArenaArc::into_raw`]: #method.into_raw
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(ArenaArc::from_raw(ptr));
+    }
+
+    /// Returns the inner value, if the `ArenaArc` has exactly one
+    /// reference
+    ///
+    /// Otherwise, an `Err` is returned with the same `ArenaArc`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    ///
+    /// let my_num = arena.alloc_arc(10);
+    /// assert_eq!(ArenaArc::try_unwrap(my_num).ok(), Some(10));
+    ///
+    /// let my_num = arena.alloc_arc(10);
+    /// let _clone = my_num.clone();
+    /// assert_eq!(*ArenaArc::try_unwrap(my_num).unwrap_err(), 10);
+    /// ```
+    pub fn try_unwrap(this: ArenaArc<T>) -> Result<T, ArenaArc<T>>
+    where
+        T: Sized
+    {
+        let counter_ref = &unsafe { this.block.as_ref() }.counter;
+
+        if counter_ref.compare_exchange(1, 0, Acquire, Relaxed).is_err() {
+            return Err(this);
+        }
+
+        Ok(Block::take_value(Self::into_block(this)))
+    }
+
+    /// Returns the inner value, if the `ArenaArc` is the last reference
+    ///
+    /// Otherwise, the reference counter is decremented and `None` is
+    /// returned.
+    /// When `into_inner` is called on all the references of a value,
+    /// exactly one of them returns the value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    ///
+    /// let my_num = arena.alloc_arc(10);
+    /// let clone = my_num.clone();
+    ///
+    /// assert_eq!(ArenaArc::into_inner(my_num), None);
+    /// assert_eq!(ArenaArc::into_inner(clone), Some(10));
+    /// assert_eq!(arena.stats(), (0, 63));
+    /// ```
+    pub fn into_inner(this: ArenaArc<T>) -> Option<T>
+    where
+        T: Sized
+    {
+        let block = Self::into_block(this);
+        let counter_ref = &unsafe { block.as_ref() }.counter;
+
+        // We decrement the reference counter
+        if counter_ref.fetch_sub(1, AcqRel) != 1 {
+            return None;
+        }
+
+        // We were the last reference
+        Some(Block::take_value(block))
+    }
+
+    /// Converts the `ArenaArc` to an [`ArenaBox`], if it has exactly
+    /// one reference
+    ///
+    /// Otherwise, an `Err` is returned with the same `ArenaArc`.
+    /// The value is not reallocated.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    ///
+    /// let my_num = arena.alloc_arc(10);
+    /// let clone = my_num.clone();
+    ///
+    /// let my_num = ArenaArc::try_into_box(my_num).unwrap_err();
+    /// std::mem::drop(clone);
+    ///
+    /// let mut my_num: ArenaBox<i32> = ArenaArc::try_into_box(my_num).unwrap();
+    /// *my_num += 1;
+    /// assert_eq!(*my_num, 11);
+    /// ```
+    ///
+    /// [`ArenaBox`]: ./struct.ArenaBox.html
+    pub fn try_into_box(this: ArenaArc<T>) -> Result<ArenaBox<T>, ArenaArc<T>> {
+        let counter_ref = &unsafe { this.block.as_ref() }.counter;
+
+        // We hold the only reference: no other thread can increment it
+        if counter_ref.load(Acquire) != 1 {
+            return Err(this);
+        }
+
+        // The counter is 1 for both ArenaArc and ArenaBox
+        Ok(unsafe { ArenaBox::from_raw(ArenaArc::into_raw(this) as *mut T) })
+    }
+
+    /// Converts the `ArenaArc` to a `Pin<ArenaArc<T>>`
+    ///
+    /// The value is never moved by the arena, so it's pinned until
+    /// the last `ArenaArc` is dropped.
+    /// To allocate a pinned value, see [`ArenaArc::pin`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// # use std::pin::Pin;
+    /// let arena = SharedArena::new();
+    /// let my_num: Pin<ArenaArc<i32>> = ArenaArc::into_pin(arena.alloc_arc(10));
+    ///
+    /// assert_eq!(*my_num.clone(), 10);
+    /// ```
+    ///
+    /// [`ArenaArc::pin`]: #method.pin
+    pub fn into_pin(this: ArenaArc<T>) -> Pin<ArenaArc<T>> {
+        // The block is not moved or reused while an ArenaArc exists,
+        // and the value is dropped in place
+        unsafe { Pin::new_unchecked(this) }
+    }
+
+    /// Writes a value in `arena`, and returns a pinned `ArenaArc`
+    /// pointing to that value, like `Arc::pin`
+    ///
+    /// Same as [`SharedArena::alloc_arc_pin`]. An `ArenaArc` which
+    /// already exists is pinned with [`ArenaArc::into_pin`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// # use std::pin::Pin;
+    /// let arena = SharedArena::new();
+    /// let my_num: Pin<ArenaArc<i32>> = ArenaArc::pin(&arena, 10);
+    ///
+    /// assert_eq!(*my_num.clone(), 10);
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`SharedArena::alloc_arc_pin`]: ./struct.SharedArena.html#method.alloc_arc_pin
+    /// [`ArenaArc::into_pin`]: #method.into_pin
+    pub fn pin(arena: &SharedArena<T>, value: T) -> Pin<ArenaArc<T>>
+    where
+        T: Sized
+    {
+        arena.alloc_arc_pin(value)
+    }
+
+    /// Returns the [`SharedArena`] that allocated the value
+    ///
+    /// It returns `None` if the arena has been dropped, or if the
+    /// value was allocated in an [`Arena`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let my_num = arena.alloc_arc(1);
+    ///
+    /// let sibling = ArenaArc::arena(&my_num).unwrap().alloc_arc(2);
+    /// assert_eq!(arena.stats(), (2, 61));
+    ///
+    /// std::mem::drop(arena);
+    /// assert!(ArenaArc::arena(&my_num).is_none());
+    /// ```
+    ///
+    /// [`SharedArena`]: ./struct.SharedArena.html
+    /// [`Arena`]: ./struct.Arena.html
+    pub fn arena(this: &ArenaArc<T>) -> Option<SharedArenaHandle<T>>
+    where
+        T: Sized
+    {
+        SharedArena::handle_of(this.block)
+    }
+
+    /// Returns a mutable reference to the value, if there is no other
+    /// `ArenaArc` pointing to it
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let mut my_num = arena.alloc_arc(10);
+    ///
+    /// *ArenaArc::get_mut(&mut my_num).unwrap() = 11;
+    /// assert_eq!(*my_num, 11);
+    ///
+    /// let _clone = my_num.clone();
+    /// assert!(ArenaArc::get_mut(&mut my_num).is_none());
+    /// ```
+    pub fn get_mut(this: &mut ArenaArc<T>) -> Option<&mut T> {
+        let block = unsafe { this.block.as_ref() };
+
+        // We hold a reference and we are borrowed mutably: no other
+        // thread can increment the counter if it's 1
+        if block.counter.load(Acquire) == 1 {
+            Some(unsafe { &mut *block.value.get() })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the value, cloning it if there
+    /// are other `ArenaArc` pointing to it
+    ///
+    /// The clone is allocated in the [`SharedArena`] of the value.
+    /// It returns `None` when the value has to be cloned but that
+    /// arena has been dropped, or the value was allocated in an
+    /// [`Arena`]: the clone is never allocated in another arena.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let mut my_num = arena.alloc_arc(10);
+    /// let other = my_num.clone();
+    ///
+    /// *ArenaArc::make_mut(&mut my_num).unwrap() += 1;
+    ///
+    /// assert_eq!(*my_num, 11);
+    /// assert_eq!(*other, 10);
+    /// assert_eq!(arena.stats(), (2, 61));
+    /// ```
+    ///
+    /// [`SharedArena`]: ./struct.SharedArena.html
+    /// [`Arena`]: ./struct.Arena.html
+    pub fn make_mut(this: &mut ArenaArc<T>) -> Option<&mut T>
+    where
+        T: Clone
+    {
+        let counter = unsafe { this.block.as_ref() }.counter.load(Acquire);
+
+        if counter != 1 {
+            let arena = SharedArena::handle_of(this.block)?;
+            *this = arena.alloc_arc(T::clone(this));
+        }
+
+        Some(unsafe { &mut *this.block.as_ref().value.get() })
+    }
+}
+
+impl<T: ?Sized, K: Kind<T>> ArenaArc<T, K> {
+    /// Changes the kind of the handle, the block is not checked and
+    /// the reference counter is not modified
+    pub(crate) fn cast_kind<K2: Kind<T>>(this: ArenaArc<T, K>) -> ArenaArc<T, K2> {
+        let block = this.block;
+        std::mem::forget(this);
+        ArenaArc { block, _kind: PhantomData }
+    }
+
+    /// Converts the `ArenaArc` to a handle of kind `K2`, without
+    /// reallocating
+    ///
+    /// A handle of a static kind, such as [`SharedArenaKind`],
+    /// releases its block without reading the kind of its page and
+    /// without branching on it.  
+    /// If the value was not allocated by an arena of kind `K2`, the
+    /// same `ArenaArc` is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, Arena, ArenaKind, SharedArena, SharedArenaKind};
+    /// let arena = SharedArena::new();
+    ///
+    /// let my_num: ArenaArc<i32, SharedArenaKind> = ArenaArc::try_into_kind(arena.alloc_arc(1)).unwrap();
+    /// let clone = my_num.clone();
+    /// assert_eq!(arena.stats(), (1, 62));
+    ///
+    /// std::mem::drop((my_num, clone));
+    /// assert_eq!(arena.stats(), (0, 63));
+    ///
+    /// assert!(ArenaArc::try_into_kind::<ArenaKind>(arena.alloc_arc(2)).is_err());
+    /// ```
+    ///
+    /// [`SharedArenaKind`]: ./enum.SharedArenaKind.html
+    pub fn try_into_kind<K2: Kind<T>>(this: ArenaArc<T, K>) -> Result<ArenaArc<T, K2>, ArenaArc<T, K>> {
+        let kind = unsafe { this.block.as_ref() }.page.page_kind();
+
+        if crate::kind::accepts::<T, K2>(kind) {
+            Ok(ArenaArc::cast_kind(this))
+        } else {
+            Err(this)
+        }
+    }
+
+    /// Converts the `ArenaArc` to a handle of [`DynamicKind`], the
+    /// default kind
+    ///
+    /// [`DynamicKind`]: ./enum.DynamicKind.html
+    pub fn into_dynamic(this: ArenaArc<T, K>) -> ArenaArc<T> {
+        ArenaArc::cast_kind(this)
+    }
+}
+
+impl<T> ArenaArc<MaybeUninit<T>> {
+    /// Converts to `ArenaArc<T>`, without moving the value
+    ///
+    /// ## Safety
+    ///
+    /// The value must be fully initialized, as for
+    /// `MaybeUninit::assume_init`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let mut my_num = arena.alloc_arc_uninit();
+    ///
+    /// ArenaArc::get_mut(&mut my_num).unwrap().write(10);
+    ///
+    /// let my_num: ArenaArc<i32> = unsafe { my_num.assume_init() };
+    /// assert_eq!(*my_num, 10);
+    /// ```
+    pub unsafe fn assume_init(self) -> ArenaArc<T> {
+        // MaybeUninit<T> has the same layout as T
+        ArenaArc::from_raw(ArenaArc::into_raw(self) as *const T)
+    }
+}
+
+impl ArenaArc<dyn Any + Send + Sync> {
+    /// Attempts to downcast the value to a concrete type
+    ///
+    /// The value is not moved. On failure, the same `ArenaArc` is
+    /// returned.
+    ///
+    /// See [`coerce!`](./macro.coerce.html) to make a `ArenaArc<dyn Any + Send + Sync>`.
+    pub fn downcast<T: Any>(self) -> Result<ArenaArc<T>, ArenaArc<dyn Any + Send + Sync>> {
+        if self.is::<T>() {
+            let ptr = ArenaArc::into_raw(self) as *const T;
+            Ok(unsafe { ArenaArc::from_raw(ptr) })
+        } else {
+            Err(self)
+        }
     }
 }
 
-impl<T> Clone for ArenaArc<T> {
+impl<T: ?Sized, K: Kind<T>> Clone for ArenaArc<T, K> {
     /// Make a clone of the ArenaArc pointer.
     ///
     /// This increase the reference counter.
@@ -129,20 +628,21 @@ impl<T> Clone for ArenaArc<T> {
     /// assert_eq!(*my_num, *my_num.clone());
     /// ```
     #[inline]
-    fn clone(&self) -> ArenaArc<T> {
+    fn clone(&self) -> ArenaArc<T, K> {
         let counter_ref = &unsafe { self.block.as_ref() }.counter;
 
         let old = counter_ref.fetch_add(1, Relaxed);
 
-        assert!(old < isize::max_value() as usize);
+        assert!(old < isize::MAX as usize);
 
         ArenaArc {
-            block: self.block
+            block: self.block,
+            _kind: PhantomData,
         }
     }
 }
 
-impl<T> std::ops::Deref for ArenaArc<T> {
+impl<T: ?Sized, K: Kind<T>> std::ops::Deref for ArenaArc<T, K> {
     type Target = T;
 
     /// ```
@@ -161,7 +661,7 @@ impl<T> std::ops::Deref for ArenaArc<T> {
 ///
 /// If it is the last reference to that value, the value is
 /// also dropped
-impl<T> Drop for ArenaArc<T> {
+impl<T: ?Sized, K: Kind<T>> Drop for ArenaArc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaBox, Arena};
     /// let arena = Arena::new();
@@ -179,7 +679,7 @@ impl<T> Drop for ArenaArc<T> {
 
         // We were the last reference
         if count == 1 {
-            Block::drop_block(self.block)
+            Block::drop_block_as::<K>(self.block)
         };
     }
 }
@@ -1,16 +1,24 @@
 
 
-use std::sync::atomic::Ordering::*;
+use crate::sync::atomic::Ordering::*;
 use std::ptr::NonNull;
+use std::pin::Pin;
+use std::any::Any;
+use std::mem::MaybeUninit;
+use std::marker::PhantomData;
 
 use crate::block::Block;
+use crate::kind::{DynamicKind, Kind};
+use crate::{ArenaArc, ArenaRc, SharedArena, SharedArenaHandle};
 
 /// A pointer to `T` in the arena
 ///
 /// `ArenaBox` implements [`DerefMut`] so it is directly mutable
 /// (without mutex or other synchronization methods).
 ///
-/// It is not clonable and can be sent to others threads.
+/// It can be sent to others threads.  
+/// `ArenaBox` doesn't implement `Clone`: the value can only be cloned
+/// in the arena that allocated it, see [`ArenaBox::try_clone`].
 ///
 /// ## `Deref` & `DerefMut` behavior
 ///
@@ -31,15 +39,22 @@ use crate::block::Block;
 /// [`Arena`]: ./struct.Arena.html
 /// [`SharedArena`]: ./struct.SharedArena.html
 /// [`DerefMut`]: https://doc.rust-lang.org/std/ops/trait.DerefMut.html
+/// [`ArenaBox::try_clone`]: #method.try_clone
 ///
-pub struct ArenaBox<T> {
+/// The parameter `K` is the [`Kind`] of the arena, see
+/// [`ArenaBox::try_into_kind`].
+///
+/// [`Kind`]: ./trait.Kind.html
+/// [`ArenaBox::try_into_kind`]: #method.try_into_kind
+pub struct ArenaBox<T: ?Sized, K: Kind<T> = DynamicKind> {
     block: NonNull<Block<T>>,
+    _kind: PhantomData<K>,
 }
 
-unsafe impl<T: Send> Send for ArenaBox<T> {}
-unsafe impl<T: Send + Sync> Sync for ArenaBox<T> {}
+unsafe impl<T: ?Sized + Send, K: Kind<T>> Send for ArenaBox<T, K> {}
+unsafe impl<T: ?Sized + Send + Sync, K: Kind<T>> Sync for ArenaBox<T, K> {}
 
-impl<T: std::fmt::Display> std::fmt::Display for ArenaBox<T> {
+impl<T: ?Sized + std::fmt::Display, K: Kind<T>> std::fmt::Display for ArenaBox<T, K> {
     /// ```
     /// # use shared_arena::{ArenaBox, SharedArena};
     /// let arena = SharedArena::new();
@@ -52,7 +67,7 @@ impl<T: std::fmt::Display> std::fmt::Display for ArenaBox<T> {
     }
 }
 
-impl<T: std::fmt::Debug> std::fmt::Debug for ArenaBox<T> {
+impl<T: ?Sized + std::fmt::Debug, K: Kind<T>> std::fmt::Debug for ArenaBox<T, K> {
     /// ```
     /// # use shared_arena::{ArenaBox, SharedArena};
     /// let arena = SharedArena::new();
@@ -65,7 +80,7 @@ impl<T: std::fmt::Debug> std::fmt::Debug for ArenaBox<T> {
     }
 }
 
-impl<T> std::fmt::Pointer for ArenaBox<T> {
+impl<T: ?Sized, K: Kind<T>> std::fmt::Pointer for ArenaBox<T, K> {
     /// ```
     /// # use shared_arena::{ArenaBox, SharedArena};
     /// let arena = SharedArena::new();
@@ -79,7 +94,7 @@ impl<T> std::fmt::Pointer for ArenaBox<T> {
     }
 }
 
-impl<T> ArenaBox<T> {
+impl<T: ?Sized> ArenaBox<T> {
     pub(crate) fn new(block: NonNull<Block<T>>) -> ArenaBox<T> {
         let counter_ref = &unsafe { block.as_ref() }.counter;
 
@@ -95,11 +110,353 @@ impl<T> ArenaBox<T> {
 
         counter_ref.store(1, Relaxed);
 
-        ArenaBox { block }
+        ArenaBox { block, _kind: PhantomData }
+    }
+
+    /// Consumes the `ArenaBox`, returning a raw pointer to the value
+    ///
+    /// The value is not dropped and the memory is still owned by
+    /// its page: to release it, the pointer has to be converted back
+    /// to an `ArenaBox` with [`ArenaBox::from_raw`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let ptr = ArenaBox::into_raw(arena.alloc(10));
+    ///
+    /// assert_eq!(unsafe { *ptr }, 10);
+    ///
+    /// let my_num = unsafe { ArenaBox::from_raw(ptr) };
+    /// assert_eq!(*my_num, 10);
+    /// ```
+    ///
+    /// [`ArenaBox::from_raw`]: #method.from_raw
+    pub fn into_raw(this: ArenaBox<T>) -> *mut T {
+        let ptr = Block::value_ptr(this.block);
+        std::mem::forget(this);
+        ptr
+    }
+
+    /// Constructs an `ArenaBox` from a raw pointer
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`ArenaBox::into_raw`] and
+    /// must be converted back only once.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, Arena};
+    /// let arena = Arena::new();
+    /// let ptr = ArenaBox::into_raw(arena.alloc(10));
+    ///
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// std::mem::drop(unsafe { ArenaBox::from_raw(ptr) });
+    /// assert_eq!(arena.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`ArenaBox::into_raw`]: #method.into_raw
+    pub unsafe fn from_raw(ptr: *mut T) -> ArenaBox<T> {
+        ArenaBox { block: Block::from_value_ptr(ptr), _kind: PhantomData }
+    }
+
+    /// Converts the `ArenaBox` to an [`ArenaArc`], without reallocating
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let my_num: ArenaArc<i32> = ArenaBox::into_arc(arena.alloc(10));
+    ///
+    /// assert_eq!(*my_num, *my_num.clone());
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`ArenaArc`]: ./struct.ArenaArc.html
+    pub fn into_arc(this: ArenaBox<T>) -> ArenaArc<T> {
+        // The counter is 1 for both ArenaBox and ArenaArc
+        unsafe { ArenaArc::from_raw(ArenaBox::into_raw(this)) }
+    }
+
+    /// Converts the `ArenaBox` to an [`ArenaRc`], without reallocating
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, ArenaBox, Arena};
+    /// let arena = Arena::new();
+    /// let my_num: ArenaRc<i32> = ArenaBox::into_rc(arena.alloc(10));
+    ///
+    /// assert_eq!(*my_num, *my_num.clone());
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`ArenaRc`]: ./struct.ArenaRc.html
+    pub fn into_rc(this: ArenaBox<T>) -> ArenaRc<T> {
+        unsafe { ArenaRc::from_raw(ArenaBox::into_raw(this)) }
+    }
+
+    /// Converts the `ArenaBox` to a `Pin<ArenaBox<T>>`
+    ///
+    /// The value is never moved by the arena, so it's pinned until
+    /// the `ArenaBox` is dropped.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// # use std::pin::Pin;
+    /// let arena = SharedArena::new();
+    /// let my_num: Pin<ArenaBox<i32>> = ArenaBox::into_pin(arena.alloc(10));
+    ///
+    /// assert_eq!(*my_num, 10);
+    /// ```
+    pub fn into_pin(this: ArenaBox<T>) -> Pin<ArenaBox<T>> {
+        // The block is not moved or reused while the ArenaBox exists,
+        // and the value is dropped in place
+        unsafe { Pin::new_unchecked(this) }
+    }
+
+    /// Returns the [`SharedArena`] that allocated the value
+    ///
+    /// It returns `None` if the arena has been dropped, or if the
+    /// value was allocated in an [`Arena`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let my_num = arena.alloc(1);
+    ///
+    /// let sibling = ArenaBox::arena(&my_num).unwrap().alloc(2);
+    /// assert_eq!(arena.stats(), (2, 61));
+    ///
+    /// std::mem::drop(arena);
+    /// assert!(ArenaBox::arena(&my_num).is_none());
+    /// ```
+    ///
+    /// [`SharedArena`]: ./struct.SharedArena.html
+    /// [`Arena`]: ./struct.Arena.html
+    pub fn arena(this: &ArenaBox<T>) -> Option<SharedArenaHandle<T>>
+    where
+        T: Sized
+    {
+        SharedArena::handle_of(this.block)
+    }
+
+    /// Clones the value in the [`SharedArena`] that allocated it
+    ///
+    /// It returns `None` if the arena has been dropped, or if the
+    /// value was allocated in an [`Arena`]: the clone is never
+    /// allocated in another arena.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let my_num = arena.alloc(10);
+    ///
+    /// let other = ArenaBox::try_clone(&my_num).unwrap();
+    /// assert_eq!(*my_num, *other);
+    /// assert_eq!(arena.stats(), (2, 61));
+    ///
+    /// std::mem::drop(arena);
+    /// assert!(ArenaBox::try_clone(&my_num).is_none());
+    /// ```
+    ///
+    /// [`SharedArena`]: ./struct.SharedArena.html
+    /// [`Arena`]: ./struct.Arena.html
+    pub fn try_clone(this: &ArenaBox<T>) -> Option<ArenaBox<T>>
+    where
+        T: Clone
+    {
+        let arena = SharedArena::handle_of(this.block)?;
+        Some(arena.alloc(T::clone(this)))
+    }
+
+    /// Moves the value out of the `ArenaBox`
+    ///
+    /// The space of the value in its arena is released.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let my_str = arena.alloc(String::from("a"));
+    ///
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// assert_eq!(ArenaBox::into_inner(my_str), "a");
+    /// assert_eq!(arena.stats(), (0, 63));
+    /// ```
+    pub fn into_inner(this: ArenaBox<T>) -> T
+    where
+        T: Sized
+    {
+        let block = this.block;
+        std::mem::forget(this);
+
+        let counter_ref = &unsafe { block.as_ref() }.counter;
+
+        // See ArenaBox<T>::new for why we touch the counter
+        let counter = counter_ref.load(Relaxed);
+        assert!(counter == 1, "ArenaBox: Counter != 1 on into_inner {}", counter);
+
+        counter_ref.store(0, Relaxed);
+
+        Block::take_value(block)
+    }
+
+    /// Consumes the `ArenaBox`, returning a mutable reference to the
+    /// value with the lifetime `'a`
+    ///
+    /// Like `Box::leak`, the value is never dropped: its block stays
+    /// marked as used, so its page is not freed, neither by
+    /// `shrink_to_fit` nor when the arena is dropped.
+    /// The reference can be `'static`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let config: &'static mut String = ArenaBox::leak(arena.alloc(String::from("a")));
+    ///
+    /// std::mem::drop(arena);
+    /// config.push('b');
+    /// assert_eq!(config, "ab");
+    /// ```
+    pub fn leak<'a>(this: ArenaBox<T>) -> &'a mut T
+    where
+        T: 'a
+    {
+        // The counter stays at 1 and the block is never released
+        unsafe { &mut *ArenaBox::into_raw(this) }
+    }
+}
+
+impl<T: ?Sized, K: Kind<T>> ArenaBox<T, K> {
+    /// Changes the kind of the handle, the block is not checked and
+    /// the counter is not modified
+    pub(crate) fn cast_kind<K2: Kind<T>>(this: ArenaBox<T, K>) -> ArenaBox<T, K2> {
+        let block = this.block;
+        std::mem::forget(this);
+        ArenaBox { block, _kind: PhantomData }
+    }
+
+    /// Converts the `ArenaBox` to a handle of kind `K2`, without
+    /// reallocating
+    ///
+    /// A handle of a static kind, such as [`ArenaKind`], releases its
+    /// block without reading the kind of its page and without
+    /// branching on it.  
+    /// If the value was not allocated by an arena of kind `K2`, the
+    /// same `ArenaBox` is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, Pool, PoolKind, SharedArena, SharedArenaKind};
+    /// let arena = SharedArena::new();
+    ///
+    /// let my_num: ArenaBox<i32, SharedArenaKind> = ArenaBox::try_into_kind(arena.alloc(1)).unwrap();
+    /// assert_eq!(arena.stats(), (1, 62));
+    ///
+    /// std::mem::drop(my_num);
+    /// assert_eq!(arena.stats(), (0, 63));
+    ///
+    /// assert!(ArenaBox::try_into_kind::<PoolKind>(arena.alloc(2)).is_err());
+    /// ```
+    ///
+    /// [`ArenaKind`]: ./enum.ArenaKind.html
+    pub fn try_into_kind<K2: Kind<T>>(this: ArenaBox<T, K>) -> Result<ArenaBox<T, K2>, ArenaBox<T, K>> {
+        let kind = unsafe { this.block.as_ref() }.page.page_kind();
+
+        if crate::kind::accepts::<T, K2>(kind) {
+            Ok(ArenaBox::cast_kind(this))
+        } else {
+            Err(this)
+        }
+    }
+
+    /// Converts the `ArenaBox` to a handle of [`DynamicKind`], the
+    /// default kind
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, Arena, ArenaKind};
+    /// let arena = Arena::new();
+    /// let my_num: ArenaBox<i32, ArenaKind> = ArenaBox::try_into_kind(arena.alloc(1)).unwrap();
+    ///
+    /// let my_num: ArenaBox<i32> = ArenaBox::into_dynamic(my_num);
+    /// assert_eq!(ArenaBox::into_inner(my_num), 1);
+    /// ```
+    ///
+    /// [`DynamicKind`]: ./enum.DynamicKind.html
+    pub fn into_dynamic(this: ArenaBox<T, K>) -> ArenaBox<T> {
+        ArenaBox::cast_kind(this)
     }
 }
 
-impl<T> std::ops::Deref for ArenaBox<T> {
+impl<T> ArenaBox<MaybeUninit<T>> {
+    /// Converts to `ArenaBox<T>`, without moving the value
+    ///
+    /// ## Safety
+    ///
+    /// The value must be fully initialized, as for
+    /// `MaybeUninit::assume_init`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let mut my_num = arena.alloc_uninit();
+    ///
+    /// my_num.write(10);
+    ///
+    /// let my_num: ArenaBox<i32> = unsafe { my_num.assume_init() };
+    /// assert_eq!(*my_num, 10);
+    /// ```
+    pub unsafe fn assume_init(self) -> ArenaBox<T> {
+        // MaybeUninit<T> has the same layout as T
+        ArenaBox::from_raw(ArenaBox::into_raw(self) as *mut T)
+    }
+}
+
+macro_rules! impl_downcast {
+    ($($any:tt)*) => {
+        impl ArenaBox<$($any)*> {
+            /// Attempts to downcast the value to a concrete type
+            ///
+            /// The value is not moved. On failure, the same `ArenaBox`
+            /// is returned.
+            ///
+            /// See [`coerce!`](./macro.coerce.html) to make a `ArenaBox<dyn Any>`.
+            pub fn downcast<T: Any>(self) -> Result<ArenaBox<T>, ArenaBox<$($any)*>> {
+                if self.is::<T>() {
+                    let ptr = ArenaBox::into_raw(self) as *mut T;
+                    Ok(unsafe { ArenaBox::from_raw(ptr) })
+                } else {
+                    Err(self)
+                }
+            }
+        }
+    };
+}
+
+impl_downcast!(dyn Any);
+impl_downcast!(dyn Any + Send);
+impl_downcast!(dyn Any + Send + Sync);
+
+impl<T: ?Sized, K: Kind<T>> std::ops::Deref for ArenaBox<T, K> {
     type Target = T;
 
     /// ```
@@ -114,7 +471,7 @@ impl<T> std::ops::Deref for ArenaBox<T> {
     }
 }
 
-impl<T> std::ops::DerefMut for ArenaBox<T> {
+impl<T: ?Sized, K: Kind<T>> std::ops::DerefMut for ArenaBox<T, K> {
     /// ```
     /// # use shared_arena::{ArenaBox, SharedArena};
     /// let arena = SharedArena::new();
@@ -130,7 +487,7 @@ impl<T> std::ops::DerefMut for ArenaBox<T> {
 /// Drop the ArenaBox<T>
 ///
 /// The value pointed by this ArenaBox is also dropped
-impl<T> Drop for ArenaBox<T> {
+impl<T: ?Sized, K: Kind<T>> Drop for ArenaBox<T, K> {
     /// ```
     /// # use shared_arena::{ArenaBox, SharedArena};
     /// let arena = SharedArena::new();
@@ -148,10 +505,10 @@ impl<T> Drop for ArenaBox<T> {
         let counter_ref = &block.counter;
 
         let counter = counter_ref.load(Relaxed);
-        assert!(counter == 1, "PoolBox: Counter != 1 on drop {}", counter);
+        assert!(counter == 1, "ArenaBox: Counter != 1 on drop {}", counter);
 
         counter_ref.store(0, Relaxed);
 
-        Block::drop_block(self.block)
+        Block::drop_block_as::<K>(self.block)
     }
 }
@@ -1,7 +1,11 @@
 
 use std::ptr::NonNull;
+use std::any::Any;
+use std::marker::PhantomData;
 
 use crate::block::Block;
+use crate::kind::{DynamicKind, Kind};
+use crate::sync::WithMut;
 
 /// A single threaded reference-counting pointer to `T` in the arena.  
 ///
@@ -46,11 +50,17 @@ use crate::block::Block;
 /// [`ArenaBox`]: ./struct.ArenaBox.html
 /// [`Clone`]: https://doc.rust-lang.org/std/clone/trait.Clone.html#tymethod.clone
 ///
-pub struct ArenaRc<T> {
+/// The parameter `K` is the [`Kind`] of the arena, see
+/// [`ArenaRc::try_into_kind`].
+///
+/// [`Kind`]: ./trait.Kind.html
+/// [`ArenaRc::try_into_kind`]: #method.try_into_kind
+pub struct ArenaRc<T: ?Sized, K: Kind<T> = DynamicKind> {
     block: NonNull<Block<T>>,
+    _kind: PhantomData<K>,
 }
 
-impl<T: std::fmt::Display> std::fmt::Display for ArenaRc<T> {
+impl<T: ?Sized + std::fmt::Display, K: Kind<T>> std::fmt::Display for ArenaRc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaRc, SharedArena};
     /// let arena = SharedArena::new();
@@ -63,7 +73,7 @@ impl<T: std::fmt::Display> std::fmt::Display for ArenaRc<T> {
     }
 }
 
-impl<T: std::fmt::Debug> std::fmt::Debug for ArenaRc<T> {
+impl<T: ?Sized + std::fmt::Debug, K: Kind<T>> std::fmt::Debug for ArenaRc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaRc, SharedArena};
     /// let arena = SharedArena::new();
@@ -76,7 +86,7 @@ impl<T: std::fmt::Debug> std::fmt::Debug for ArenaRc<T> {
     }
 }
 
-impl<T> std::fmt::Pointer for ArenaRc<T> {
+impl<T: ?Sized, K: Kind<T>> std::fmt::Pointer for ArenaRc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaRc, SharedArena};
     /// let arena = SharedArena::new();
@@ -90,22 +100,223 @@ impl<T> std::fmt::Pointer for ArenaRc<T> {
     }
 }
 
-impl<T> ArenaRc<T> {
+impl<T: ?Sized> ArenaRc<T> {
     pub(crate) fn new(mut block: NonNull<Block<T>>) -> ArenaRc<T> {
         // ArenaRc is not Send, so we can make the counter non-atomic
-        let counter_mut = unsafe { block.as_mut() }.counter.get_mut();
+        unsafe { block.as_mut() }.counter.with_value_mut(|counter_mut| {
+            // The bitfield indicated the block as free so it's guarantee to be zero,
+            // but we check, just in case something went wrong
+
+            assert!(*counter_mut == 0, "ArenaRc: Counter not zero {}", counter_mut);
+            *counter_mut = 1;
+        });
+
+        ArenaRc { block, _kind: PhantomData }
+    }
+
+    /// Returns `true` if the two `ArenaRc` point to the same value
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, Pool};
+    /// let arena = Pool::new();
+    /// let my_num = arena.alloc_rc(10);
+    ///
+    /// assert!(ArenaRc::ptr_eq(&my_num, &my_num.clone()));
+    /// assert!(!ArenaRc::ptr_eq(&my_num, &arena.alloc_rc(10)));
+    /// ```
+    pub fn ptr_eq(this: &ArenaRc<T>, other: &ArenaRc<T>) -> bool {
+        std::ptr::addr_eq(this.block.as_ptr(), other.block.as_ptr())
+    }
+
+    /// Consumes the `ArenaRc`, returning a raw pointer to the value
+    ///
+    /// The reference counter is not decremented: to avoid a leak,
+    /// the pointer has to be converted back to an `ArenaRc` with
+    /// [`ArenaRc::from_raw`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, Pool};
+    /// let pool = Pool::new();
+    /// let ptr = ArenaRc::into_raw(pool.alloc_rc(10));
+    ///
+    /// assert_eq!(unsafe { *ptr }, 10);
+    ///
+    /// let my_num = unsafe { ArenaRc::from_raw(ptr) };
+    /// assert_eq!(*my_num, 10);
+    /// ```
+    ///
+    /// [`ArenaRc::from_raw`]: #method.from_raw
+    pub fn into_raw(this: ArenaRc<T>) -> *const T {
+        let ptr = Block::value_ptr(this.block);
+        std::mem::forget(this);
+        ptr
+    }
+
+    /// Constructs an `ArenaRc` from a raw pointer
+    ///
+    /// The reference counter is not incremented: the `ArenaRc`
+    /// takes the reference owned by the pointer.
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`ArenaRc::into_raw`], on the
+    /// same thread.  
+    /// Each call to `from_raw` must match a call to `into_raw`, or
+    /// to [`ArenaRc::increment_strong_count`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, Pool};
+    /// let pool = Pool::new();
+    /// let ptr = ArenaRc::into_raw(pool.alloc_rc(10));
+    ///
+    /// assert_eq!(pool.stats(), (1, 62));
+    /// std::mem::drop(unsafe { ArenaRc::from_raw(ptr) });
+    /// assert_eq!(pool.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`ArenaRc::into_raw`]: #method.into_raw
+    /// [`ArenaRc::increment_strong_count`]: #method.increment_strong_count
+    pub unsafe fn from_raw(ptr: *const T) -> ArenaRc<T> {
+        ArenaRc { block: Block::from_value_ptr(ptr), _kind: PhantomData }
+    }
+
+    /// Increments the reference counter of the value pointed by `ptr`
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`ArenaRc::into_raw`], on the
+    /// same thread, and the value must still be referenced.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, Pool};
+    /// let pool = Pool::new();
+    /// let ptr = ArenaRc::into_raw(pool.alloc_rc(10));
+    ///
+    /// unsafe {
+    ///     ArenaRc::increment_strong_count(ptr);
+    ///
+    ///     let first = ArenaRc::from_raw(ptr);
+    ///     let second = ArenaRc::from_raw(ptr);
+    ///     assert_eq!(*first, *second);
+    /// }
+    /// ```
+    ///
+    /// [`ArenaRc::into_raw`]: #method.into_raw
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        let block = Block::from_value_ptr(ptr);
+        (*block.as_ptr()).counter.with_value_mut(|counter_mut| {
+            assert!(*counter_mut < isize::MAX as usize);
+            *counter_mut += 1;
+        });
+    }
 
-        // The bitfield indicated the block as free so it's guarantee to be zero,
-        // but we check, just in case something went wrong
+    /// Decrements the reference counter of the value pointed by `ptr`
+    ///
+    /// If it was the last reference, the value is dropped.
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`ArenaRc::into_raw`], on the
+    /// same thread, and must own a reference to the value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, Pool};
+    /// let pool = Pool::new();
+    /// let ptr = ArenaRc::into_raw(pool.alloc_rc(10));
+    ///
+    /// assert_eq!(pool.stats(), (1, 62));
+    /// unsafe { ArenaRc::decrement_strong_count(ptr) };
+    /// assert_eq!(pool.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`ArenaRc::into_raw`]: #method.into_raw
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(ArenaRc::from_raw(ptr));
+    }
+}
 
-        assert!(*counter_mut == 0, "ArenaRc: Counter not zero {}", counter_mut);
-        *counter_mut = 1;
+impl<T: ?Sized, K: Kind<T>> ArenaRc<T, K> {
+    /// Changes the kind of the handle, the block is not checked and
+    /// the reference counter is not modified
+    pub(crate) fn cast_kind<K2: Kind<T>>(this: ArenaRc<T, K>) -> ArenaRc<T, K2> {
+        let block = this.block;
+        std::mem::forget(this);
+        ArenaRc { block, _kind: PhantomData }
+    }
 
-        ArenaRc { block }
+    /// Converts the `ArenaRc` to a handle of kind `K2`, without
+    /// reallocating
+    ///
+    /// A handle of a static kind, such as [`PoolKind`], releases its
+    /// block without reading the kind of its page and without
+    /// branching on it.  
+    /// If the value was not allocated by an arena of kind `K2`, the
+    /// same `ArenaRc` is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, Arena, Pool, PoolKind};
+    /// let pool = Pool::new();
+    ///
+    /// let my_num: ArenaRc<i32, PoolKind> = ArenaRc::try_into_kind(pool.alloc_rc(1)).unwrap();
+    /// let clone = my_num.clone();
+    /// assert_eq!(pool.stats(), (1, 62));
+    ///
+    /// std::mem::drop((my_num, clone));
+    /// assert_eq!(pool.stats(), (0, 63));
+    ///
+    /// assert!(ArenaRc::try_into_kind::<PoolKind>(Arena::new().alloc_rc(2)).is_err());
+    /// ```
+    ///
+    /// [`PoolKind`]: ./enum.PoolKind.html
+    pub fn try_into_kind<K2: Kind<T>>(this: ArenaRc<T, K>) -> Result<ArenaRc<T, K2>, ArenaRc<T, K>> {
+        let kind = unsafe { this.block.as_ref() }.page.page_kind();
+
+        if crate::kind::accepts::<T, K2>(kind) {
+            Ok(ArenaRc::cast_kind(this))
+        } else {
+            Err(this)
+        }
+    }
+
+    /// Converts the `ArenaRc` to a handle of [`DynamicKind`], the
+    /// default kind
+    ///
+    /// [`DynamicKind`]: ./enum.DynamicKind.html
+    pub fn into_dynamic(this: ArenaRc<T, K>) -> ArenaRc<T> {
+        ArenaRc::cast_kind(this)
     }
 }
 
-impl<T> Clone for ArenaRc<T> {
+impl ArenaRc<dyn Any> {
+    /// Attempts to downcast the value to a concrete type
+    ///
+    /// The value is not moved. On failure, the same `ArenaRc` is
+    /// returned.
+    ///
+    /// See [`coerce!`](./macro.coerce.html) to make a `ArenaRc<dyn Any>`.
+    pub fn downcast<T: Any>(self) -> Result<ArenaRc<T>, ArenaRc<dyn Any>> {
+        if self.is::<T>() {
+            let ptr = ArenaRc::into_raw(self) as *const T;
+            Ok(unsafe { ArenaRc::from_raw(ptr) })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<T: ?Sized, K: Kind<T>> Clone for ArenaRc<T, K> {
     /// Make a clone of the ArenaRc pointer.
     ///
     /// This increase the reference counter.
@@ -117,20 +328,21 @@ impl<T> Clone for ArenaRc<T> {
     /// assert_eq!(*my_num, *my_num.clone());
     /// ```
     #[inline]
-    fn clone(&self) -> ArenaRc<T> {
+    fn clone(&self) -> ArenaRc<T, K> {
         // ArenaRc is not Send, so we can make the counter non-atomic
-        let counter_mut = unsafe { &mut *self.block.as_ptr() }.counter.get_mut();
-
-        assert!(*counter_mut < isize::max_value() as usize);
-        *counter_mut += 1;
+        unsafe { &mut *self.block.as_ptr() }.counter.with_value_mut(|counter_mut| {
+            assert!(*counter_mut < isize::MAX as usize);
+            *counter_mut += 1;
+        });
 
         ArenaRc {
-            block: self.block
+            block: self.block,
+            _kind: PhantomData,
         }
     }
 }
 
-impl<T> std::ops::Deref for ArenaRc<T> {
+impl<T: ?Sized, K: Kind<T>> std::ops::Deref for ArenaRc<T, K> {
     type Target = T;
 
     /// ```
@@ -149,7 +361,7 @@ impl<T> std::ops::Deref for ArenaRc<T> {
 ///
 /// If it is the last reference to that value, the value is
 /// also dropped
-impl<T> Drop for ArenaRc<T> {
+impl<T: ?Sized, K: Kind<T>> Drop for ArenaRc<T, K> {
     /// ```
     /// # use shared_arena::{ArenaRc, Arena};
     /// let arena = Arena::new();
@@ -161,14 +373,15 @@ impl<T> Drop for ArenaRc<T> {
     /// ```
     fn drop(&mut self) {
         // ArenaRc is not Send, so we can make the counter non-atomic
-        let counter_mut = unsafe { self.block.as_mut() }.counter.get_mut();
-
-        // // We decrement the reference counter
-        *counter_mut -= 1;
+        let counter = unsafe { self.block.as_mut() }.counter.with_value_mut(|counter_mut| {
+            // // We decrement the reference counter
+            *counter_mut -= 1;
+            *counter_mut
+        });
 
         // We were the last reference
-        if *counter_mut == 0 {
-            Block::drop_block(self.block)
+        if counter == 0 {
+            Block::drop_block_as::<K>(self.block)
         };
     }
 }
@@ -1,7 +1,10 @@
 use std::cell::UnsafeCell;
-use std::sync::atomic::AtomicUsize;
+use std::convert::Infallible;
+use std::mem::MaybeUninit;
+use crate::sync::atomic::AtomicUsize;
 use std::ptr::NonNull;
 
+use crate::kind::Kind;
 use crate::page::{
     arena::PageArena,
     shared_arena::PageSharedArena,
@@ -15,100 +18,225 @@ use crate::page::{
 
 // const ALIGN_BLOCK: usize = max(128, 64);
 
-// We make the struct repr(C) to ensure that the pointer to the inner
-// value remains at offset 0. This is to avoid any pointer arithmetic
-// when dereferencing it
+/// Releases the block at an index of a page, without knowing the
+/// type of its values
+///
+/// Every page stores this function at a fixed offset for its kind,
+/// so a block can be released from a pointer to an unsized value
+/// (`Block<dyn Trait>`)
+pub(crate) type ReleaseBlockFn = unsafe fn(page: NonNull<u8>, index: usize);
+
+// We make the struct repr(C) to ensure that the header (counter and
+// page) remains at offset 0, whatever the type of the value.
+// The value is after the header, so it can be unsized
 #[repr(C)]
-pub struct Block<T> {
-    /// Inner value
-    pub value: UnsafeCell<T>,
+pub struct Block<T: ?Sized> {
     /// Number of references to this block
     pub counter: AtomicUsize,
-    /// Information about its page.
-    /// It's a tagged pointer on 64 bits architectures.
+    /// Information about its page, see [`PageTaggedPtr`].
     /// Contains:
-    ///   - Pointer to page
+    ///   - Offset of the block in its page
     ///   - Index of the block in page
     ///   - PageKind
+    ///
     /// Read only and initialized on Page creation.
     /// Doesn't need to be atomic.
     pub(crate) page: PageTaggedPtr,
+    /// Inner value
+    pub value: UnsafeCell<T>,
 }
 
-impl<T> Block<T> {
-    pub(crate) fn drop_block(block: NonNull<Block<T>>) {
-        let block_ref = unsafe { block.as_ref() };
+impl<T: ?Sized> Block<T> {
+    /// Returns the block containing the value pointed by `ptr`
+    ///
+    /// The value follows the header of the block, padded to its
+    /// alignment.
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must point to a value in a block
+    pub(crate) unsafe fn from_value_ptr(ptr: *const T) -> NonNull<Block<T>> {
+        let header = std::mem::offset_of!(Block<u8>, value);
+        let align = std::mem::align_of_val(&*ptr);
+        let offset = (header + align - 1) & !(align - 1);
+
+        NonNull::new_unchecked(ptr.byte_sub(offset) as *mut Block<T>)
+    }
 
-        match block_ref.page.page_kind() {
-            PageKind::SharedArena => {
-                let page_ptr = block_ref.page.page_ptr::<PageSharedArena<T>>();
-                PageSharedArena::<T>::drop_block(page_ptr, block);
-            }
-            PageKind::Arena => {
-                let page_ptr = block_ref.page.page_ptr::<PageArena<T>>();
-                PageArena::<T>::drop_block(page_ptr, block);
-            }
-            PageKind::Pool => {
-                let page_ptr = block_ref.page.page_ptr::<PagePool<T>>();
-                PagePool::<T>::drop_block(page_ptr, block);
-            }
+    /// Returns a pointer to the value of the block
+    pub(crate) fn value_ptr(block: NonNull<Block<T>>) -> *mut T {
+        unsafe { UnsafeCell::raw_get(std::ptr::addr_of!((*block.as_ptr()).value)) }
+    }
+
+    /// Returns a pointer to the page of the block
+    ///
+    /// It is derived from the pointer to the block, which must have
+    /// been derived from a pointer to the whole page
+    pub(crate) fn page_ptr<P>(block: NonNull<Block<T>>) -> NonNull<P> {
+        let offset = unsafe { block.as_ref() }.page.page_offset();
+        unsafe {
+            NonNull::new_unchecked(block.as_ptr().cast::<u8>().sub(offset)).cast()
+        }
+    }
+
+    /// Drop the inner value and release the block, to a page of kind `K`
+    #[inline]
+    pub(crate) fn drop_block_as<K: Kind<T>>(block: NonNull<Block<T>>) {
+        unsafe {
+            // Drop the inner value
+            std::ptr::drop_in_place(block.as_ref().value.get());
+        }
+
+        K::release_block(block);
+    }
+
+    /// Drop the inner value and release the block, to a page of kind
+    /// `kind` with the function stored in the page
+    pub(crate) fn drop_block_erased(block: NonNull<Block<T>>, kind: PageKind) {
+        unsafe {
+            // Drop the inner value
+            std::ptr::drop_in_place(block.as_ref().value.get());
+        }
+
+        Block::release_block_erased(block, kind);
+    }
+
+    /// Release the block to its page, without dropping the inner value
+    ///
+    /// The counter must be zero
+    pub(crate) fn release_block(block: NonNull<Block<T>>) {
+        let kind = unsafe { block.as_ref() }.page.page_kind();
+        Block::release_block_erased(block, kind)
+    }
+
+    /// Same as `release_block`, with the kind of the page already known
+    fn release_block_erased(block: NonNull<Block<T>>, kind: PageKind) {
+        let tagged_ptr = unsafe { block.as_ref() }.page;
+
+        debug_assert_eq!(kind, tagged_ptr.page_kind());
+
+        // The type of the value might be erased, so we read the function
+        // releasing the block from its page
+        let offset = match kind {
+            PageKind::SharedArena => std::mem::offset_of!(PageSharedArena<()>, release),
+            PageKind::Arena => std::mem::offset_of!(PageArena<()>, release),
+            PageKind::Pool => std::mem::offset_of!(PagePool<()>, release),
+        };
+
+        let page_ptr = Block::page_ptr::<u8>(block);
+
+        unsafe {
+            let release = page_ptr.as_ptr().add(offset) as *const ReleaseBlockFn;
+            (*release)(page_ptr, tagged_ptr.index_block());
         }
     }
 }
 
-#[cfg(target_pointer_width = "64")]
-#[derive(Copy, Clone)]
-pub(crate) struct PageTaggedPtr {
-    pub(crate) data: usize,
+impl<T> Block<T> {
+    /// Calls `initializer` on the uninitialized value of the block,
+    /// and returns the block once it's initialized
+    ///
+    /// If `initializer` panics or returns an error, the block is
+    /// released, without dropping the value.
+    /// The counter must be zero
+    pub(crate) fn try_init<F, E>(block: NonNull<Block<T>>, initializer: F) -> Result<NonNull<Block<T>>, E>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> Result<&T, E>
+    {
+        struct Guard<T>(NonNull<Block<T>>);
+
+        impl<T> Drop for Guard<T> {
+            fn drop(&mut self) {
+                Block::release_block(self.0);
+            }
+        }
+
+        let guard = Guard(block);
+        let ptr = Block::value_ptr(block);
+
+        // MaybeUninit<T> has the same layout as T
+        let reference = initializer(unsafe { &mut *(ptr as *mut MaybeUninit<T>) })?;
+
+        assert_eq!(
+            ptr as *const T,
+            reference as *const T,
+            "`initializer` must return a reference of its parameter"
+        );
+
+        std::mem::forget(guard);
+        Ok(block)
+    }
+
+    /// Same as `try_init`, with an initializer that can't fail
+    pub(crate) fn init<F>(block: NonNull<Block<T>>, initializer: F) -> NonNull<Block<T>>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
+    {
+        match Block::try_init(block, |uninit| Ok::<_, Infallible>(initializer(uninit))) {
+            Ok(block) => block,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Move the inner value out and release the block
+    ///
+    /// The counter must be zero
+    pub(crate) fn take_value(block: NonNull<Block<T>>) -> T {
+        let value = unsafe { block.as_ref().value.get().read() };
+        Block::release_block(block);
+        value
+    }
 }
 
-#[cfg(not(target_pointer_width = "64"))]
+/// Information about the page of a block, in a `usize`
+///
+/// Contains, from the least significant bits:
+///   - Index of the block in the page, 6 bits
+///   - PageKind, 2 bits
+///   - Offset in bytes of the block from the start of its page
+///
+/// It doesn't contain the address of the page: it is computed from
+/// the address of the block with [`Block::page_ptr`], which keeps the
+/// provenance of the pointer to the block and doesn't depend on the
+/// width of the addresses.
 #[derive(Copy, Clone)]
 pub(crate) struct PageTaggedPtr {
-    pub(crate) ptr: usize,
-    pub(crate) data: usize
+    pub(crate) data: usize,
 }
 
 impl std::fmt::Debug for PageTaggedPtr {
-    #[cfg(target_pointer_width = "64")]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("PageTaggedPtr")
-         .field("data    ", &format!("{:064b}", self.data))
-         .field("page_ptr", &format!("{:064b}", self.page_ptr::<usize>().as_ptr() as usize))
-         .field("page_kind", &self.page_kind())
-         .field("page_index_block", &format!("{:08b} ({})", self.index_block(), self.index_block()))
-         .finish()
-    }
-
-    #[cfg(not(target_pointer_width = "64"))]
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("PageTaggedPtr")
-         .field("page_ptr", &format!("{:032b}", self.page_ptr::<usize>().as_ptr() as usize))
-         .field("data    ", &format!("{:032b}", self.data))
+         .field("data", &format!("{:0width$b}", self.data, width = usize::BITS as usize))
+         .field("page_offset", &self.page_offset())
          .field("page_kind", &self.page_kind())
          .field("page_index_block", &format!("{:08b} ({})", self.index_block(), self.index_block()))
          .finish()
     }
 }
 
+/// Number of bits of the tag, below the offset
+const TAG_BITS: u32 = 8;
+
 impl PageTaggedPtr {
-    #[cfg(target_pointer_width = "64")]
-    pub(crate) fn new(page_ptr: usize, index: usize, kind: PageKind) -> PageTaggedPtr {
-        let tag = Self::make_tag(index, kind);
+    /// `page_offset` is the offset in bytes of the block from the
+    /// start of its page
+    pub(crate) fn new(page_offset: usize, index: usize, kind: PageKind) -> PageTaggedPtr {
+        assert!(
+            page_offset <= usize::MAX >> TAG_BITS,
+            "PageTaggedPtr: the page is too large"
+        );
 
         PageTaggedPtr {
-            data: (page_ptr & !(0b11111111 << 56)) | (tag << 56),
+            data: (page_offset << TAG_BITS) | Self::make_tag(index, kind),
         }
     }
 
-    #[cfg(not(target_pointer_width = "64"))]
-    pub(crate) fn new(page_ptr: usize, index: usize, kind: PageKind) -> PageTaggedPtr {
-        let tag = Self::make_tag(index, kind);
-
-        PageTaggedPtr {
-            ptr: page_ptr,
-            data: tag
-        }
+    /// Information of the block at `block` in the page at `page`
+    pub(crate) fn of_block<P, B>(page: *const P, block: *const B, index: usize, kind: PageKind) -> PageTaggedPtr {
+        // The block is in the allocation of the page
+        let page_offset = unsafe { block.cast::<u8>().offset_from(page.cast::<u8>()) };
+        PageTaggedPtr::new(page_offset as usize, index, kind)
     }
 
     pub(crate) fn make_tag(index: usize, kind: PageKind) -> usize {
@@ -121,16 +249,9 @@ impl PageTaggedPtr {
         kind | index
     }
 
-    #[cfg(target_pointer_width = "64")]
-    pub(crate) fn page_ptr<T>(self) -> NonNull<T> {
-        let ptr = ((self.data << 8) as isize >> 8) as *mut T;
-
-        NonNull::new(ptr).expect("Invalid pointer")
-    }
-
-    #[cfg(not(target_pointer_width = "64"))]
-    pub(crate) fn page_ptr<T>(self) -> NonNull<T> {
-        NonNull::new(self.ptr as *mut T).unwrap()
+    /// Offset in bytes of the block from the start of its page
+    pub(crate) fn page_offset(self) -> usize {
+        self.data >> TAG_BITS
     }
 
     pub(crate) fn page_kind(self) -> PageKind {
@@ -138,17 +259,12 @@ impl PageTaggedPtr {
     }
 
     pub(crate) fn index_block(self) -> usize {
-        #[cfg(target_pointer_width = "64")]
-        let rotate = 56;
-        #[cfg(not(target_pointer_width = "64"))]
-        let rotate = 0;
-
-        (self.data >> rotate) & 0b111111
+        self.data & 0b111111
     }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub(crate) enum PageKind {
+pub enum PageKind {
     SharedArena = 0,
     Arena = 1,
     Pool = 2
@@ -156,12 +272,7 @@ pub(crate) enum PageKind {
 
 impl From<PageTaggedPtr> for PageKind {
     fn from(source: PageTaggedPtr) -> Self {
-        #[cfg(target_pointer_width = "64")]
-        let rotate = 62;
-        #[cfg(not(target_pointer_width = "64"))]
-        let rotate = 6;
-
-        let kind = source.data >> rotate;
+        let kind = (source.data >> 6) & 0b11;
 
         match kind {
             0 => PageKind::SharedArena,
@@ -172,9 +283,9 @@ impl From<PageTaggedPtr> for PageKind {
     }
 }
 
-impl Into<usize> for PageKind {
-    fn into(self) -> usize {
-        match self {
+impl From<PageKind> for usize {
+    fn from(kind: PageKind) -> usize {
+        match kind {
             PageKind::SharedArena => 0,
             PageKind::Arena => 1,
             PageKind::Pool => 2
@@ -200,54 +311,71 @@ mod tests {
 
     #[test]
     fn page_tagged_ptr() {
-        let real_ptr = Box::into_raw(Box::new(1));
+        let offsets = [0, 16, 4096, 63 * 128 + 64, usize::MAX >> 8];
 
         for index_block in 0..64 {
-            let tagged_ptr = PageTaggedPtr::new(real_ptr as usize, index_block, PageKind::SharedArena);
-            let ptr = tagged_ptr.page_ptr::<usize>().as_ptr();
-            assert_eq!(ptr, real_ptr as *mut _, "{:064b}", ptr as usize);
-            assert_eq!(tagged_ptr.page_kind(), PageKind::SharedArena);
-            assert_eq!(tagged_ptr.index_block(), index_block);
-
-            let tagged_ptr = PageTaggedPtr::new(real_ptr as usize, index_block, PageKind::Arena);
-            let ptr = tagged_ptr.page_ptr::<usize>().as_ptr();
-            assert_eq!(ptr, real_ptr as *mut _, "{:064b}", ptr as usize);
-            assert_eq!(tagged_ptr.page_kind(), PageKind::Arena);
-            assert_eq!(tagged_ptr.index_block(), index_block);
-
-            let tagged_ptr = PageTaggedPtr::new(real_ptr as usize, index_block, PageKind::Pool);
-            let ptr = tagged_ptr.page_ptr::<usize>().as_ptr();
-            assert_eq!(ptr, real_ptr as *mut _, "{:064b}", ptr as usize);
-            assert_eq!(tagged_ptr.page_kind(), PageKind::Pool);
-            assert_eq!(tagged_ptr.index_block(), index_block);
-
-            let tagged_ptr = PageTaggedPtr::new(16, index_block, PageKind::SharedArena);
-            let ptr = tagged_ptr.page_ptr::<usize>().as_ptr();
-            assert_eq!(ptr, 16 as *mut _, "{:064b}", ptr as usize);
-            assert_eq!(tagged_ptr.page_kind(), PageKind::SharedArena);
-            assert_eq!(tagged_ptr.index_block(), index_block);
-
-            let tagged_ptr = PageTaggedPtr::new(16, index_block, PageKind::Arena);
-            let ptr = tagged_ptr.page_ptr::<usize>().as_ptr();
-            assert_eq!(ptr, 16 as *mut _, "{:064b}", ptr as usize);
-            assert_eq!(tagged_ptr.page_kind(), PageKind::Arena);
-            assert_eq!(tagged_ptr.index_block(), index_block);
-
-            let tagged_ptr = PageTaggedPtr::new(16, index_block, PageKind::Pool);
-            let ptr = tagged_ptr.page_ptr::<usize>().as_ptr();
-            assert_eq!(ptr, 16 as *mut _, "{:064b}", ptr as usize);
-            assert_eq!(tagged_ptr.page_kind(), PageKind::Pool);
-            assert_eq!(tagged_ptr.index_block(), index_block);
+            for &offset in &offsets {
+                for &kind in &[PageKind::SharedArena, PageKind::Arena, PageKind::Pool] {
+                    let tagged_ptr = PageTaggedPtr::new(offset, index_block, kind);
+                    assert_eq!(tagged_ptr.page_offset(), offset, "{:?}", tagged_ptr);
+                    assert_eq!(tagged_ptr.page_kind(), kind);
+                    assert_eq!(tagged_ptr.index_block(), index_block);
+                }
+            }
         }
+    }
 
-        unsafe { Box::from_raw(real_ptr) };
+    #[test]
+    #[should_panic]
+    fn page_tagged_ptr_too_large() {
+        let _ = PageTaggedPtr::new((usize::MAX >> 8) + 1, 0, PageKind::Arena);
+    } // grcov_ignore
+
+    #[test]
+    fn block_page_ptr() {
+        use std::ptr::NonNull;
+        use crate::{Arena, Pool, SharedArena};
+
+        // The page is found from the blocks of each kind of arena,
+        // with any size and alignment of the values
+        fn check<T>(block: NonNull<super::Block<T>>, kind: PageKind) {
+            let tagged_ptr = unsafe { block.as_ref() }.page;
+            assert_eq!(tagged_ptr.page_kind(), kind);
+
+            let page = super::Block::page_ptr::<u8>(block);
+            let expected = block.as_ptr() as usize - tagged_ptr.page_offset();
+            assert_eq!(page.as_ptr() as usize, expected);
+        }
+
+        fn block_of<T>(value: &impl std::ops::Deref<Target = T>) -> NonNull<super::Block<T>> {
+            unsafe { super::Block::from_value_ptr(&**value as *const T) }
+        }
+
+        #[repr(align(256))]
+        struct Aligned;
+
+        let arena = SharedArena::new();
+        let values: Vec<_> = (0..70).map(|n| arena.alloc(n)).collect();
+        for value in &values {
+            check(block_of(value), PageKind::SharedArena);
+        }
+        let first = super::Block::page_ptr::<u8>(block_of(&values[0]));
+        let last = super::Block::page_ptr::<u8>(block_of(&values[62]));
+        assert_eq!(first, last);
+
+        let arena = Arena::new();
+        let value = arena.alloc(Aligned);
+        check(block_of(&value), PageKind::Arena);
+
+        let pool = Pool::new();
+        let value = pool.alloc([0u8; 1000]);
+        check(block_of(&value), PageKind::Pool);
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn page_tagged_ptr_debug() {
-        let real_ptr = Box::into_raw(Box::new(1));
-
-        let tagged_ptr = PageTaggedPtr::new(real_ptr as usize, 63, PageKind::Arena);
+        let tagged_ptr = PageTaggedPtr::new(4096, 63, PageKind::Arena);
         println!("{:?} {:?}", tagged_ptr.clone(), PageKind::Arena);
 
         let tagged_ptr_2 = tagged_ptr;
@@ -255,8 +383,23 @@ mod tests {
 
         assert!(tagged_ptr.data == tagged_ptr_2.data);
         assert!(tagged_ptr.data == tagged_ptr_3.data);
+    }
 
-        unsafe { Box::from_raw(real_ptr) };
+    #[test]
+    fn release_offset() {
+        use std::mem::offset_of;
+        use crate::page::{arena::PageArena, pool::PagePool, shared_arena::PageSharedArena};
+
+        #[repr(align(256))]
+        struct Aligned;
+
+        // The offset must be the same for any T, it's read from
+        // blocks with an erased type
+        assert_eq!(offset_of!(PageSharedArena<u8>, release), offset_of!(PageSharedArena<()>, release));
+        assert_eq!(offset_of!(PageSharedArena<Aligned>, release), offset_of!(PageSharedArena<()>, release));
+        assert_eq!(offset_of!(PageArena<Aligned>, release), offset_of!(PageArena<()>, release));
+        assert_eq!(offset_of!(PagePool<Aligned>, release), offset_of!(PagePool<()>, release));
+        assert_eq!(offset_of!(PagePool<[u64; 9]>, release), offset_of!(PagePool<()>, release));
     }
 
     #[test]
@@ -264,28 +407,24 @@ mod tests {
     fn invalid_block() {
         use std::cell::UnsafeCell;
         use std::ptr::NonNull;
-        use std::sync::atomic::AtomicUsize;
+        use crate::sync::atomic::AtomicUsize;
 
         let mut block = super::Block {
-            value: UnsafeCell::new(1),
             counter: AtomicUsize::new(1),
             page: super::PageTaggedPtr {
                 data: !0,
-                #[cfg(not(target_pointer_width = "64"))]
-                ptr: !0,
             },
+            value: UnsafeCell::new(1),
         };
 
-        super::Block::drop_block(NonNull::from(&mut block));
+        super::Block::drop_block_as::<crate::kind::DynamicKind>(NonNull::from(&mut block));
     } // grcov_ignore
 
     #[test]
     #[should_panic]
     fn invalid_tagged_ptr() {
-        super::PageKind::from(super::PageTaggedPtr {
+        let _ = super::PageKind::from(super::PageTaggedPtr {
             data: !0,
-            #[cfg(not(target_pointer_width = "64"))]
-            ptr: !0,
         });
     } // grcov_ignore
 }
@@ -33,7 +33,7 @@ pub struct CacheAligned<T: Sized>(T);
 
 impl<T> CacheAligned<T> {
     #[allow(dead_code)]
-    pub fn new(v: T) -> CacheAligned<T> {
+    pub const fn new(v: T) -> CacheAligned<T> {
         CacheAligned(v)
     }
 }
@@ -78,6 +78,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn test_cached_aligned_clone() {
         let var = CacheAligned::new(10);
         assert!(*var == 10 && *var.clone() == 10);
@@ -0,0 +1,281 @@
+//! Conversion of the handles to handles of unsized values, the way
+//! `Box<T>` converts to `Box<dyn Trait>`
+
+use std::marker::PhantomData;
+
+use crate::{ArenaArc, ArenaBox, ArenaRc, Kind, PoolBox};
+
+/// Converts a handle to a handle of an unsized value, such as a trait
+/// object or a slice
+///
+/// `coerce!(handle => Target)` works with [`ArenaBox`], [`ArenaArc`],
+/// [`ArenaRc`] and [`PoolBox`]. The value is not moved: it stays in
+/// its page, which is found from the block when it's dropped.
+///
+/// On stable Rust, the handles can't implement `CoerceUnsized`, so
+/// this macro converts the pointer to the value instead. Only the
+/// implicit unsizing coercions of the compiler are accepted, from
+/// `T` to `dyn Trait` or from `[T; N]` to `[T]`.
+///
+/// The handle returned has the [`DynamicKind`], whatever the kind of
+/// `handle`: the type of the page is erased with the type of the value.
+///
+/// ## Example
+///
+/// ```
+/// use shared_arena::{coerce, ArenaArc, ArenaBox, SharedArena};
+/// use std::fmt::Display;
+///
+/// let arena = SharedArena::new();
+///
+/// let values: Vec<ArenaBox<dyn Display>> = vec![
+///     coerce!(arena.alloc(1) => dyn Display),
+///     coerce!(arena.alloc(2) => dyn Display),
+/// ];
+/// assert_eq!(values[1].to_string(), "2");
+///
+/// let slice: ArenaArc<[u8]> = coerce!(SharedArena::new().alloc_arc([1, 2, 3]) => [u8]);
+/// assert_eq!(slice.len(), 3);
+/// ```
+///
+/// [`ArenaBox`]: ./struct.ArenaBox.html
+/// [`ArenaArc`]: ./struct.ArenaArc.html
+/// [`ArenaRc`]: ./struct.ArenaRc.html
+/// [`PoolBox`]: ./struct.PoolBox.html
+/// [`DynamicKind`]: ./enum.DynamicKind.html
+#[macro_export]
+macro_rules! coerce {
+    ($handle:expr => $target:ty) => {{
+        let handle = $handle;
+        let marker = $crate::__private::marker(&handle);
+        let ptr = $crate::__private::CoerceHandle::into_raw_ptr(handle);
+        // Only an unsizing coercion can convert the pointer
+        let ptr: *const $target = ptr;
+        #[allow(unused_unsafe)]
+        unsafe { $crate::__private::from_raw_ptr(marker, ptr) }
+    }};
+}
+
+/// Handles that can be converted with [`coerce!`]
+///
+/// Not part of the public API, it's used by the macro only.
+#[doc(hidden)]
+pub trait CoerceHandle {
+    type Value: ?Sized;
+    type With<U: ?Sized>;
+
+    fn into_raw_ptr(this: Self) -> *const Self::Value;
+
+    /// ## Safety
+    ///
+    /// `ptr` must come from `into_raw_ptr`, with its metadata
+    /// converted by an unsizing coercion only
+    unsafe fn from_raw_ptr<U: ?Sized>(ptr: *const U) -> Self::With<U>;
+}
+
+#[doc(hidden)]
+pub fn marker<H: CoerceHandle>(_: &H) -> PhantomData<H> {
+    PhantomData
+}
+
+/// ## Safety
+///
+/// See [`CoerceHandle::from_raw_ptr`]
+#[doc(hidden)]
+pub unsafe fn from_raw_ptr<H, U>(_: PhantomData<H>, ptr: *const U) -> H::With<U>
+where
+    H: CoerceHandle,
+    U: ?Sized
+{
+    H::from_raw_ptr(ptr)
+}
+
+impl<T: ?Sized, K: Kind<T>> CoerceHandle for ArenaBox<T, K> {
+    type Value = T;
+    type With<U: ?Sized> = ArenaBox<U>;
+
+    fn into_raw_ptr(this: ArenaBox<T, K>) -> *const T {
+        ArenaBox::into_raw(ArenaBox::into_dynamic(this))
+    }
+
+    unsafe fn from_raw_ptr<U: ?Sized>(ptr: *const U) -> ArenaBox<U> {
+        ArenaBox::from_raw(ptr as *mut U)
+    }
+}
+
+impl<T: ?Sized, K: Kind<T>> CoerceHandle for ArenaArc<T, K> {
+    type Value = T;
+    type With<U: ?Sized> = ArenaArc<U>;
+
+    fn into_raw_ptr(this: ArenaArc<T, K>) -> *const T {
+        ArenaArc::into_raw(ArenaArc::into_dynamic(this))
+    }
+
+    unsafe fn from_raw_ptr<U: ?Sized>(ptr: *const U) -> ArenaArc<U> {
+        ArenaArc::from_raw(ptr)
+    }
+}
+
+impl<T: ?Sized, K: Kind<T>> CoerceHandle for ArenaRc<T, K> {
+    type Value = T;
+    type With<U: ?Sized> = ArenaRc<U>;
+
+    fn into_raw_ptr(this: ArenaRc<T, K>) -> *const T {
+        ArenaRc::into_raw(ArenaRc::into_dynamic(this))
+    }
+
+    unsafe fn from_raw_ptr<U: ?Sized>(ptr: *const U) -> ArenaRc<U> {
+        ArenaRc::from_raw(ptr)
+    }
+}
+
+impl<T: ?Sized> CoerceHandle for PoolBox<T> {
+    type Value = T;
+    type With<U: ?Sized> = PoolBox<U>;
+
+    fn into_raw_ptr(this: PoolBox<T>) -> *const T {
+        PoolBox::into_raw(this)
+    }
+
+    unsafe fn from_raw_ptr<U: ?Sized>(ptr: *const U) -> PoolBox<U> {
+        PoolBox::from_raw(ptr as *mut U)
+    }
+}
+
+/// Code that should fail to compile.
+/// compile_fail is supported on doc only
+///
+/// Fails because `u8` doesn't coerce to `u64`
+/// ```compile_fail
+/// use shared_arena::{coerce, ArenaBox, SharedArena};
+///
+/// let arena = SharedArena::new();
+/// let _: ArenaBox<u64> = coerce!(arena.alloc(1u8) => u64);
+/// ```
+///
+/// Fails because `Rc` isn't `Send`
+/// ```compile_fail
+/// use shared_arena::{coerce, ArenaBox, SharedArena};
+/// use std::any::Any;
+///
+/// let arena = SharedArena::new();
+/// let _ = coerce!(arena.alloc(std::rc::Rc::new(1)) => dyn Any + Send);
+/// ```
+#[allow(dead_code)]
+fn coerce_fail() {} // grcov_ignore
+
+#[cfg(test)]
+mod tests {
+    use crate::{ArenaArc, ArenaBox, ArenaRc, Arena, Pool, PoolBox, SharedArena};
+    use std::any::Any;
+    use std::fmt::Debug;
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    trait Handler: Send {
+        fn handle(&mut self) -> usize;
+    }
+
+    struct Counter(usize);
+
+    impl Handler for Counter {
+        fn handle(&mut self) -> usize {
+            self.0 += 1;
+            self.0
+        }
+    }
+
+    #[repr(align(64))]
+    struct Aligned(Arc<AtomicUsize>);
+
+    impl Handler for Aligned {
+        fn handle(&mut self) -> usize {
+            self.0.fetch_add(1, Ordering::Relaxed) + 1
+        }
+    }
+
+    impl Drop for Aligned {
+        fn drop(&mut self) {
+            self.0.fetch_add(100, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn coerce_dyn_trait() {
+        let counters = SharedArena::new();
+        let aligned = Arena::new();
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let mut handlers: Vec<ArenaBox<dyn Handler>> = vec![
+            coerce!(counters.alloc(Counter(10)) => dyn Handler),
+            coerce!(aligned.alloc(Aligned(dropped.clone())) => dyn Handler),
+        ];
+
+        assert_eq!(handlers[0].handle(), 11);
+        assert_eq!(handlers[1].handle(), 1);
+
+        let handlers = std::thread::spawn(move || {
+            handlers.iter_mut().map(|h| h.handle()).collect::<Vec<_>>()
+        }).join().unwrap();
+
+        assert_eq!(handlers, vec![12, 2]);
+        assert_eq!(dropped.load(Ordering::Relaxed), 102);
+        assert_eq!(counters.stats(), (0, 63));
+        assert_eq!(aligned.stats(), (0, 63));
+    }
+
+    #[test]
+    fn coerce_all_handles() {
+        let arena = SharedArena::new();
+        let arc: ArenaArc<dyn Debug + Send + Sync> = coerce!(arena.alloc_arc(1) => dyn Debug + Send + Sync);
+        let clone = arc.clone();
+        assert_eq!(format!("{:?}", clone), "1");
+
+        let arena = Arena::new();
+        let rc: ArenaRc<[u16]> = coerce!(arena.alloc_rc([1, 2, 3]) => [u16]);
+        assert_eq!(&rc[1..], &[2, 3]);
+
+        let pool = Pool::new();
+        let mut value: PoolBox<dyn Iterator<Item = u8>> = coerce!(pool.alloc(0..4) => dyn Iterator<Item = u8>);
+        assert_eq!(value.next(), Some(0));
+        assert_eq!(value.sum::<u8>(), 6);
+
+        std::mem::drop(rc);
+        assert_eq!(arena.stats(), (0, 63));
+        assert!(ArenaArc::ptr_eq(&arc, &clone));
+    }
+
+    #[test]
+    fn coerce_downcast() {
+        let strings = SharedArena::new();
+
+        let value: ArenaBox<dyn Any> = coerce!(strings.alloc(String::from("a")) => dyn Any);
+        let value = value.downcast::<usize>().unwrap_err();
+        let value: ArenaBox<String> = value.downcast().unwrap();
+        assert_eq!(*value, "a");
+
+        let numbers = SharedArena::new();
+        let value: ArenaArc<dyn Any + Send + Sync> = coerce!(numbers.alloc_arc(10u64) => dyn Any + Send + Sync);
+        let clone = value.clone();
+        let value: ArenaArc<u64> = value.downcast().unwrap();
+        assert!(clone.is::<u64>());
+        assert_eq!(*value, 10);
+
+        std::mem::drop(clone);
+        assert_eq!(ArenaArc::into_inner(value), Some(10));
+        assert_eq!(numbers.stats(), (0, 63));
+
+        let arena = Arena::new();
+        let value: ArenaRc<dyn Any> = coerce!(arena.alloc_rc(1u8) => dyn Any);
+        assert!(value.clone().downcast::<u16>().is_err());
+        assert_eq!(*value.downcast::<u8>().unwrap(), 1);
+
+        let pool = Pool::new();
+        let value: PoolBox<dyn Any> = coerce!(pool.alloc(1i8) => dyn Any);
+        let value: PoolBox<i8> = value.downcast().unwrap();
+        assert_eq!(*value, 1);
+
+        std::mem::drop(value);
+        assert_eq!(pool.stats(), (0, 63));
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::atomic::AtomicUsize;
+use crate::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
 use std::cell::Cell;
 use static_assertions::const_assert;
 
@@ -10,3 +10,36 @@ pub(crate) type Bitfield = AtomicUsize;
 pub(crate) type Pointer<T> = Cell<*mut T>;
 
 const_assert!(std::mem::size_of::<Bitfield>() == BITFIELD_WIDTH / 8);
+
+/// Lock taken by the thread that modifies the page lists of an arena
+///
+/// `new` doesn't wait: it returns `None` when another thread already
+/// holds the lock.
+pub(crate) struct WriterGuard<'a> {
+    writer: &'a AtomicBool
+}
+
+impl WriterGuard<'_> {
+    pub(crate) fn new(writer: &AtomicBool) -> Option<WriterGuard<'_>> {
+        if !writer.load(Relaxed) && !writer.swap(true, AcqRel) {
+            Some(WriterGuard { writer })
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn new_blocking(writer: &AtomicBool) -> WriterGuard<'_> {
+        loop {
+            if !writer.swap(true, AcqRel) {
+                return WriterGuard { writer }
+            }
+            crate::sync::yield_now();
+        }
+    }
+}
+
+impl Drop for WriterGuard<'_> {
+    fn drop(&mut self) {
+        self.writer.store(false, Release);
+    }
+}
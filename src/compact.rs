@@ -0,0 +1,103 @@
+use std::marker::PhantomData;
+
+use crate::ArenaArc;
+
+/// Number of bits used by the index of the block in a `Compact32`
+const INDEX_BITS: u32 = 6;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+
+/// Highest page id that can be encoded in a `Compact32`
+pub(crate) const MAX_PAGE_ID: u32 = u32::MAX >> INDEX_BITS;
+
+/// A reference-counting pointer to `T` in a [`SharedArena`], on 32 bits
+///
+/// `Compact32` is the compact representation of an [`ArenaArc`]:
+/// instead of a pointer, it stores the id of the page in the arena
+/// (26 bits) and the index of the value in that page (6 bits).
+/// On 64 bits targets, it takes half the memory of an `ArenaArc`.
+///
+/// The value is accessed through the arena it comes from, which keeps
+/// a table of its pages: see [`SharedArena::get_compact`].
+///
+/// Like an `ArenaArc`, a `Compact32` owns a reference to the value.
+/// But it doesn't know its arena, so dropping it doesn't release the
+/// value: it must be given back to the arena with
+/// [`SharedArena::from_compact`] or [`SharedArena::drop_compact`],
+/// otherwise the value is leaked.  
+/// There is no room in 32 bits to check the arena of a `Compact32`:
+/// the functions taking it are unsafe, it must be given to the arena
+/// that made it.
+///
+/// ```
+/// # use shared_arena::{ArenaArc, Compact32, SharedArena};
+/// let arena = SharedArena::new();
+///
+/// let compact: Compact32<i32> = arena.to_compact(arena.alloc_arc(10));
+/// assert_eq!(std::mem::size_of_val(&compact), 4);
+/// assert_eq!(unsafe { *arena.get_compact(&compact) }, 10);
+///
+/// let arc: ArenaArc<i32> = unsafe { arena.from_compact(compact) };
+/// assert_eq!(*arc, 10);
+/// ```
+///
+/// [`ArenaArc`]: ./struct.ArenaArc.html
+/// [`SharedArena`]: ./struct.SharedArena.html
+/// [`SharedArena::get_compact`]: ./struct.SharedArena.html#method.get_compact
+/// [`SharedArena::from_compact`]: ./struct.SharedArena.html#method.from_compact
+/// [`SharedArena::drop_compact`]: ./struct.SharedArena.html#method.drop_compact
+#[repr(transparent)]
+#[must_use = "the value is leaked if the Compact32 is not given back to its arena"]
+pub struct Compact32<T> {
+    id: u32,
+    _marker: PhantomData<ArenaArc<T>>,
+}
+
+impl<T> Compact32<T> {
+    pub(crate) fn new(page_id: u32, index: usize) -> Compact32<T> {
+        assert!(page_id <= MAX_PAGE_ID, "Compact32: page id {} doesn't fit in 26 bits", page_id);
+
+        Compact32 {
+            id: (page_id << INDEX_BITS) | index as u32,
+            _marker: PhantomData
+        }
+    }
+
+    pub(crate) fn page_id(&self) -> u32 {
+        self.id >> INDEX_BITS
+    }
+
+    pub(crate) fn index_block(&self) -> usize {
+        (self.id & INDEX_MASK) as usize
+    }
+}
+
+impl<T> std::fmt::Debug for Compact32<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Compact32")
+         .field("page_id", &self.page_id())
+         .field("index_block", &self.index_block())
+         .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Compact32, MAX_PAGE_ID};
+
+    #[test]
+    fn compact_encoding() {
+        for index in 0..63 {
+            for &page_id in &[0, 1, 1000, MAX_PAGE_ID] {
+                let compact = Compact32::<usize>::new(page_id, index);
+                assert_eq!(compact.page_id(), page_id);
+                assert_eq!(compact.index_block(), index);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn compact_too_many_pages() {
+        let _ = Compact32::<usize>::new(MAX_PAGE_ID + 1, 0);
+    } // grcov_ignore
+}
@@ -0,0 +1,173 @@
+use std::marker::PhantomData;
+use crate::sync::atomic::{AtomicPtr, Ordering::*};
+
+use crate::page::shared_arena::PageSharedArena;
+use crate::page_table::PageTable;
+
+/// The head fits in 64 bits: a 32 bits page id and a 32 bits version
+#[cfg(target_has_atomic = "64")]
+mod layout {
+    pub(super) type Bits = u64;
+    pub(super) type AtomicBits = crate::sync::atomic::AtomicU64;
+    pub(super) const ID_BITS: u32 = 32;
+}
+
+/// Without 64 bits atomics (mips, powerpc, ..), the head fits in 32
+/// bits: a 20 bits page id and a 12 bits version.
+/// The address space can't hold much more than 2^20 pages anyway
+#[cfg(not(target_has_atomic = "64"))]
+mod layout {
+    pub(super) type Bits = u32;
+    pub(super) type AtomicBits = crate::sync::atomic::AtomicU32;
+    pub(super) const ID_BITS: u32 = 20;
+}
+
+use layout::{AtomicBits, Bits, ID_BITS};
+
+/// Id of the null page, the highest id that fits in the head
+const NULL_ID: u32 = (Bits::MAX >> (Bits::BITS - ID_BITS)) as u32;
+
+/// A value of the head of a `FreeList`
+///
+/// The id of the first page in the lower `ID_BITS` bits, and the
+/// version of the head in the upper bits, wrapping around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Head(Bits);
+
+impl Head {
+    fn new(id: u32, version: u32) -> Head {
+        // The upper bits of the version are shifted out
+        Head(((version as Bits) << ID_BITS) | id as Bits)
+    }
+
+    fn id(self) -> u32 {
+        (self.0 & NULL_ID as Bits) as u32
+    }
+
+    fn version(self) -> u32 {
+        (self.0 >> ID_BITS) as u32
+    }
+
+    /// The head after it is replaced with `page`
+    fn next<T>(self, page: *mut PageSharedArena<T>) -> Head {
+        let id = unsafe { page.as_ref() }.map(|page| page.id.load(Relaxed)).unwrap_or(NULL_ID);
+        Head::new(Head::checked_id(id), self.version().wrapping_add(1))
+    }
+
+    // NULL_ID is u32::MAX with 64 bits atomics: the page table never
+    // gives it, the comparison is always true
+    #[allow(clippy::absurd_extreme_comparisons)]
+    fn checked_id(id: u32) -> u32 {
+        assert!(id <= NULL_ID, "SharedArena: too many pages for the free list");
+        id
+    }
+}
+
+/// Head of the free list of a `SharedArena`, safe from the ABA problem
+///
+/// Any thread can pop the first page of the list, with a
+/// compare-and-swap of the head. Between the time a thread read the
+/// head and its `next_free`, and the compare-and-swap, the page might
+/// have been popped by another thread, put in the pending list, and
+/// be the head again with a different `next_free`.
+/// A compare-and-swap on a pointer would succeed and link a page no
+/// longer in the list.
+///
+/// Instead, the head stores the id of the page in the page table of
+/// the arena, with a version incremented on each change: the
+/// compare-and-swap fails when the head has been changed meanwhile.
+/// It fits in an `AtomicU64`, or in an `AtomicU32` on the targets
+/// without 64 bits atomics, see `layout`.
+pub(crate) struct FreeList<T> {
+    head: AtomicBits,
+    _marker: PhantomData<AtomicPtr<PageSharedArena<T>>>,
+}
+
+impl<T> FreeList<T> {
+    pub(crate) fn new(first: *mut PageSharedArena<T>) -> FreeList<T> {
+        FreeList {
+            head: AtomicBits::new(Head::new(NULL_ID, 0).next(first).0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the head and its first page, null if the list is empty
+    pub(crate) fn load(
+        &self,
+        pages: &PageTable<PageSharedArena<T>>,
+        order: crate::sync::atomic::Ordering
+    ) -> (Head, *mut PageSharedArena<T>)
+    {
+        let head = Head(self.head.load(order));
+
+        let page = match head.id() {
+            NULL_ID => std::ptr::null_mut(),
+            // The page is removed from the table only once it's removed
+            // from the list. If it was the head when we read it, the
+            // list has changed since: it's seen as empty, the caller
+            // reads it again
+            id => pages.get(id).map(|page| page.as_ptr()).unwrap_or_else(std::ptr::null_mut),
+        };
+
+        (head, page)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        Head(self.head.load(Acquire)).id() == NULL_ID
+    }
+
+    /// Replaces the head with `new`, if it's still `current`
+    pub(crate) fn compare_exchange(&self, current: Head, new: *mut PageSharedArena<T>) -> bool {
+        self.head.compare_exchange(current.0, current.next(new).0, AcqRel, Relaxed).is_ok()
+    }
+
+    /// Replaces the head with `new` and returns the previous first page
+    pub(crate) fn swap(
+        &self,
+        new: *mut PageSharedArena<T>,
+        pages: &PageTable<PageSharedArena<T>>
+    ) -> *mut PageSharedArena<T>
+    {
+        let mut current = Head(self.head.load(Relaxed));
+
+        loop {
+            match self.head.compare_exchange_weak(current.0, current.next(new).0, AcqRel, Acquire) {
+                Ok(_) => break,
+                Err(head) => current = Head(head),
+            }
+        }
+
+        match current.id() {
+            NULL_ID => std::ptr::null_mut(),
+            id => pages.get(id).map(|page| page.as_ptr()).expect("Page of the free list not in the table"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Head, NULL_ID};
+
+    #[test]
+    fn head_version() {
+        let max_version = Head::new(0, u32::MAX).version();
+        let head = Head::new(NULL_ID, max_version);
+        assert_eq!(head.id(), NULL_ID);
+
+        let next = head.next::<usize>(std::ptr::null_mut());
+        assert_eq!(next.id(), NULL_ID);
+        assert_eq!(next.version(), 0);
+        assert_ne!(next, Head::new(NULL_ID, max_version));
+
+        let head = Head::new(NULL_ID - 1, 7);
+        assert_eq!(head.id(), NULL_ID - 1);
+        assert_eq!(head.version(), 7);
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(not(target_has_atomic = "64"))]
+    fn head_id_too_large() {
+        Head::checked_id(NULL_ID + 1);
+    } // grcov_ignore
+}
@@ -0,0 +1,278 @@
+//! Kinds of page of the handles, known at compile time or read from
+//! the blocks
+
+use std::ptr::NonNull;
+
+use crate::block::{Block, PageKind};
+use crate::page::{arena::PageArena, pool::PagePool, shared_arena::PageSharedArena};
+
+mod sealed {
+    use std::ptr::NonNull;
+
+    use crate::block::{Block, PageKind};
+
+    pub trait Sealed<T: ?Sized> {
+        /// Kind of the page of the blocks, `None` when it's read from
+        /// the tag of each block
+        const PAGE_KIND: Option<PageKind>;
+
+        /// Release the block to its page, without dropping the inner value
+        ///
+        /// The counter must be zero
+        fn release_block(block: NonNull<Block<T>>);
+    }
+}
+
+pub(crate) use sealed::Sealed;
+
+/// The kind of arena which allocated the value of a handle
+///
+/// [`ArenaBox`], [`ArenaArc`] and [`ArenaRc`] have a type parameter
+/// of this trait, [`DynamicKind`] by default: the handle comes from
+/// any arena, and its drop reads the kind of the page from the block.
+///
+/// With [`SharedArenaKind`], [`ArenaKind`] or [`PoolKind`], the kind is
+/// known at compile time: the drop doesn't read it and doesn't branch
+/// on it, it directly calls the function releasing the block of that
+/// page. These kinds are implemented for sized values only, their
+/// type is the type of the page. [`coerce!`] converts a handle to a
+/// handle of [`DynamicKind`].  
+/// A handle is converted with `try_into_kind`, which checks its
+/// kind once, and back with `into_dynamic`.
+///
+/// This trait is sealed, it can't be implemented outside this crate.
+///
+/// ## Example
+///
+/// ```
+/// # use shared_arena::{ArenaBox, ArenaKind, Arena, SharedArenaKind};
+/// let arena = Arena::new();
+///
+/// let my_num: ArenaBox<i32, ArenaKind> = ArenaBox::try_into_kind(arena.alloc(1)).unwrap();
+/// assert_eq!(*my_num, 1);
+///
+/// // The value was not allocated by a SharedArena
+/// let my_num = ArenaBox::into_dynamic(my_num);
+/// assert!(ArenaBox::try_into_kind::<SharedArenaKind>(my_num).is_err());
+/// ```
+///
+/// [`ArenaBox`]: ./struct.ArenaBox.html
+/// [`ArenaArc`]: ./struct.ArenaArc.html
+/// [`ArenaRc`]: ./struct.ArenaRc.html
+/// [`DynamicKind`]: ./enum.DynamicKind.html
+/// [`SharedArenaKind`]: ./enum.SharedArenaKind.html
+/// [`ArenaKind`]: ./enum.ArenaKind.html
+/// [`PoolKind`]: ./enum.PoolKind.html
+/// [`coerce!`]: ./macro.coerce.html
+pub trait Kind<T: ?Sized>: Sealed<T> + 'static {}
+
+/// Values allocated by any arena, the kind is read from their block
+///
+/// See [`Kind`](./trait.Kind.html)
+pub enum DynamicKind {}
+
+/// Values allocated by a [`SharedArena`](./struct.SharedArena.html)
+///
+/// See [`Kind`](./trait.Kind.html)
+pub enum SharedArenaKind {}
+
+/// Values allocated by an [`Arena`](./struct.Arena.html)
+///
+/// See [`Kind`](./trait.Kind.html)
+pub enum ArenaKind {}
+
+/// Values allocated by a [`Pool`](./struct.Pool.html)
+///
+/// See [`Kind`](./trait.Kind.html)
+pub enum PoolKind {}
+
+impl<T: ?Sized> Sealed<T> for DynamicKind {
+    const PAGE_KIND: Option<PageKind> = None;
+
+    /// The type of the value might be erased, the block is released
+    /// with the function stored in its page
+    fn release_block(block: NonNull<Block<T>>) {
+        Block::release_block(block)
+    }
+}
+
+impl<T> Sealed<T> for SharedArenaKind {
+    const PAGE_KIND: Option<PageKind> = Some(PageKind::SharedArena);
+
+    #[inline]
+    fn release_block(block: NonNull<Block<T>>) {
+        debug_assert_eq!(unsafe { block.as_ref() }.page.page_kind(), PageKind::SharedArena);
+        PageSharedArena::<T>::release_block(Block::page_ptr(block), block)
+    }
+}
+
+impl<T> Sealed<T> for ArenaKind {
+    const PAGE_KIND: Option<PageKind> = Some(PageKind::Arena);
+
+    #[inline]
+    fn release_block(block: NonNull<Block<T>>) {
+        debug_assert_eq!(unsafe { block.as_ref() }.page.page_kind(), PageKind::Arena);
+        PageArena::<T>::release_block(Block::page_ptr(block), block)
+    }
+}
+
+impl<T> Sealed<T> for PoolKind {
+    const PAGE_KIND: Option<PageKind> = Some(PageKind::Pool);
+
+    #[inline]
+    fn release_block(block: NonNull<Block<T>>) {
+        debug_assert_eq!(unsafe { block.as_ref() }.page.page_kind(), PageKind::Pool);
+        PagePool::<T>::release_block(Block::page_ptr(block), block)
+    }
+}
+
+impl<T: ?Sized> Kind<T> for DynamicKind {}
+impl<T> Kind<T> for SharedArenaKind {}
+impl<T> Kind<T> for ArenaKind {}
+impl<T> Kind<T> for PoolKind {}
+
+/// Returns `true` when a block of a page of kind `kind` can be in a
+/// handle of kind `K`
+pub(crate) fn accepts<T: ?Sized, K: Kind<T>>(kind: PageKind) -> bool {
+    match K::PAGE_KIND {
+        Some(expected) => expected == kind,
+        None => true,
+    }
+}
+
+/// Code that should fail to compile.
+/// compile_fail is supported on doc only
+///
+/// Fails because `Kind` is sealed
+/// ```compile_fail
+/// use shared_arena::{ArenaBox, Kind};
+///
+/// enum MyKind {}
+/// impl<T> Kind<T> for MyKind {}
+/// ```
+///
+/// Fails because a static kind needs a sized value
+/// ```compile_fail
+/// use shared_arena::{ArenaBox, SharedArenaKind};
+///
+/// fn erased(value: ArenaBox<dyn std::fmt::Display, SharedArenaKind>) {}
+/// ```
+#[allow(dead_code)]
+fn kind_fail() {} // grcov_ignore
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::collections::HashSet;
+    use std::fmt::Display;
+
+    use crate::{coerce, Arena, ArenaArc, ArenaBox, ArenaRc, Pool, SharedArena};
+    use super::{ArenaKind, DynamicKind, PoolKind, SharedArenaKind};
+
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn static_kind_drop() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let arena = SharedArena::new();
+        let value = arena.alloc(DropCounter(dropped.clone()));
+        let value: ArenaBox<_, SharedArenaKind> = ArenaBox::try_into_kind(value).ok().unwrap();
+        let arc = arena.alloc_arc(DropCounter(dropped.clone()));
+        let arc: ArenaArc<_, SharedArenaKind> = ArenaArc::try_into_kind(arc).ok().unwrap();
+        let arc2 = arc.clone();
+        assert_eq!(arena.stats(), (2, 61));
+        drop((value, arc, arc2));
+        assert_eq!(arena.stats(), (0, 63));
+
+        let arena = Arena::new();
+        let value: ArenaBox<_, ArenaKind> = ArenaBox::try_into_kind(arena.alloc(DropCounter(dropped.clone()))).ok().unwrap();
+        let rc: ArenaRc<_, ArenaKind> = ArenaRc::try_into_kind(arena.alloc_rc(DropCounter(dropped.clone()))).ok().unwrap();
+        assert_eq!(arena.stats(), (2, 61));
+        drop((value, rc));
+        assert_eq!(arena.stats(), (0, 63));
+
+        let pool = Pool::new();
+        let rc: ArenaRc<_, PoolKind> = ArenaRc::try_into_kind(pool.alloc_rc(DropCounter(dropped.clone()))).ok().unwrap();
+        let boxed = pool.alloc(DropCounter(dropped.clone()));
+        assert_eq!(pool.stats(), (2, 61));
+        drop((rc, boxed));
+        assert_eq!(pool.stats(), (0, 63));
+
+        assert_eq!(dropped.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn static_kind_erased() {
+        let arena = SharedArena::new();
+        let value: ArenaBox<_, SharedArenaKind> = ArenaBox::try_into_kind(arena.alloc(1)).unwrap();
+
+        // The unsized value has the dynamic kind
+        let value: ArenaBox<dyn Display> = coerce!(value => dyn Display);
+        assert_eq!(value.to_string(), "1");
+        assert_eq!(arena.stats(), (1, 62));
+        drop(value);
+        assert_eq!(arena.stats(), (0, 63));
+
+        let arena = Arena::new();
+        let arc: ArenaArc<_, ArenaKind> = ArenaArc::try_into_kind(arena.alloc_arc([1u8, 2, 3])).unwrap();
+        let arc: ArenaArc<[u8]> = coerce!(arc => [u8]);
+        assert_eq!(arc.len(), 3);
+        drop(arc);
+        assert_eq!(arena.stats(), (0, 63));
+
+        let pool = Pool::new();
+        let rc: ArenaRc<_, PoolKind> = ArenaRc::try_into_kind(pool.alloc_rc(String::from("a"))).unwrap();
+        let rc: ArenaRc<dyn Display> = coerce!(rc => dyn Display);
+        assert_eq!(rc.to_string(), "a");
+        drop(rc);
+        assert_eq!(pool.stats(), (0, 63));
+    }
+
+    #[test]
+    fn kind_mismatch() {
+        let arena = Arena::new();
+
+        let value = arena.alloc(1);
+        let value = ArenaBox::try_into_kind::<SharedArenaKind>(value).err().unwrap();
+        let value = ArenaBox::try_into_kind::<PoolKind>(value).err().unwrap();
+        let value = ArenaBox::try_into_kind::<DynamicKind>(value).ok().unwrap();
+        assert_eq!(*value, 1);
+
+        let arc = ArenaArc::try_into_kind::<ArenaKind>(arena.alloc_arc(2)).unwrap();
+        let arc = ArenaArc::try_into_kind::<SharedArenaKind>(arc).err().unwrap();
+        assert_eq!(*ArenaArc::into_dynamic(arc), 2);
+
+        let rc = ArenaRc::try_into_kind::<PoolKind>(Pool::new().alloc_rc(3)).unwrap();
+        let rc = ArenaRc::try_into_kind::<ArenaKind>(rc).err().unwrap();
+        assert_eq!(*rc, 3);
+
+        drop(value);
+        assert_eq!(arena.stats(), (0, 63));
+    }
+
+    #[test]
+    fn static_kind_traits() {
+        let arena = SharedArena::new();
+
+        let set: HashSet<ArenaArc<i32, SharedArenaKind>> = (0..3)
+            .map(|n| ArenaArc::try_into_kind(arena.alloc_arc(n)).unwrap())
+            .collect();
+        assert!(set.contains(&2));
+
+        let a: ArenaBox<_, SharedArenaKind> = ArenaBox::try_into_kind(arena.alloc(1)).unwrap();
+        let b: ArenaBox<_, SharedArenaKind> = ArenaBox::try_into_kind(arena.alloc(2)).unwrap();
+        assert!(a < b);
+        assert_eq!(format!("{} {:?}", a, b), "1 2");
+
+        assert_eq!(std::mem::size_of::<ArenaBox<u8, SharedArenaKind>>(), std::mem::size_of::<usize>());
+        assert_eq!(std::mem::size_of::<ArenaArc<u8, ArenaKind>>(), std::mem::size_of::<ArenaArc<u8>>());
+    }
+}
@@ -9,6 +9,22 @@
 //!
 //! ![](https://raw.githubusercontent.com/sebastiencs/shared-arena/images/table.svg)
 //!
+//! And [`SharedSlab`], built on the same pages, which returns `Copy`
+//! generational keys instead of pointers.
+//!
+//! The `ArenaArc` of a `SharedArena` can be stored as a [`Compact32`],
+//! a 32 bits handle resolved through the arena.
+//!
+//! The handles convert to trait objects with [`coerce!`], such as
+//! `ArenaBox<dyn Trait>`, without moving the value.
+//!
+//! The handles are generic over the [`Kind`] of their arena: with a
+//! kind known at compile time and a sized value, their drop directly
+//! releases the block to its page.
+//!
+//! With the feature `serde`, the handles implement `Serialize` and
+//! `Deserialize`, and [`ArenaSeed`] deserializes values into an arena.
+//!
 //! # Performance
 //!
 //! On my laptop, with Intel i7-10750H, running Clear Linux OS 33840,
@@ -54,6 +70,10 @@
 //!
 //! The difference between `SharedArena`/`Arena` and `Pool` is that
 //! `Pool` does not use atomics.  
+//! Its values can still be sent to other threads with
+//! [`SendablePoolBox`]: their blocks are released in an atomic
+//! bitfield of the page, merged by the `Pool` when it runs out of
+//! free blocks.
 //!
 //! # Safety
 //!
@@ -66,8 +86,15 @@
 //! [`SharedArena`]: ./struct.SharedArena.html
 //! [`Arena`]: ./struct.Arena.html
 //! [`Pool`]: ./struct.Pool.html
+//! [`SendablePoolBox`]: ./struct.SendablePoolBox.html
+//! [`SharedSlab`]: ./struct.SharedSlab.html
+//! [`Compact32`]: ./struct.Compact32.html
+//! [`coerce!`]: ./macro.coerce.html
+//! [`Kind`]: ./trait.Kind.html
+//! [`ArenaSeed`]: ./struct.ArenaSeed.html
 
 mod shared_arena;
+mod shared_slab;
 mod arena;
 mod arena_arc;
 mod arena_rc;
@@ -77,12 +104,39 @@ mod cache_line;
 mod common;
 mod block;
 mod page;
+mod page_table;
+mod free_list;
+mod compact;
+mod typed_alloc;
+mod std_impls;
+mod coerce;
+mod shrink;
+mod reclaim;
+mod sync;
+mod kind;
+#[cfg(all(test, loom))]
+mod loom_models;
+#[cfg(feature = "serde")]
+mod serde_impls;
 
 pub use {
     arena::Arena,
-    self::shared_arena::SharedArena,
+    self::shared_arena::{SharedArena, SharedArenaHandle},
+    shared_slab::{SharedSlab, SlabKey},
     arena_arc::ArenaArc,
+    compact::Compact32,
     arena_box::ArenaBox,
     arena_rc::ArenaRc,
-    pool::{Pool, PoolBox},
+    pool::{Pool, PoolBox, SendablePoolBox},
+    typed_alloc::TypedAlloc,
+    shrink::{AutoShrink, ShrinkReport},
+    kind::{Kind, DynamicKind, SharedArenaKind, ArenaKind, PoolKind},
 };
+
+#[cfg(feature = "serde")]
+pub use serde_impls::ArenaSeed;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::coerce::{CoerceHandle, marker, from_raw_ptr};
+}
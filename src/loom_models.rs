@@ -0,0 +1,275 @@
+//! Models of the concurrent paths of `SharedArena` and of the remote
+//! frees of `Pool`, checked with loom
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom_
+//! ```
+//!
+//! Each model runs with a page or two: loom explores all the
+//! interleavings of the atomic operations of the threads.
+
+use loom::thread;
+
+use crate::{ArenaBox, Pool, SharedArena};
+
+/// Runs `f` with all the interleavings of its threads, with at most
+/// 3 preemptions
+///
+/// Filling a page takes a few hundred atomic operations, more than
+/// the default limit of branches
+fn model<F>(f: F)
+where
+    F: Fn() + Sync + Send + 'static
+{
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound.get_or_insert(3);
+    builder.max_branches = 20_000;
+    builder.check(f);
+}
+
+/// Allocates until a single block is left on the first page
+fn fill_page(arena: &SharedArena<usize>) -> Vec<ArenaBox<usize>> {
+    (0..62).map(|n| arena.alloc(n)).collect()
+}
+
+#[test]
+fn loom_alloc_free() {
+    model(|| {
+        let arena = SharedArena::<usize>::new();
+        let arena2 = arena.handle();
+
+        let thread = thread::spawn(move || {
+            let value = arena2.alloc(1);
+            assert_eq!(*value, 1);
+        });
+
+        let value = arena.alloc(2);
+        assert_eq!(*value, 2);
+        drop(value);
+
+        thread.join().unwrap();
+
+        arena.check_lists();
+        assert_eq!(arena.stats(), (0, 63));
+    });
+}
+
+#[test]
+fn loom_alloc_last_block() {
+    // Both threads want the last block: one removes the full page from
+    // the free list, and allocates a new page
+    model(|| {
+        let arena = SharedArena::<usize>::new();
+        let values = fill_page(&arena);
+        let arena2 = arena.handle();
+
+        let thread = thread::spawn(move || {
+            let value = arena2.alloc(1);
+            assert_eq!(*value, 1);
+            value
+        });
+
+        let value = arena.alloc(2);
+        let other = thread.join().unwrap();
+
+        assert_eq!(*value, 2);
+        assert_eq!(*other, 1);
+
+        drop((value, other, values));
+
+        arena.check_lists();
+        assert_eq!(arena.stats(), (0, 126));
+    });
+}
+
+#[test]
+fn loom_release_on_full_page() {
+    // The page is full and out of the free list, the release puts it in
+    // the pending list while the other thread allocates
+    model(|| {
+        let arena = SharedArena::<usize>::new();
+        let mut values = fill_page(&arena);
+        values.push(arena.alloc(62));
+
+        let released = values.pop().unwrap();
+        let arena2 = arena.handle();
+
+        let thread = thread::spawn(move || {
+            drop(released);
+        });
+
+        let value = arena2.alloc(1);
+        thread.join().unwrap();
+
+        drop((value, values));
+
+        arena.check_lists();
+        assert_eq!(arena.stats().0, 0);
+    });
+}
+
+#[test]
+fn loom_arc_clone_drop() {
+    model(|| {
+        let arena = SharedArena::<usize>::new();
+        let arc = arena.alloc_arc(1);
+        let arc2 = arc.clone();
+
+        let thread = thread::spawn(move || {
+            assert_eq!(*arc2, 1);
+            drop(arc2);
+        });
+
+        drop(arc);
+        thread.join().unwrap();
+
+        assert_eq!(arena.stats(), (0, 63));
+    });
+}
+
+#[test]
+fn loom_alloc_shrink() {
+    // A shrink removes the pages fully free while another thread
+    // allocates and releases
+    model(|| {
+        let arena = SharedArena::<usize>::with_capacity(63 * 2);
+        let arena2 = arena.handle();
+
+        let thread = thread::spawn(move || {
+            let value = arena2.alloc(1);
+            assert_eq!(*value, 1);
+        });
+
+        arena.shrink_to_fit();
+        thread.join().unwrap();
+
+        arena.check_lists();
+        arena.reclaim_now();
+        assert_eq!(arena.stats().0, 0);
+
+        let value = arena.alloc(2);
+        assert_eq!(*value, 2);
+    });
+}
+
+#[test]
+fn loom_try_shrink_release() {
+    // The block released makes the page fully free while try_shrink
+    // examines it
+    model(|| {
+        let arena = SharedArena::<usize>::with_capacity(63 * 2);
+        let value = arena.alloc(1);
+
+        let thread = thread::spawn(move || {
+            drop(value);
+        });
+
+        arena.try_shrink(4);
+        thread.join().unwrap();
+
+        arena.check_lists();
+        arena.reclaim_now();
+        assert_eq!(arena.stats().0, 0);
+    });
+}
+
+#[test]
+fn loom_drop_arena() {
+    // The arena is dropped while a block is released: the last of them
+    // deallocates the page
+    model(|| {
+        let arena = SharedArena::<usize>::new();
+        let value = arena.alloc(1);
+        let arc = arena.alloc_arc(2);
+
+        let thread = thread::spawn(move || {
+            assert_eq!(*arc, 2);
+            drop(arc);
+        });
+
+        drop(arena);
+        assert_eq!(*value, 1);
+        drop(value);
+
+        thread.join().unwrap();
+    });
+}
+
+#[test]
+fn loom_drop_arena_concurrent_release() {
+    // Two blocks of a page are released once the arena is dropped: the
+    // last release defers the page while the other might still read it
+    model(|| {
+        let arena = SharedArena::<usize>::new();
+        let first = arena.alloc_arc(1);
+        let second = arena.alloc_arc(2);
+        let readers = arena.readers();
+        drop(arena);
+
+        let thread = thread::spawn(move || {
+            assert_eq!(*first, 1);
+            drop(first);
+        });
+
+        assert_eq!(*second, 2);
+        drop(second);
+
+        thread.join().unwrap();
+
+        // The page, its last owner, is deallocated
+        assert_eq!(readers.owners(), 0);
+    });
+}
+
+#[test]
+fn loom_pool_remote_drop() {
+    // Blocks are released from other threads while the pool is dropped:
+    // the page is pushed on the stack of remote frees, or the push
+    // finds it closed
+    model(|| {
+        let pool = Pool::<usize>::new();
+        let first = pool.alloc(1).into_sendable();
+        let second = pool.alloc(2).into_sendable();
+        let local = pool.alloc(3);
+
+        let thread = thread::spawn(move || {
+            assert_eq!(*first, 1);
+            drop(first);
+        });
+
+        let thread2 = thread::spawn(move || {
+            assert_eq!(*second, 2);
+            drop(second);
+        });
+
+        drop(local);
+        drop(pool);
+
+        thread.join().unwrap();
+        thread2.join().unwrap();
+    });
+}
+
+#[test]
+fn loom_pool_remote_drain() {
+    // The pool drains the stack of remote frees while a page is pushed
+    model(|| {
+        let pool = Pool::<usize>::new();
+        let first = pool.alloc(1).into_sendable();
+        let second = pool.alloc(2).into_sendable();
+        let local = pool.alloc(3);
+
+        let thread = thread::spawn(move || {
+            drop(first);
+            drop(second);
+        });
+
+        pool.stats();
+
+        thread.join().unwrap();
+
+        assert_eq!(pool.stats(), (1, 62));
+        drop(local);
+        assert_eq!(pool.stats(), (0, 63));
+    });
+}
@@ -1,22 +1,25 @@
 
-use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering::*};
+use crate::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering::*};
 use std::sync::{Arc, Weak};
 use std::cell::Cell;
-use std::ptr::NonNull;
+use std::ptr::{NonNull, addr_of, addr_of_mut};
 use std::alloc::{alloc, dealloc, Layout};
 
 use crate::cache_line::CacheAligned;
-use crate::block::{Block, PageTaggedPtr, PageKind};
+use crate::block::{Block, PageTaggedPtr, PageKind, ReleaseBlockFn};
 use crate::common::BLOCK_PER_PAGE;
+use crate::shrink::ShrinkState;
 
 pub type Bitfield = usize;
 pub type BitfieldAtomic = AtomicUsize;
 
 
+#[repr(C)]
 pub struct PageArena<T> {
     /// Bitfield representing free and non-free blocks.
     /// - 1 = free
     /// - 0 = non-free
+    ///
     /// The most significant bit is dedicated to the arena and is
     /// used to determine when to deallocate the Page.
     /// With this bit reserved, we used BITFIELD_WIDTH - 1 bits for
@@ -24,16 +27,28 @@ pub struct PageArena<T> {
     /// Note that the bit for the arena is inversed:
     /// - 1 = Page is still referenced from an arena
     /// - 0 = The Page isn't referenced in an arena
+    ///
     /// It is inversed so that Bitfield::trailing_zeros doesn't
     /// count that bit
     pub bitfield: Cell<Bitfield>,
+    /// Releases a block of this page, see [`ReleaseBlockFn`].
+    /// Its offset in the page doesn't depend on `T`
+    pub release: ReleaseBlockFn,
     pub bitfield_atomic: CacheAligned<BitfieldAtomic>,
     /// Array of Block
     pub blocks: [Block<T>; BLOCK_PER_PAGE],
     pub arena_pending_list: Weak<AtomicPtr<PageArena<T>>>,
     pub next_free: AtomicPtr<PageArena<T>>,
     pub next: AtomicPtr<PageArena<T>>,
+    /// Previous page in the list of all pages, to remove the page
+    /// without walking the list
+    pub prev: Cell<*mut PageArena<T>>,
     pub in_free_list: AtomicBool,
+    /// The page was fully free at the last step of `AutoShrink`
+    pub idle: Cell<bool>,
+    /// State of the `AutoShrink` policy of the arena, which counts the
+    /// blocks released
+    pub shrink: Option<Arc<ShrinkState>>,
 }
 
 impl<T> std::fmt::Debug for PageArena<T> {
@@ -45,11 +60,25 @@ impl<T> std::fmt::Debug for PageArena<T> {
     }
 }
 
+/// Type erased [`PageArena::release_block`], stored in the page
+///
+/// ## Safety
+///
+/// `page` must point to a `PageArena<T>` and `index` must be the index
+/// of a block in use
+unsafe fn release_erased<T>(page: NonNull<u8>, index: usize) {
+    let page = page.cast::<PageArena<T>>();
+    // Derived from the page pointer, to keep the provenance of the whole page
+    let block = NonNull::new_unchecked(addr_of_mut!((*page.as_ptr()).blocks[index]));
+    PageArena::<T>::release_block(page, block);
+}
+
 fn deallocate_page<T>(page: *mut PageArena<T>) {
     let layout = Layout::new::<PageArena<T>>();
     unsafe {
         std::ptr::drop_in_place(&mut (*page).arena_pending_list as *mut _);
-        dealloc(page as *mut PageArena<T> as *mut u8, layout);
+        std::ptr::drop_in_place(&mut (*page).shrink as *mut _);
+        dealloc(page as *mut u8, layout);
     }
 }
 
@@ -64,6 +93,7 @@ impl<T> PageArena<T> {
 
     fn new(
         arena_pending_list: Weak<AtomicPtr<PageArena<T>>>,
+        shrink: Option<Arc<ShrinkState>>,
         next: *mut PageArena<T>
     ) -> NonNull<PageArena<T>>
     {
@@ -77,21 +107,30 @@ impl<T> PageArena<T> {
 
         // We fill the bitfields with ones
         page.bitfield = Cell::new(!0);
+        page.release = release_erased::<T>;
         // page.bitfield = Cell::new(!0);
-        page.bitfield_atomic.store(0, Relaxed);
-        page.next_free = AtomicPtr::new(next);
-        page.next = AtomicPtr::new(next);
-        page.in_free_list = AtomicBool::new(true);
+        page.idle = Cell::new(false);
+        page.prev = Cell::new(std::ptr::null_mut());
+
+        // The atomics are written in place, with loom they can't be
+        // dropped uninitialized
+        unsafe {
+            addr_of_mut!(page.bitfield_atomic).write(CacheAligned::new(AtomicUsize::new(0)));
+            addr_of_mut!(page.next_free).write(AtomicPtr::new(next));
+            addr_of_mut!(page.next).write(AtomicPtr::new(next));
+            addr_of_mut!(page.in_free_list).write(AtomicBool::new(true));
+        }
 
         let pending_ptr = &mut page.arena_pending_list as *mut Weak<AtomicPtr<PageArena<T>>>;
         unsafe {
             pending_ptr.write(arena_pending_list);
+            addr_of_mut!(page.shrink).write(shrink);
         }
 
         // initialize the blocks
         for (index, block) in page.blocks.iter_mut().enumerate() {
-            block.page = PageTaggedPtr::new(page_copy.as_ptr() as usize, index, PageKind::Arena);
-            block.counter = AtomicUsize::new(0);
+            block.page = PageTaggedPtr::of_block(page_copy.as_ptr(), block, index, PageKind::Arena);
+            unsafe { addr_of_mut!(block.counter).write(AtomicUsize::new(0)) };
         }
 
         page_ptr
@@ -102,22 +141,33 @@ impl<T> PageArena<T> {
     /// Returns the first and last PageArena in the list
     pub fn make_list(
         npages: usize,
-        arena_pending_list: &Arc<AtomicPtr<PageArena<T>>>
+        arena_pending_list: &Arc<AtomicPtr<PageArena<T>>>,
+        shrink: &Option<Arc<ShrinkState>>
     ) -> (NonNull<PageArena<T>>, NonNull<PageArena<T>>)
     {
         let arena_pending_list = Arc::downgrade(arena_pending_list);
 
-        let last = PageArena::<T>::new(arena_pending_list.clone(), std::ptr::null_mut());
+        let last = PageArena::<T>::new(arena_pending_list.clone(), shrink.clone(), std::ptr::null_mut());
         let mut previous = last;
 
         for _ in 0..npages - 1 {
-            let page = PageArena::<T>::new(arena_pending_list.clone(), previous.as_ptr());
+            let page = PageArena::<T>::new(arena_pending_list.clone(), shrink.clone(), previous.as_ptr());
+            unsafe { previous.as_ref() }.prev.set(page.as_ptr());
             previous = page;
         }
 
         (previous, last)
     }
 
+    /// Returns a pointer to the block at `index`
+    ///
+    /// It has the provenance of the whole page, [`Block::page_ptr`]
+    /// derives the page from it
+    pub(crate) fn block_ptr(&self, index: usize) -> NonNull<Block<T>> {
+        let page: *const Self = self;
+        unsafe { NonNull::new_unchecked(addr_of!((*page).blocks[index]) as *mut Block<T>) }
+    }
+
     /// Search for a free [`Block`] in the [`PageArena`] and mark it as non-free
     ///
     /// If there is no free block, it returns None
@@ -139,22 +189,26 @@ impl<T> PageArena<T> {
             self.bitfield.set(self.bitfield.get() & !(1 << index_free));
             // println!("AFTER  {:064b}", self.bitfield.get());
 
-            return Some(NonNull::from(&self.blocks[index_free]))
+            return Some(self.block_ptr(index_free))
         }
     }
 
-    pub(crate) fn drop_block(mut page: NonNull<PageArena<T>>, block: NonNull<Block<T>>) {
+    /// Mark the block as free
+    ///
+    /// The inner value must have been dropped or moved out before
+    pub(crate) fn release_block(mut page: NonNull<PageArena<T>>, block: NonNull<Block<T>>) {
         let page_ptr = page.as_ptr();
         let page = unsafe { page.as_mut() };
         let block = unsafe { block.as_ref() };
 
-        unsafe {
-            // Drop the inner value
-            std::ptr::drop_in_place(block.value.get());
-        }
-
         let bit = 1 << block.page.index_block();
 
+        // Counted before our bit is set: the page might be removed by
+        // the arena once it's fully free
+        if let Some(state) = &page.shrink {
+            state.released();
+        }
+
         // We set our bit to mark the block as free.
         // fetch_add is faster than fetch_or (xadd vs cmpxchg), and
         // we're sure to be the only thread to set this bit.
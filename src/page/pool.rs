@@ -1,19 +1,70 @@
 use std::cell::Cell;
-use std::ptr::NonNull;
+use std::ptr::{NonNull, addr_of, addr_of_mut};
 use std::alloc::{alloc, dealloc, Layout};
 use std::rc::{Rc, Weak};
-use std::sync::atomic::AtomicUsize;
+use crate::sync::atomic::{AtomicPtr, AtomicUsize};
+use crate::sync::atomic::Ordering::*;
+use std::sync::Arc;
 
-use crate::block::{PageTaggedPtr, PageKind, Block};
+use crate::block::{PageTaggedPtr, PageKind, Block, ReleaseBlockFn};
 use crate::common::{BLOCK_PER_PAGE, MASK_ARENA_BIT, Pointer};
+use crate::shrink::ShrinkState;
 
+#[repr(C)]
 pub struct PagePool<T> {
     pub(crate) bitfield: usize,
+    /// Releases a block of this page, see [`ReleaseBlockFn`].
+    /// Its offset in the page doesn't depend on `T`
+    pub(crate) release: ReleaseBlockFn,
     pub(crate) blocks: [Block<T>; BLOCK_PER_PAGE],
     pub(crate) arena_free_list: Weak<Pointer<PagePool<T>>>,
     pub(crate) next_free: Pointer<PagePool<T>>,
     pub(crate) next: Pointer<PagePool<T>>,
+    /// Previous page in the list of all pages, to remove the page
+    /// without walking the list
+    pub(crate) prev: Pointer<PagePool<T>>,
     pub(crate) in_free_list: bool,
+    /// The page was fully free at the last step of `AutoShrink`
+    pub(crate) idle: bool,
+    /// Blocks released from other threads, with a `SendablePoolBox`.
+    /// 1 = free, they are merged in `bitfield` by the pool.
+    /// Once the pool is dropped, all the blocks are released here
+    pub(crate) bitfield_atomic: AtomicUsize,
+    /// Stack of the pages with blocks in `bitfield_atomic`, drained by
+    /// the pool
+    pub(crate) pool_remote_frees: std::sync::Weak<RemoteFrees<T>>,
+    /// Next page in the stack of `pool_remote_frees`
+    pub(crate) next_remote: AtomicPtr<PagePool<T>>,
+    /// State of the `AutoShrink` policy of the pool, which counts the
+    /// blocks released
+    pub(crate) shrink: Option<Arc<ShrinkState>>,
+}
+
+/// Head of the stack of the pages with blocks released from other
+/// threads
+///
+/// A page is pushed by the thread releasing its first block since the
+/// pool merged it. Until the pool pops it, the page keeps the blocks
+/// in `bitfield_atomic`: it's not fully free and it can't be dropped
+/// by a shrink. Once the pool is dropped, the stack is closed.
+pub(crate) type RemoteFrees<T> = AtomicPtr<PagePool<T>>;
+
+/// Head of a closed stack of remote frees, it's never a page
+pub(crate) fn closed_remote_frees<T>() -> *mut PagePool<T> {
+    NonNull::dangling().as_ptr()
+}
+
+/// Type erased [`PagePool::release_block`], stored in the page
+///
+/// ## Safety
+///
+/// `page` must point to a `PagePool<T>` and `index` must be the index
+/// of a block in use
+unsafe fn release_erased<T>(page: NonNull<u8>, index: usize) {
+    let page = page.cast::<PagePool<T>>();
+    // Derived from the page pointer, to keep the provenance of the whole page
+    let block = NonNull::new_unchecked(addr_of_mut!((*page.as_ptr()).blocks[index]));
+    PagePool::<T>::release_block(page, block);
 }
 
 impl<T> PagePool<T> {
@@ -29,12 +80,16 @@ impl<T> PagePool<T> {
         let layout = Layout::new::<PagePool<T>>();
         unsafe {
             std::ptr::drop_in_place(&mut (*page).arena_free_list as *mut _);
-            dealloc(page as *mut PagePool<T> as *mut u8, layout);
+            std::ptr::drop_in_place(&mut (*page).pool_remote_frees as *mut _);
+            std::ptr::drop_in_place(&mut (*page).shrink as *mut _);
+            dealloc(page as *mut u8, layout);
         }
     }
 
     fn new(
         arena_free_list: Weak<Pointer<PagePool<T>>>,
+        pool_remote_frees: std::sync::Weak<RemoteFrees<T>>,
+        shrink: Option<Arc<ShrinkState>>,
         next: *mut PagePool<T>
     ) -> NonNull<PagePool<T>>
     {
@@ -48,25 +103,33 @@ impl<T> PagePool<T> {
 
         // We fill the bitfield with ones
         page.bitfield = !0;
+        page.release = release_erased::<T>;
         // page.next_free.set(next);
         // page.next.set(next);
         page.in_free_list = true;
+        page.idle = false;
 
         let free_ptr = &mut page.arena_free_list as *mut Weak<Pointer<PagePool<T>>>;
+        let remote_ptr = &mut page.pool_remote_frees as *mut std::sync::Weak<RemoteFrees<T>>;
         unsafe {
             free_ptr.write(arena_free_list);
+            remote_ptr.write(pool_remote_frees);
+            (&mut page.bitfield_atomic as *mut AtomicUsize).write(AtomicUsize::new(0));
+            addr_of_mut!(page.next_remote).write(AtomicPtr::new(std::ptr::null_mut()));
             // TODO: forget the old weak
 
             let next_free_ptr = &mut page.next_free as *mut Pointer<_>;
             let next_ptr = &mut page.next as *mut Pointer<_>;
             next_free_ptr.write(Cell::new(next));
             next_ptr.write(Cell::new(next));
+            addr_of_mut!(page.prev).write(Cell::new(std::ptr::null_mut()));
+            addr_of_mut!(page.shrink).write(shrink);
         }
 
         // initialize the blocks
         for (index, block) in page.blocks.iter_mut().enumerate() {
-            block.page = PageTaggedPtr::new(page_copy.as_ptr() as usize, index, PageKind::Pool);
-            block.counter = AtomicUsize::new(0);
+            block.page = PageTaggedPtr::of_block(page_copy.as_ptr(), block, index, PageKind::Pool);
+            unsafe { addr_of_mut!(block.counter).write(AtomicUsize::new(0)) };
         }
 
         page_ptr
@@ -77,26 +140,45 @@ impl<T> PagePool<T> {
     /// Returns the first and last Page in the list
     pub fn make_list(
         npages: usize,
-        arena_free_list: &Rc<Pointer<PagePool<T>>>
+        arena_free_list: &Rc<Pointer<PagePool<T>>>,
+        pool_remote_frees: &Arc<RemoteFrees<T>>,
+        shrink: &Option<Arc<ShrinkState>>
     ) -> (NonNull<PagePool<T>>, NonNull<PagePool<T>>)
     {
         let arena_free_list = Rc::downgrade(arena_free_list);
+        let pool_remote_frees = Arc::downgrade(pool_remote_frees);
 
-        let last = PagePool::<T>::new(arena_free_list.clone(), std::ptr::null_mut());
+        let last = PagePool::<T>::new(
+            arena_free_list.clone(), pool_remote_frees.clone(), shrink.clone(), std::ptr::null_mut()
+        );
         let mut previous = last;
 
         for _ in 0..npages - 1 {
             let previous_ptr = unsafe { previous.as_mut() };
-            let page = PagePool::<T>::new(arena_free_list.clone(), previous_ptr);
+            let page = PagePool::<T>::new(
+                arena_free_list.clone(), pool_remote_frees.clone(), shrink.clone(), previous_ptr
+            );
+            previous_ptr.prev.set(page.as_ptr());
             previous = page;
         }
 
         (previous, last)
     }
 
+    /// Returns a pointer to the block at `index`
+    ///
+    /// It has the provenance of the whole page, [`Block::page_ptr`]
+    /// derives the page from it
+    pub(crate) fn block_ptr(&self, index: usize) -> NonNull<Block<T>> {
+        let page: *const Self = self;
+        unsafe { NonNull::new_unchecked(addr_of!((*page).blocks[index]) as *mut Block<T>) }
+    }
+
     /// Search for a free [`Block`] in the [`Page`] and mark it as non-free
     ///
-    /// If there is no free block, it returns None
+    /// If there is no free block, it returns None.
+    /// The blocks released from other threads are not merged here: the
+    /// page might not be pushed on the stack of remote frees yet
     pub(crate) fn acquire_free_block(&mut self) -> Option<NonNull<Block<T>>> {
         let index_free = self.bitfield.trailing_zeros() as usize;
 
@@ -107,30 +189,43 @@ impl<T> PagePool<T> {
         // We clear the bit of the free block to mark it as non free
         self.bitfield &= !(1 << index_free);
 
-        Some(NonNull::from(&self.blocks[index_free]))
+        Some(self.block_ptr(index_free))
+    }
+
+    /// Moves the blocks released from other threads to `bitfield`
+    ///
+    /// Returns `false` if there was none.
+    /// It must be called by the pool only, on a page popped from the
+    /// stack of remote frees
+    pub(crate) fn merge_remote_frees(&mut self) -> bool {
+        let remote = self.bitfield_atomic.swap(0, AcqRel);
+        self.bitfield |= remote;
+        remote != 0
     }
 
-    pub(crate) fn drop_block(mut page: NonNull<PagePool<T>>, block: NonNull<Block<T>>) {
+    /// Mark the block as free
+    ///
+    /// The inner value must have been dropped or moved out before
+    pub(crate) fn release_block(mut page: NonNull<PagePool<T>>, block: NonNull<Block<T>>) {
         let page_ptr = page.as_ptr();
         let page = unsafe { page.as_mut() };
         let block = unsafe { block.as_ref() };
 
-        unsafe {
-            // Drop the inner value
-            std::ptr::drop_in_place(block.value.get());
+        // The bit dedicated to the Pool is inversed (1 for used, 0 for free)
+        if page.bitfield & MASK_ARENA_BIT == 0 {
+            // The pool has been dropped, the blocks are released in
+            // bitfield_atomic now
+            PagePool::<T>::release_block_remote(NonNull::from(page), NonNull::from(block));
+            return;
+        }
+
+        if let Some(state) = &page.shrink {
+            state.released();
         }
 
         let index_in_page = block.page.index_block();
         page.bitfield |= 1 << index_in_page;
 
-        // The bit dedicated to the Page is inversed (1 for used, 0 for free)
-        if !page.bitfield == MASK_ARENA_BIT {
-            // We were the last block/arena referencing this page
-            // Deallocate it
-            PagePool::<T>::deallocate_page(page_ptr);
-            return;
-        }
-
         if !page.in_free_list {
             page.in_free_list = true;
 
@@ -141,18 +236,118 @@ impl<T> PagePool<T> {
             };
         }
     }
+
+    /// Mark the block as free, from any thread
+    ///
+    /// The block is released in `bitfield_atomic`, the non-atomic
+    /// fields of the page are not touched.
+    /// The inner value must have been dropped or moved out before
+    pub(crate) fn release_block_remote(page: NonNull<PagePool<T>>, block: NonNull<Block<T>>) {
+        let page_ptr = page.as_ptr();
+        let bit = 1 << unsafe { block.as_ref() }.page.index_block();
+
+        let (bitfield_atomic, remote_frees) = unsafe {
+            (
+                &*std::ptr::addr_of!((*page_ptr).bitfield_atomic),
+                (*std::ptr::addr_of!((*page_ptr).pool_remote_frees)).upgrade()
+            )
+        };
+
+        // Counted before our bit is set, the page is not touched after
+        if let Some(state) = unsafe { &*std::ptr::addr_of!((*page_ptr).shrink) } {
+            state.released();
+        }
+
+        // Once our bit is set, the pool might deallocate the page:
+        // it must not be touched after this line, unless we push it
+        let old_bitfield = bitfield_atomic.fetch_add(bit, AcqRel);
+
+        // The bit dedicated to the Pool is set when it's dropped
+        if old_bitfield | bit == !0 {
+            // We were the last block/pool referencing this page
+            // Deallocate it
+            PagePool::<T>::deallocate_page(page_ptr);
+            return;
+        }
+
+        // The first block released since the pool merged the page, or
+        // since it was dropped, pushes it. The other bits are merged
+        // with ours
+        if old_bitfield != 0 {
+            return;
+        }
+
+        if let Some(remote_frees) = remote_frees {
+            PagePool::<T>::push_remote(page_ptr, &remote_frees);
+        }
+    }
+
+    /// Pushes `page` on the stack of remote frees
+    ///
+    /// The page is not merged by the pool until it's popped, it can't
+    /// be deallocated during the push
+    fn push_remote(page: *mut PagePool<T>, remote_frees: &RemoteFrees<T>) {
+        let next_remote = unsafe { &*addr_of!((*page).next_remote) };
+        let mut head = remote_frees.load(Relaxed);
+
+        loop {
+            if head == closed_remote_frees() {
+                // The pool was dropped before the push
+                drop_remote_page(page);
+                return;
+            }
+
+            next_remote.store(head, Relaxed);
+
+            match remote_frees.compare_exchange_weak(head, page, Release, Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current
+            }
+        }
+    }
 }
 
 pub(crate) fn drop_page<T>(page: *mut PagePool<T>) {
-    // We clear the bit dedicated to the arena
-    let new_bitfield = {
+    let bitfield = {
         let page = unsafe { page.as_mut().unwrap() };
+        // We clear the bit dedicated to the pool, the next blocks
+        // will be released in bitfield_atomic
         page.bitfield &= !MASK_ARENA_BIT;
-        page.bitfield
+
+        let mut current = page.bitfield_atomic.load(Relaxed);
+
+        loop {
+            // In bitfield_atomic, the bit of the pool is 1 when it's
+            // dropped. If blocks released from other threads are not
+            // merged, the page is on the stack of remote frees, or is
+            // being pushed: its bit is set once it's taken from the
+            // stack, see drop_remote_page
+            let bitfield = match current {
+                0 => page.bitfield | MASK_ARENA_BIT,
+                _ => current | page.bitfield
+            };
+
+            match page.bitfield_atomic.compare_exchange_weak(current, bitfield, AcqRel, Relaxed) {
+                Ok(_) => break bitfield,
+                Err(bitfield) => current = bitfield
+            }
+        }
     };
 
-    if !new_bitfield == MASK_ARENA_BIT {
-        // No one is referencing this page anymore (neither Arena, ArenaBox or ArenaArc)
+    if bitfield == !0 {
+        // No one is referencing this page anymore (neither Pool, PoolBox or ArenaRc)
+        PagePool::<T>::deallocate_page(page);
+    }
+}
+
+/// Sets the bit of the dropped pool in a page taken from the stack of
+/// remote frees
+///
+/// The page must not be touched after the call
+pub(crate) fn drop_remote_page<T>(page: *mut PagePool<T>) {
+    let bitfield_atomic = unsafe { &*addr_of!((*page).bitfield_atomic) };
+
+    if bitfield_atomic.fetch_or(MASK_ARENA_BIT, AcqRel) | MASK_ARENA_BIT == !0 {
         PagePool::<T>::deallocate_page(page);
     }
 }
@@ -1,19 +1,25 @@
 
-use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering::*};
+use crate::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering::*};
 use std::sync::{Arc, Weak};
 
-use std::ptr::NonNull;
+use std::ptr::{NonNull, addr_of, addr_of_mut};
 use std::alloc::{alloc, dealloc, Layout};
 
 use crate::cache_line::CacheAligned;
 use crate::common::{BLOCK_PER_PAGE, Bitfield, MASK_ARENA_BIT};
-use crate::block::{Block, PageTaggedPtr, PageKind};
+use crate::block::{Block, PageTaggedPtr, PageKind, ReleaseBlockFn};
+use crate::page_table::PageTable;
+use crate::reclaim::{Deferred, ReadGuard, Readers};
+use crate::shared_arena::{SharedArena, SharedArenaInner};
+use crate::shrink::ShrinkState;
 
 
+#[repr(C)]
 pub struct PageSharedArena<T> {
     /// Bitfield representing free and non-free blocks.
     /// - 1 = free
     /// - 0 = non-free
+    ///
     /// The most significant bit is dedicated to the arena and is
     /// used to determine when to deallocate the Page.
     /// With this bit reserved, we used BITFIELD_WIDTH - 1 bits for
@@ -21,15 +27,33 @@ pub struct PageSharedArena<T> {
     /// Note that the bit for the arena is inversed:
     /// - 1 = Page is still referenced from an arena
     /// - 0 = The Page isn't referenced in an arena
+    ///
     /// It is inversed so that Bitfield::trailing_zeros doesn't
     /// count that bit
     pub bitfield: CacheAligned<Bitfield>,
+    /// Releases a block of this page, see [`ReleaseBlockFn`].
+    /// Its offset in the page doesn't depend on `T`
+    pub release: ReleaseBlockFn,
     /// Array of Block
     pub blocks: [Block<T>; BLOCK_PER_PAGE],
-    pub arena_pending_list: Weak<AtomicPtr<PageSharedArena<T>>>,
+    /// The arena owning this page
+    pub arena: Weak<SharedArenaInner<T>>,
+    /// Readers of the pages of the arena, the page is one of its owners
+    pub readers: &'static Readers,
+    /// State of the `AutoShrink` policy of the arena, which counts the
+    /// blocks released
+    pub shrink: Option<Arc<ShrinkState>>,
     pub next_free: AtomicPtr<PageSharedArena<T>>,
     pub next: AtomicPtr<PageSharedArena<T>>,
     pub in_free_list: AtomicBool,
+    /// The page was fully free at the last evaluation of `AutoShrink`
+    pub idle: AtomicBool,
+    /// The page has been removed from the free list by a shrink, and
+    /// will be removed from the full list
+    pub released: AtomicBool,
+    /// Index of the page in the page table of the arena.
+    /// A new id is given to the page when it's reused after a shrink
+    pub id: AtomicU32,
 }
 
 impl<T> std::fmt::Debug for PageSharedArena<T> {
@@ -41,12 +65,39 @@ impl<T> std::fmt::Debug for PageSharedArena<T> {
     }
 }
 
-fn deallocate_page<T>(page: *mut PageSharedArena<T>) {
+/// Type erased [`PageSharedArena::release_block`], stored in the page
+///
+/// ## Safety
+///
+/// `page` must point to a `PageSharedArena<T>` and `index` must be the index
+/// of a block in use
+unsafe fn release_erased<T>(page: NonNull<u8>, index: usize) {
+    let page = page.cast::<PageSharedArena<T>>();
+    // Derived from the page pointer, to keep the provenance of the whole page
+    let block = NonNull::new_unchecked(addr_of_mut!((*page.as_ptr()).blocks[index]));
+    PageSharedArena::<T>::release_block(page, block);
+}
+
+pub(crate) fn deallocate_page<T>(page: *mut PageSharedArena<T>) {
     let layout = Layout::new::<PageSharedArena<T>>();
-    unsafe {
-        std::ptr::drop_in_place(&mut (*page).arena_pending_list as *mut _);
-        dealloc(page as *mut PageSharedArena<T> as *mut u8, layout);
-    }
+    let readers = unsafe {
+        let readers = (*page).readers;
+        std::ptr::drop_in_place(&mut (*page).arena as *mut _);
+        std::ptr::drop_in_place(&mut (*page).shrink as *mut _);
+        dealloc(page as *mut u8, layout);
+        readers
+    };
+    readers.remove_owner();
+}
+
+/// Type erased [`deallocate_page`], for [`Readers::defer`]
+unsafe fn deallocate_erased<T>(page: NonNull<u8>) {
+    deallocate_page(page.cast::<PageSharedArena<T>>().as_ptr());
+}
+
+/// Returns `page`, to deallocate with [`Readers::defer`]
+pub(crate) fn deferred_page<T>(page: NonNull<PageSharedArena<T>>) -> Deferred {
+    Deferred { page: page.cast(), dealloc: deallocate_erased::<T> }
 }
 
 impl<T> PageSharedArena<T> {
@@ -59,7 +110,10 @@ impl<T> PageSharedArena<T> {
     }
 
     fn new(
-        arena_pending_list: Weak<AtomicPtr<PageSharedArena<T>>>,
+        arena: Weak<SharedArenaInner<T>>,
+        readers: &'static Readers,
+        shrink: Option<Arc<ShrinkState>>,
+        pages: &PageTable<PageSharedArena<T>>,
         next: *mut PageSharedArena<T>
     ) -> NonNull<PageSharedArena<T>>
     {
@@ -72,56 +126,77 @@ impl<T> PageSharedArena<T> {
         // Don't invoke any Drop here, the allocated page is uninitialized
 
         // We fill the bitfield with ones
-        page.bitfield.store(!0, Relaxed);
-        page.next_free = AtomicPtr::new(next);
-        page.next = AtomicPtr::new(next);
-        page.in_free_list = AtomicBool::new(true);
+        page.release = release_erased::<T>;
 
-        let pending_ptr = &mut page.arena_pending_list as *mut Weak<AtomicPtr<PageSharedArena<T>>>;
+        // The atomics are written in place, with loom they can't be
+        // dropped uninitialized
         unsafe {
-            pending_ptr.write(arena_pending_list);
+            addr_of_mut!(page.bitfield).write(CacheAligned::new(AtomicUsize::new(!0)));
+            addr_of_mut!(page.next_free).write(AtomicPtr::new(next));
+            addr_of_mut!(page.next).write(AtomicPtr::new(next));
+            addr_of_mut!(page.in_free_list).write(AtomicBool::new(true));
+            addr_of_mut!(page.idle).write(AtomicBool::new(false));
+            addr_of_mut!(page.released).write(AtomicBool::new(false));
         }
 
+        let arena_ptr = &mut page.arena as *mut Weak<SharedArenaInner<T>>;
+        unsafe {
+            arena_ptr.write(arena);
+            addr_of_mut!(page.readers).write(readers);
+            addr_of_mut!(page.shrink).write(shrink);
+        }
+        readers.add_owner();
+
         // initialize the blocks
         for (index, block) in page.blocks.iter_mut().enumerate() {
-            block.page = PageTaggedPtr::new(page_copy.as_ptr() as usize, index, PageKind::SharedArena);
-            block.counter = AtomicUsize::new(0);
+            block.page = PageTaggedPtr::of_block(page_copy.as_ptr(), block, index, PageKind::SharedArena);
+            unsafe { addr_of_mut!(block.counter).write(AtomicUsize::new(0)) };
         }
 
+        unsafe { addr_of_mut!(page.id).write(AtomicU32::new(pages.push(page_copy))) };
+
         page_ptr
     }
 
     /// Make a new list of PageSharedArena
     ///
+    /// The pages are registered in `pages` and owners of `readers`.
     /// Returns the first and last PageSharedArena in the list
     pub fn make_list(
         npages: usize,
-        arena_pending_list: &Arc<AtomicPtr<PageSharedArena<T>>>
+        arena: &Weak<SharedArenaInner<T>>,
+        readers: &'static Readers,
+        shrink: &Option<Arc<ShrinkState>>,
+        pages: &PageTable<PageSharedArena<T>>
     ) -> (NonNull<PageSharedArena<T>>, NonNull<PageSharedArena<T>>)
     {
-        let arena_pending_list = Arc::downgrade(arena_pending_list);
-
-        let last = PageSharedArena::<T>::new(arena_pending_list.clone(), std::ptr::null_mut());
+        let last = PageSharedArena::<T>::new(arena.clone(), readers, shrink.clone(), pages, std::ptr::null_mut());
         let mut previous = last;
 
         for _ in 0..npages - 1 {
-            let page = PageSharedArena::<T>::new(arena_pending_list.clone(), previous.as_ptr());
+            let page = PageSharedArena::<T>::new(arena.clone(), readers, shrink.clone(), pages, previous.as_ptr());
             previous = page;
         }
 
         (previous, last)
     }
 
+    /// Links `pages`, removed by a shrink, in a new list
+    ///
+    /// A thread which read them before their removal might still be
+    /// loading their fields: they are reset with atomic stores.
     pub(crate) fn make_list_from_slice(
         pages: &[NonNull<PageSharedArena<T>>]
     ) -> (NonNull<PageSharedArena<T>>, NonNull<PageSharedArena<T>>) {
-        for (index, page) in pages.iter().map(|p| unsafe { &mut *p.as_ptr() }).enumerate() {
+        for (index, page) in pages.iter().map(|p| unsafe { p.as_ref() }).enumerate() {
             let next = pages.get(index + 1)
                             .map(|p| p.as_ptr())
                             .unwrap_or_else(std::ptr::null_mut);
-            page.next_free = AtomicPtr::new(next);
-            page.next = AtomicPtr::new(next);
-            page.in_free_list = AtomicBool::new(true);
+            page.next_free.store(next, Relaxed);
+            page.next.store(next, Relaxed);
+            page.in_free_list.store(true, Relaxed);
+            page.idle.store(false, Relaxed);
+            page.released.store(false, Relaxed);
         }
         (
             pages.first().copied().unwrap(),
@@ -129,6 +204,15 @@ impl<T> PageSharedArena<T> {
         )
     }
 
+    /// Returns a pointer to the block at `index`
+    ///
+    /// It has the provenance of the whole page, [`Block::page_ptr`]
+    /// derives the page from it
+    pub(crate) fn block_ptr(&self, index: usize) -> NonNull<Block<T>> {
+        let page: *const Self = self;
+        unsafe { NonNull::new_unchecked(addr_of!((*page).blocks[index]) as *mut Block<T>) }
+    }
+
     /// Search for a free [`Block`] in the [`PageSharedArena`] and mark it as non-free
     ///
     /// If there is no free block, it returns None
@@ -138,7 +222,8 @@ impl<T> PageSharedArena<T> {
 
             let index_free = bitfield.trailing_zeros() as usize;
 
-            if index_free == BLOCK_PER_PAGE {
+            // A sealed page has no bit set
+            if index_free >= BLOCK_PER_PAGE {
                 return None;
             }
 
@@ -149,23 +234,39 @@ impl<T> PageSharedArena<T> {
             // We check that the bit was still set in previous_bitfield.
             // If the bit is zero, it means another thread took it.
             if previous_bitfield & bit != 0 {
-                return self.blocks.get(index_free).map(NonNull::from);
+                return Some(self.block_ptr(index_free));
             }
         }
     }
 
-    pub(crate) fn drop_block(mut page: NonNull<PageSharedArena<T>>, block: NonNull<Block<T>>) {
+    /// Marks the page, fully free, as removed from its arena
+    ///
+    /// Once sealed, no block can be acquired on the page. Returns
+    /// `false` if a block of the page is in use.
+    pub(crate) fn seal(&self) -> bool {
+        self.bitfield.compare_exchange(!0, 0, AcqRel, Relaxed).is_ok()
+    }
+
+    /// Reverts `seal`, to put the page back in its arena
+    pub(crate) fn unseal(&self) {
+        let old = self.bitfield.swap(!0, AcqRel);
+        assert_eq!(old, 0, "The page wasn't sealed");
+    }
+
+    /// Mark the block as free
+    ///
+    /// The inner value must have been dropped or moved out before
+    pub(crate) fn release_block(page: NonNull<PageSharedArena<T>>, block: NonNull<Block<T>>) {
         let page_ptr = page.as_ptr();
-        let page = unsafe { page.as_mut() };
+        let page = unsafe { page.as_ref() };
         let block = unsafe { block.as_ref() };
 
-        unsafe {
-            // Drop the inner value
-            std::ptr::drop_in_place(block.value.get());
-        }
-
         let bit = 1 << block.page.index_block();
 
+        // Once our bit is set, the page might be removed from the arena
+        // by a shrink in another thread. The guard keeps it allocated
+        let guard = ReadGuard::new(page.readers);
+
         // We set our bit to mark the block as free.
         // fetch_add is faster than fetch_or (xadd vs cmpxchg), and
         // we're sure to be the only thread to set this bit.
@@ -175,12 +276,24 @@ impl<T> PageSharedArena<T> {
 
         // The bit dedicated to the Arena is inversed (1 for used, 0 for free)
         if !new_bitfield == MASK_ARENA_BIT {
-            // We were the last block/arena referencing this page
-            // Deallocate it
-            deallocate_page(page_ptr);
+            // We were the last block/arena referencing this page.
+            // Other threads releasing a block might still be reading
+            // it: it's deallocated once they are done
+            let readers = page.readers;
+            drop(guard);
+            readers.defer(vec![deferred_page(NonNull::from(page))]);
             return;
         }
 
+        // The arena releases its fully free pages when the free ratio is
+        // above its threshold and our page becomes fully free
+        let shrink = match &page.shrink {
+            Some(state) if state.released() || (new_bitfield == !0 && state.is_above_ratio()) => {
+                page.arena.upgrade()
+            }
+            _ => None
+        };
+
         // Put our page in pending_free_list of the arena, if necessary
         if !page.in_free_list.load(Relaxed) {
             // Another thread might have changed self.in_free_list
@@ -189,7 +302,8 @@ impl<T> PageSharedArena<T> {
             // For self reference:
             // https://gpuopen.com/gdc-presentations/2019/gdc-2019-s2-amd-ryzen-processor-software-optimization.pdf
             if !page.in_free_list.swap(true, Acquire) {
-                if let Some(arena_pending_list) = page.arena_pending_list.upgrade() {
+                if let Some(arena) = page.arena.upgrade() {
+                    let arena_pending_list = &arena.pending_free_list;
                     loop {
                         let current = arena_pending_list.load(Relaxed);
                         page.next_free.store(current, Relaxed);
@@ -203,20 +317,31 @@ impl<T> PageSharedArena<T> {
                 }
             }
         }
+
+        // The shrink deallocates the pages removed only if no guard
+        // is held
+        drop(guard);
+
+        if let Some(inner) = shrink {
+            SharedArena::release_step(inner);
+        }
     }
 }
 
-pub(crate) fn drop_page<T>(page: *mut PageSharedArena<T>) {
-    // We clear the bit dedicated to the arena
+/// Clears the bit dedicated to the arena
+///
+/// Returns `true` when no block of the page is in use: the caller has
+/// to deallocate it with `deallocate_page`, once the threads reading
+/// the page are done (see `reclaim`).  
+/// Otherwise the page is deallocated when its last block is released
+pub(crate) fn drop_page<T>(page: *mut PageSharedArena<T>) -> bool {
     let old_bitfield = {
         let page = unsafe { page.as_ref().unwrap() };
         page.bitfield.fetch_sub(MASK_ARENA_BIT, AcqRel)
     };
 
-    if !old_bitfield == 0 {
-        // No one is referencing this page anymore (neither Arena, ArenaBox or ArenaArc)
-        deallocate_page(page);
-    }
+    // No one is referencing this page anymore (neither Arena, ArenaBox or ArenaArc)
+    !old_bitfield == 0
 }
 
 impl<T> Drop for PageSharedArena<T> {
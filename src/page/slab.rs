@@ -0,0 +1,141 @@
+use crate::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering::*};
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ptr::{NonNull, addr_of_mut};
+use std::alloc::{alloc, dealloc, Layout};
+
+use crate::cache_line::CacheAligned;
+use crate::common::{BLOCK_PER_PAGE, Bitfield};
+
+/// A slot of a [`PageSlab`]
+pub struct Slot<T> {
+    /// Inner value, initialized when the generation is odd
+    pub value: UnsafeCell<MaybeUninit<T>>,
+    /// Generation of the slot.
+    /// It is incremented each time a value is inserted or removed:
+    ///   - odd = the slot contains a value
+    ///   - even = the slot is vacant
+    ///
+    /// A key is valid only if its generation is equal to this one.
+    pub generation: AtomicU32,
+}
+
+pub struct PageSlab<T> {
+    /// Bitfield representing free and non-free slots.
+    /// - 1 = free
+    /// - 0 = non-free
+    ///
+    /// The most significant bit is always set and is not used
+    pub bitfield: CacheAligned<Bitfield>,
+    /// Array of Slot
+    pub slots: [Slot<T>; BLOCK_PER_PAGE],
+    /// Index of the page in the page table of the slab
+    pub id: u32,
+    pub next_free: AtomicPtr<PageSlab<T>>,
+    pub in_free_list: AtomicBool,
+}
+
+impl<T> std::fmt::Debug for PageSlab<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PageSlab")
+         .field("id", &self.id)
+         .field("next_free", &self.next_free.load(Relaxed))
+         .finish()
+    }
+}
+
+impl<T> PageSlab<T> {
+    fn allocate() -> NonNull<PageSlab<T>> {
+        let layout = Layout::new::<PageSlab<T>>();
+        unsafe {
+            let page = alloc(layout) as *const PageSlab<T>;
+            NonNull::from(&*page)
+        }
+    }
+
+    pub(crate) fn new(
+        id: u32,
+        next: *mut PageSlab<T>
+    ) -> NonNull<PageSlab<T>>
+    {
+        let mut page_ptr = Self::allocate();
+        let page = unsafe { page_ptr.as_mut() };
+
+        // Initialize the page
+        // Don't invoke any Drop here, the allocated page is uninitialized
+
+        // We fill the bitfield with ones
+        page.id = id;
+
+        // The atomics are written in place, with loom they can't be
+        // dropped uninitialized
+        unsafe {
+            addr_of_mut!(page.bitfield).write(CacheAligned::new(AtomicUsize::new(!0)));
+            addr_of_mut!(page.next_free).write(AtomicPtr::new(next));
+            addr_of_mut!(page.in_free_list).write(AtomicBool::new(true));
+        }
+
+        // initialize the slots, they are all vacant
+        for slot in page.slots.iter_mut() {
+            unsafe { addr_of_mut!(slot.generation).write(AtomicU32::new(0)) };
+        }
+
+        page_ptr
+    }
+
+    /// Search for a free [`Slot`] in the [`PageSlab`] and mark it as non-free
+    ///
+    /// If there is no free slot, it returns None
+    pub(crate) fn acquire_free_slot(&self) -> Option<usize> {
+        loop {
+            let bitfield = self.bitfield.load(Relaxed);
+
+            let index_free = bitfield.trailing_zeros() as usize;
+
+            if index_free == BLOCK_PER_PAGE {
+                return None;
+            }
+
+            let bit = 1 << index_free;
+
+            let previous_bitfield = self.bitfield.fetch_and(!bit, AcqRel);
+
+            // We check that the bit was still set in previous_bitfield.
+            // If the bit is zero, it means another thread took it.
+            if previous_bitfield & bit != 0 {
+                return Some(index_free);
+            }
+        }
+    }
+
+    /// Mark the slot at `index` as free
+    ///
+    /// The value must have been moved out or dropped before
+    pub(crate) fn release_slot(&self, index: usize) {
+        self.bitfield.fetch_add(1 << index, AcqRel);
+    }
+
+    /// Drop all the values in the page and deallocate it
+    pub(crate) fn drop_page(page: NonNull<PageSlab<T>>) {
+        let page_ref = unsafe { page.as_ref() };
+
+        for slot in &page_ref.slots {
+            if slot.generation.load(Acquire) % 2 == 1 {
+                unsafe {
+                    std::ptr::drop_in_place((*slot.value.get()).as_mut_ptr());
+                }
+            }
+        }
+
+        let layout = Layout::new::<PageSlab<T>>();
+        unsafe {
+            dealloc(page.as_ptr() as *mut u8, layout);
+        }
+    }
+}
+
+impl<T> Drop for PageSlab<T> {
+    fn drop(&mut self) {
+        panic!("PAGE");
+    }
+}
@@ -0,0 +1,204 @@
+use crate::sync::atomic::{AtomicPtr, AtomicUsize, Ordering::*};
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+/// Number of buckets in a `PageTable`.
+/// Bucket `n` holds `2^n` entries, so 32 buckets are enough for any
+/// page id that fits in a `u32`.
+const NBUCKETS: usize = 32;
+
+/// A table of pages, indexed by a page id
+///
+/// Lookups are lock-free and can run while another thread pushes
+/// new pages.
+/// `push` and `remove` must not be called concurrently: the arenas
+/// call them with their writer lock held.
+///
+/// Entries never move once pushed: the table is made of buckets of
+/// increasing size (1, 2, 4, 8, ..) allocated the first time they
+/// are needed.  
+/// The ids of the removed pages are given to the next pages pushed,
+/// so the ids stay below the highest number of pages in the table.
+pub(crate) struct PageTable<P> {
+    buckets: [AtomicPtr<AtomicPtr<P>>; NBUCKETS],
+    len: AtomicUsize,
+    /// Ids of the removed pages
+    free_ids: Mutex<Vec<u32>>,
+}
+
+/// Returns the bucket and the index in that bucket of a page id
+fn locate(id: usize) -> (usize, usize) {
+    let position = id + 1;
+    let bucket = (usize::BITS - 1 - position.leading_zeros()) as usize;
+
+    (bucket, position - (1 << bucket))
+}
+
+fn bucket_len(bucket: usize) -> usize {
+    1 << bucket
+}
+
+impl<P> PageTable<P> {
+    pub(crate) fn new() -> PageTable<P> {
+        PageTable {
+            buckets: std::array::from_fn(|_| AtomicPtr::new(std::ptr::null_mut())),
+            len: AtomicUsize::new(0),
+            free_ids: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Number of ids given by the table
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Acquire)
+    }
+
+    /// Add a page to the table and returns its id
+    ///
+    /// The id of a removed page is reused first.
+    /// Must be called by a single thread at a time
+    pub(crate) fn push(&self, page: NonNull<P>) -> u32 {
+        if let Some(id) = self.free_ids.lock().unwrap().pop() {
+            let entry = self.entry(id).expect("PageTable: invalid free id");
+            entry.store(page.as_ptr(), Release);
+            return id;
+        }
+
+        let id = self.len.load(Relaxed);
+
+        assert!(id < u32::MAX as usize, "Too many pages in the table");
+
+        let (bucket, index) = locate(id);
+
+        let mut entries = self.buckets[bucket].load(Acquire);
+
+        if entries.is_null() {
+            let slice: Box<[AtomicPtr<P>]> = (0..bucket_len(bucket))
+                .map(|_| AtomicPtr::new(std::ptr::null_mut()))
+                .collect();
+
+            entries = Box::into_raw(slice) as *mut AtomicPtr<P>;
+            self.buckets[bucket].store(entries, Release);
+        }
+
+        unsafe { &*entries.add(index) }.store(page.as_ptr(), Release);
+
+        self.len.store(id + 1, Release);
+
+        id as u32
+    }
+
+    fn entry(&self, id: u32) -> Option<&AtomicPtr<P>> {
+        let id = id as usize;
+
+        if id >= self.len() {
+            return None;
+        }
+
+        let (bucket, index) = locate(id);
+        let entries = self.buckets[bucket].load(Acquire);
+
+        unsafe { entries.as_ref().map(|_| &*entries.add(index)) }
+    }
+
+    /// Returns the page registered with `id`, if any
+    pub(crate) fn get(&self, id: u32) -> Option<NonNull<P>> {
+        self.entry(id).and_then(|entry| NonNull::new(entry.load(Acquire)))
+    }
+
+    /// Removes the page registered with `id`
+    ///
+    /// The id is given to a page pushed later: the caller must make
+    /// sure that nothing resolves it to the removed page anymore.
+    /// Must be called by a single thread at a time
+    pub(crate) fn remove(&self, id: u32) {
+        let entry = self.entry(id).expect("PageTable: invalid id");
+        let old = entry.swap(std::ptr::null_mut(), AcqRel);
+        assert!(!old.is_null(), "PageTable: page {} removed twice", id);
+
+        self.free_ids.lock().unwrap().push(id);
+    }
+
+    /// Iterates over all the pages of the table
+    pub(crate) fn iter(&self) -> impl Iterator<Item = NonNull<P>> + '_ {
+        (0..self.len() as u32).filter_map(move |id| self.get(id))
+    }
+}
+
+impl<P> Drop for PageTable<P> {
+    fn drop(&mut self) {
+        for (bucket, entries) in self.buckets.iter().enumerate() {
+            let entries = entries.load(Relaxed);
+
+            if !entries.is_null() {
+                let slice = std::ptr::slice_from_raw_parts_mut(entries, bucket_len(bucket));
+                drop(unsafe { Box::from_raw(slice) });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{locate, PageTable};
+    use std::ptr::NonNull;
+
+    #[test]
+    fn page_table_locate() {
+        assert_eq!(locate(0), (0, 0));
+        assert_eq!(locate(1), (1, 0));
+        assert_eq!(locate(2), (1, 1));
+        assert_eq!(locate(3), (2, 0));
+        assert_eq!(locate(6), (2, 3));
+        assert_eq!(locate(7), (3, 0));
+    }
+
+    #[test]
+    fn page_table_push_get() {
+        let mut values: Vec<usize> = (0..100).collect();
+        let table = PageTable::<usize>::new();
+
+        for value in values.iter_mut() {
+            let id = table.push(NonNull::from(&mut *value));
+            assert_eq!(id as usize, *value);
+        }
+
+        assert_eq!(table.len(), 100);
+        assert!(table.get(100).is_none());
+
+        for id in 0..100 {
+            let page = table.get(id).unwrap();
+            assert_eq!(unsafe { *page.as_ref() }, id as usize);
+        }
+
+        assert_eq!(table.iter().count(), 100);
+
+        table.remove(10);
+        assert!(table.get(10).is_none());
+        assert_eq!(table.iter().count(), 99);
+
+        table.remove(20);
+        table.remove(30);
+        assert_eq!(table.iter().count(), 97);
+
+        // The removed ids are reused, the table doesn't grow
+        assert_eq!(table.push(NonNull::from(&mut values[30])), 30);
+        assert_eq!(table.push(NonNull::from(&mut values[20])), 20);
+        assert_eq!(table.push(NonNull::from(&mut values[10])), 10);
+        assert_eq!(table.get(10).map(|p| unsafe { *p.as_ref() }), Some(10));
+        assert_eq!(table.len(), 100);
+        assert_eq!(table.iter().count(), 100);
+
+        assert_eq!(table.push(NonNull::from(&mut values[0])), 100);
+    }
+
+    #[test]
+    #[should_panic]
+    fn page_table_remove_twice() {
+        let mut value = 1;
+        let table = PageTable::<usize>::new();
+
+        let id = table.push(NonNull::from(&mut value));
+        table.remove(id);
+        table.remove(id);
+    } // grcov_ignore
+}
@@ -1,13 +1,18 @@
-use std::cell::Cell;
+use std::cell::{Cell, OnceCell};
 use std::ptr::NonNull;
 use std::marker::PhantomData;
 use std::rc::Rc;
 use std::mem::MaybeUninit;
+use std::any::Any;
+use std::sync::Arc;
+use crate::sync::atomic::Ordering::{Acquire, Relaxed};
+use crate::sync::WithMut;
 
-use crate::block::Block;
+use crate::block::{Block, PageKind};
 use crate::common::{BLOCK_PER_PAGE, Pointer};
-use crate::page::pool::{PagePool, drop_page};
-use crate::ArenaRc;
+use crate::page::pool::{PagePool, RemoteFrees, closed_remote_frees, drop_page, drop_remote_page};
+use crate::{ArenaRc, AutoShrink, ShrinkReport};
+use crate::shrink::{AUTO_SHRINK_BUDGET, ShrinkAction, ShrinkState};
 
 /// A pointer to `T` in `Pool`
 ///
@@ -31,32 +36,185 @@ use crate::ArenaRc;
 /// [`SharedArena`]: ./struct.SharedArena.html
 /// [`DerefMut`]: https://doc.rust-lang.org/std/ops/trait.DerefMut.html
 ///
-pub struct PoolBox<T> {
+pub struct PoolBox<T: ?Sized> {
     block: NonNull<Block<T>>,
     _marker: PhantomData<*mut ()>
 }
 
-impl<T> PoolBox<T> {
+impl<T: ?Sized> PoolBox<T> {
     fn new(mut block: NonNull<Block<T>>) -> PoolBox<T> {
         // PoolBox is not Send, so we can make the counter non-atomic
-        let counter_mut = unsafe { block.as_mut() }.counter.get_mut();
-
-        // let counter = &mut unsafe { block.as_mut() }.counter;
-        // See ArenaBox<T>::new for why we touch the counter
-        assert!(*counter_mut == 0, "PoolBox: Counter not zero {}", counter_mut);
-        *counter_mut = 1;
+        unsafe { block.as_mut() }.counter.with_value_mut(|counter_mut| {
+            // See ArenaBox<T>::new for why we touch the counter
+            assert!(*counter_mut == 0, "PoolBox: Counter not zero {}", counter_mut);
+            *counter_mut = 1;
+        });
         PoolBox { block, _marker: PhantomData }
     }
+
+    /// Consumes the `PoolBox`, returning a raw pointer to the value
+    ///
+    /// The value is not dropped and the memory is still owned by
+    /// its page: to release it, the pointer has to be converted back
+    /// to a `PoolBox` with [`PoolBox::from_raw`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{PoolBox, Pool};
+    /// let pool = Pool::new();
+    /// let ptr = PoolBox::into_raw(pool.alloc(10));
+    ///
+    /// assert_eq!(unsafe { *ptr }, 10);
+    ///
+    /// let my_num = unsafe { PoolBox::from_raw(ptr) };
+    /// assert_eq!(*my_num, 10);
+    /// ```
+    ///
+    /// [`PoolBox::from_raw`]: #method.from_raw
+    pub fn into_raw(this: PoolBox<T>) -> *mut T {
+        let ptr = Block::value_ptr(this.block);
+        std::mem::forget(this);
+        ptr
+    }
+
+    /// Constructs a `PoolBox` from a raw pointer
+    ///
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`PoolBox::into_raw`], on the
+    /// same thread, and must be converted back only once.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{PoolBox, Pool};
+    /// let pool = Pool::new();
+    /// let ptr = PoolBox::into_raw(pool.alloc(10));
+    ///
+    /// assert_eq!(pool.stats(), (1, 62));
+    /// std::mem::drop(unsafe { PoolBox::from_raw(ptr) });
+    /// assert_eq!(pool.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`PoolBox::into_raw`]: #method.into_raw
+    pub unsafe fn from_raw(ptr: *mut T) -> PoolBox<T> {
+        PoolBox { block: Block::from_value_ptr(ptr), _marker: PhantomData }
+    }
+
+    /// Converts the `PoolBox` to an [`ArenaRc`], without reallocating
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaRc, PoolBox, Pool};
+    /// let pool = Pool::new();
+    /// let my_num: ArenaRc<i32> = PoolBox::into_rc(pool.alloc(10));
+    ///
+    /// assert_eq!(*my_num, *my_num.clone());
+    /// assert_eq!(pool.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`ArenaRc`]: ./struct.ArenaRc.html
+    pub fn into_rc(this: PoolBox<T>) -> ArenaRc<T> {
+        // The counter is 1 for both PoolBox and ArenaRc
+        unsafe { ArenaRc::from_raw(PoolBox::into_raw(this)) }
+    }
+
+    /// Converts the `PoolBox` to a [`SendablePoolBox`], which can be
+    /// sent to other threads, without reallocating
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{Pool, SendablePoolBox};
+    /// let pool = Pool::new();
+    /// let my_vec: SendablePoolBox<Vec<u8>> = pool.alloc(vec![1, 2]).into_sendable();
+    ///
+    /// let sum = std::thread::spawn(move || {
+    ///     my_vec.iter().sum::<u8>()
+    /// }).join().unwrap();
+    ///
+    /// assert_eq!(sum, 3);
+    /// assert_eq!(pool.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`SendablePoolBox`]: ./struct.SendablePoolBox.html
+    pub fn into_sendable(self) -> SendablePoolBox<T>
+    where
+        T: Sized
+    {
+        let block = self.block;
+        std::mem::forget(self);
+        SendablePoolBox { block }
+    }
 }
 
-impl<T> std::ops::Deref for PoolBox<T> {
+impl PoolBox<dyn Any> {
+    /// Attempts to downcast the value to a concrete type
+    ///
+    /// The value is not moved. On failure, the same `PoolBox` is
+    /// returned.
+    ///
+    /// See [`coerce!`](./macro.coerce.html) to make a `PoolBox<dyn Any>`.
+    pub fn downcast<T: Any>(self) -> Result<PoolBox<T>, PoolBox<dyn Any>> {
+        if self.is::<T>() {
+            let ptr = PoolBox::into_raw(self) as *mut T;
+            Ok(unsafe { PoolBox::from_raw(ptr) })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<T: ?Sized + std::fmt::Display> std::fmt::Display for PoolBox<T> {
+    /// ```
+    /// # use shared_arena::{PoolBox, Pool};
+    /// let pool = Pool::new();
+    /// let my_num = pool.alloc(10);
+    ///
+    /// println!("{}", my_num);
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for PoolBox<T> {
+    /// ```
+    /// # use shared_arena::{PoolBox, Pool};
+    /// let pool = Pool::new();
+    /// let my_opt: PoolBox<Option<i32>> = pool.alloc(Some(10));
+    ///
+    /// println!("{:?}", my_opt);
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> std::fmt::Pointer for PoolBox<T> {
+    /// ```
+    /// # use shared_arena::{PoolBox, Pool};
+    /// let pool = Pool::new();
+    /// let my_num = pool.alloc(10);
+    ///
+    /// println!("{:p}", my_num);
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ptr: *const T = &**self;
+        std::fmt::Pointer::fmt(&ptr, f)
+    }
+}
+
+impl<T: ?Sized> std::ops::Deref for PoolBox<T> {
     type Target = T;
     fn deref(&self) -> &T {
         unsafe { &*self.block.as_ref().value.get() }
     }
 }
 
-impl<T> std::ops::DerefMut for PoolBox<T> {
+impl<T: ?Sized> std::ops::DerefMut for PoolBox<T> {
     fn deref_mut(&mut self) -> &mut T {
         unsafe { &mut *self.block.as_ref().value.get() }
     }
@@ -65,17 +223,128 @@ impl<T> std::ops::DerefMut for PoolBox<T> {
 /// Drop the PoolBox<T>
 ///
 /// The value pointed by this PoolBox is also dropped
-impl<T> Drop for PoolBox<T> {
+impl<T: ?Sized> Drop for PoolBox<T> {
     fn drop(&mut self) {
         // PoolBox is not Send, so we can make the counter non-atomic
-        let counter_mut = unsafe { self.block.as_mut() }.counter.get_mut();
-        // let block = unsafe { self.block.as_mut() };
+        unsafe { self.block.as_mut() }.counter.with_value_mut(|counter_mut| {
+            // See ArenaBox<T>::new for why we touch the counter
+            assert!(*counter_mut == 1, "PoolBox: Counter != 1 on drop {}", counter_mut);
+            *counter_mut = 0;
+        });
+
+        // The page is always of a Pool, the value might be unsized
+        Block::drop_block_erased(self.block, PageKind::Pool)
+    }
+}
+
+/// A pointer to `T` in `Pool`, which can be sent to other threads
+///
+/// It's made with [`PoolBox::into_sendable`], so a `Pool` can produce
+/// values consumed by other threads.  
+/// When it's dropped, the block is released in an atomic bitfield of
+/// its page, without touching the `Pool`. The `Pool` reuses the block
+/// when it runs out of free blocks.
+///
+/// It can't be converted back to a `PoolBox`, because the `PoolBox`
+/// might be dropped on another thread than the one of its `Pool`.
+///
+/// ```
+/// # use shared_arena::{Pool, SendablePoolBox};
+/// let pool = Pool::new();
+///
+/// let handles: Vec<_> = (0..4).map(|n| {
+///     let mut value: SendablePoolBox<usize> = pool.alloc(n).into_sendable();
+///     std::thread::spawn(move || {
+///         *value *= 10;
+///         SendablePoolBox::into_inner(value)
+///     })
+/// }).collect();
+///
+/// let results: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+///
+/// assert_eq!(results, vec![0, 10, 20, 30]);
+/// assert_eq!(pool.stats(), (0, 63));
+/// ```
+///
+/// [`PoolBox::into_sendable`]: ./struct.PoolBox.html#method.into_sendable
+pub struct SendablePoolBox<T> {
+    block: NonNull<Block<T>>,
+}
+
+unsafe impl<T: Send> Send for SendablePoolBox<T> {}
+unsafe impl<T: Sync> Sync for SendablePoolBox<T> {}
+
+impl<T> SendablePoolBox<T> {
+    /// Returns the inner value, the block is released to its page
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{Pool, SendablePoolBox};
+    /// let pool = Pool::new();
+    /// let my_num = pool.alloc(10).into_sendable();
+    ///
+    /// assert_eq!(SendablePoolBox::into_inner(my_num), 10);
+    /// ```
+    pub fn into_inner(this: SendablePoolBox<T>) -> T {
+        let block = this.block;
+        std::mem::forget(this);
+
+        let value = unsafe { std::ptr::read(Block::value_ptr(block)) };
+        SendablePoolBox::release(block);
+        value
+    }
+
+    /// Releases the block from any thread, its value must have been
+    /// dropped or moved out
+    fn release(block: NonNull<Block<T>>) {
+        let block_ref = unsafe { block.as_ref() };
 
         // See ArenaBox<T>::new for why we touch the counter
-        assert!(*counter_mut == 1, "PoolBox: Counter != 1 on drop {}", counter_mut);
-        *counter_mut = 0;
+        let counter = block_ref.counter.swap(0, Relaxed);
+        assert!(counter == 1, "SendablePoolBox: Counter != 1 on drop {}", counter);
+
+        let page = Block::page_ptr::<PagePool<T>>(block);
+        PagePool::<T>::release_block_remote(page, block);
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for SendablePoolBox<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for SendablePoolBox<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
 
-        Block::drop_block(self.block)
+impl<T> std::ops::Deref for SendablePoolBox<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.block.as_ref().value.get() }
+    }
+}
+
+impl<T> std::ops::DerefMut for SendablePoolBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.block.as_ref().value.get() }
+    }
+}
+
+/// Drop the SendablePoolBox<T>
+///
+/// The value is dropped on the current thread, and the block is
+/// released atomically to its page
+impl<T> Drop for SendablePoolBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            std::ptr::drop_in_place(Block::value_ptr(self.block));
+        }
+
+        SendablePoolBox::release(self.block);
     }
 }
 
@@ -88,9 +357,17 @@ impl<T> Drop for PoolBox<T> {
 /// [`PoolBox`]: ./struct.PoolBox.html
 ///
 pub struct Pool<T: Sized> {
-    free: Rc<Pointer<PagePool<T>>>,
+    /// Initialized on first use with `new_lazy`
+    free: OnceCell<Rc<Pointer<PagePool<T>>>>,
+    /// Pages with blocks released from other threads
+    remote_frees: OnceCell<Arc<RemoteFrees<T>>>,
     page_list: Pointer<PagePool<T>>,
     npages: Cell<usize>,
+    /// State of the `AutoShrink` policy, shared with the pages
+    shrink: Option<Arc<ShrinkState>>,
+    /// Page of the free list where the next step of `AutoShrink`
+    /// resumes, null to start from the beginning
+    shrink_cursor: Pointer<PagePool<T>>,
     _marker: PhantomData<*mut ()>
 }
 
@@ -127,55 +404,188 @@ impl<T: Sized> Pool<T> {
     /// # arena.alloc(1);
     /// ```
     pub fn with_capacity(cap: usize) -> Pool<T> {
+        Self::with_capacity_and_shrink(cap, AutoShrink::new())
+    }
+
+    /// Constructs a new `Pool` capable of holding at least `cap` elements,
+    /// and shrinking itself with the policy `auto_shrink`
+    ///
+    /// See [`AutoShrink`] for the policy.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{AutoShrink, Pool};
+    /// let arena = Pool::with_capacity_and_shrink(2048, AutoShrink::new().free_ratio(75));
+    /// # arena.alloc(1);
+    /// ```
+    ///
+    /// [`AutoShrink`]: ./struct.AutoShrink.html
+    pub fn with_capacity_and_shrink(cap: usize, auto_shrink: AutoShrink) -> Pool<T> {
         let npages = ((cap.max(1) - 1) / BLOCK_PER_PAGE) + 1;
         let free = Rc::new(Cell::new(std::ptr::null_mut()));
+        let remote_frees = Arc::new(RemoteFrees::new(std::ptr::null_mut()));
 
-        let (mut first, _) = PagePool::make_list(npages, &free);
+        let shrink = ShrinkState::new(auto_shrink, npages);
+        let (mut first, _) = PagePool::make_list(npages, &free, &remote_frees, &shrink);
         let first_ref = unsafe { first.as_mut() };
 
         free.set(first_ref);
 
         Pool {
             npages: Cell::new(npages),
-            free,
+            free: OnceCell::from(free),
+            remote_frees: OnceCell::from(remote_frees),
             page_list: Cell::new(first_ref),
+            shrink,
+            shrink_cursor: Cell::new(std::ptr::null_mut()),
+            _marker: PhantomData
+        }
+    }
+
+    /// Constructs a new `Pool` without allocating
+    ///
+    /// The first page is allocated on the first call to one of the
+    /// alloc* functions.  
+    /// Because it is a `const fn`, it can be used to initialize a
+    /// `thread_local`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::Pool;
+    /// thread_local! {
+    ///     static NUMBERS: Pool<usize> = const { Pool::new_lazy() };
+    /// }
+    ///
+    /// NUMBERS.with(|pool| {
+    ///     assert_eq!(pool.stats(), (0, 0));
+    ///
+    ///     let num = pool.alloc(1);
+    ///     assert_eq!(pool.stats(), (1, 62));
+    /// });
+    /// ```
+    pub const fn new_lazy() -> Pool<T> {
+        Pool {
+            npages: Cell::new(0),
+            free: OnceCell::new(),
+            remote_frees: OnceCell::new(),
+            page_list: Cell::new(std::ptr::null_mut()),
+            shrink: None,
+            shrink_cursor: Cell::new(std::ptr::null_mut()),
             _marker: PhantomData
         }
     }
 
+    fn free(&self) -> &Rc<Pointer<PagePool<T>>> {
+        self.free.get_or_init(|| Rc::new(Cell::new(std::ptr::null_mut())))
+    }
+
+    fn remote_frees(&self) -> &Arc<RemoteFrees<T>> {
+        self.remote_frees.get_or_init(|| Arc::new(RemoteFrees::new(std::ptr::null_mut())))
+    }
+
+    /// Moves the blocks released from other threads to their pages,
+    /// and puts those pages back in the free list
+    ///
+    /// Only the pages of the stack of remote frees are visited
+    fn drain_remote_frees(&self) {
+        let Some(remote_frees) = self.remote_frees.get() else { return };
+
+        // Loaded first to not write its cache line on each call
+        if remote_frees.load(Relaxed).is_null() {
+            return;
+        }
+
+        let mut next = remote_frees.swap(std::ptr::null_mut(), Acquire);
+
+        while let Some(page) = unsafe { next.as_mut() } {
+            // Read before the merge, the page can be pushed again after
+            next = page.next_remote.load(Relaxed);
+
+            if page.merge_remote_frees() && !page.in_free_list {
+                page.in_free_list = true;
+                page.next_free.set(self.free().get());
+                self.free().set(page);
+            }
+        }
+    }
+
     fn alloc_new_page(&self) -> NonNull<PagePool<T>> {
         let len = self.npages.get();
 
-        let to_allocate = len.max(1).min(900_000);
+        let to_allocate = len.clamp(1, 900_000);
 
-        let (first, mut last) = PagePool::make_list(to_allocate, &self.free);
+        let (first, mut last) = PagePool::make_list(to_allocate, self.free(), self.remote_frees(), &self.shrink);
 
+        let last_ptr = last.as_ptr();
         let last_ref = unsafe { last.as_mut() };
-        last_ref.next_free.set(self.free.get());
+        last_ref.next_free.set(self.free().get());
         last_ref.next.set(self.page_list.get());
+        if let Some(current) = unsafe { self.page_list.get().as_ref() } {
+            current.prev.set(last_ptr);
+        }
 
         let first_ptr = first.as_ptr();
-        self.free.set(first_ptr);
+        self.free().set(first_ptr);
         self.page_list.set(first_ptr);
 
         self.npages.set(len + to_allocate);
+        if let Some(state) = &self.shrink {
+            state.add_pages(to_allocate);
+        }
 
         first
     }
 
     fn find_place(&self) -> NonNull<Block<T>> {
+        let block = self.acquire_place();
+
+        if let Some(action) = self.shrink.as_ref().and_then(|state| state.allocated()) {
+            self.auto_shrink_step(action);
+        }
+
+        block
+    }
+
+    /// Takes a step of the `AutoShrink` policy, examining a few pages
+    fn auto_shrink_step(&self, action: ShrinkAction) {
+        let Some(state) = &self.shrink else { return };
+
+        match action {
+            ShrinkAction::All => {
+                let report = self.shrink_budget(AUTO_SHRINK_BUDGET, |_, free| free);
+                state.shrunk(&report);
+            }
+            ShrinkAction::Idle => {
+                self.shrink_budget(AUTO_SHRINK_BUDGET, |page, free| std::mem::replace(&mut page.idle, free));
+            }
+        }
+    }
+
+    fn acquire_place(&self) -> NonNull<Block<T>> {
         loop {
-            while let Some(page) = unsafe { self.free.get().as_mut() } {
+            while let Some(page) = unsafe { self.free().get().as_mut() } {
                 if let Some(block) = page.acquire_free_block() {
                     return block;
                 }
 
                 let next = page.next_free.get();
 
-                self.free.set(next);
+                self.free().set(next);
                 page.in_free_list = false;
+                page.idle = false;
+
+                if std::ptr::eq(self.shrink_cursor.get(), page) {
+                    self.shrink_cursor.set(std::ptr::null_mut());
+                }
+            }
+
+            self.drain_remote_frees();
+
+            if self.free().get().is_null() {
+                self.alloc_new_page();
             }
-            self.alloc_new_page();
         }
     }
 
@@ -211,14 +621,16 @@ impl<T: Sized> Pool<T> {
     /// The difference with [`alloc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When the [`PoolBox`] is dropped, the value is also
     /// dropped. If the value is not initialized correctly, it will
@@ -256,22 +668,60 @@ impl<T: Sized> Pool<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_with<F>(&self, initializer: F) -> PoolBox<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = PoolBox::new(block);
-
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
+        PoolBox::new(Block::init(self.find_place(), initializer))
+    }
 
-        result
+    /// Finds an empty space in the arena and calls the function
+    /// `initializer` with its argument pointing to that space.
+    /// It returns a [`PoolBox`] pointing to the newly initialized value,
+    /// or the error returned by `initializer`.
+    ///
+    /// On error, or if `initializer` panics, the space is given back
+    /// to the arena and the value is not dropped.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of `initializer`.
+    ///
+    /// ## Panics
+    ///
+    /// Like [`alloc_with`], if `initializer` returns a reference
+    /// different from its parameter.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::Pool;
+    /// # use std::io::Read;
+    /// let arena = Pool::<[u8; 4]>::new();
+    ///
+    /// let mut source: &[u8] = b"abcd";
+    /// let data = arena.try_alloc_with(|uninit| {
+    ///     let buffer = uninit.write([0; 4]);
+    ///     source.read_exact(buffer)?;
+    ///     Ok::<_, std::io::Error>(&*buffer)
+    /// }).unwrap();
+    /// assert_eq!(&*data, b"abcd");
+    ///
+    /// // The source is empty, the space is given back to the arena
+    /// let result = arena.try_alloc_with(|uninit| {
+    ///     let buffer = uninit.write([0; 4]);
+    ///     source.read_exact(buffer)?;
+    ///     Ok::<_, std::io::Error>(&*buffer)
+    /// });
+    /// assert!(result.is_err());
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`PoolBox`]: ./struct.PoolBox.html
+    /// [`alloc_with`]: #method.alloc_with
+    pub fn try_alloc_with<F, E>(&self, initializer: F) -> Result<PoolBox<T>, E>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> Result<&T, E>
+    {
+        Block::try_init(self.find_place(), initializer).map(PoolBox::new)
     }
 
     /// Writes a value in the arena, and returns an [`ArenaRc`]
@@ -306,14 +756,16 @@ impl<T: Sized> Pool<T> {
     /// The difference with [`alloc_rc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When all [`ArenaRc`] pointing that value are dropped, the value
     /// is also dropped. If the value is not initialized correctly, it will
@@ -351,22 +803,9 @@ impl<T: Sized> Pool<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_rc_with<F>(&self, initializer: F) -> ArenaRc<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = ArenaRc::new(block);
-
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
-
-        result
+        ArenaRc::new(Block::init(self.find_place(), initializer))
     }
 
     /// Returns a tuple of non-free and free spaces in the arena
@@ -384,6 +823,8 @@ impl<T: Sized> Pool<T> {
     /// assert!(used == 1 && free == 62);
     /// ```
     pub fn stats(&self) -> (usize, usize) {
+        self.drain_remote_frees();
+
         let mut next = self.page_list.get();
         let mut used = 0;
         let mut npages = 0;
@@ -399,8 +840,6 @@ impl<T: Sized> Pool<T> {
             npages += 1;
         }
 
-        assert!(npages == self.npages.get());
-
         let free = (npages * BLOCK_PER_PAGE) - used;
 
         (used, free)
@@ -416,7 +855,7 @@ impl<T: Sized> Pool<T> {
             size += 1;
         }
 
-        let mut next = self.free.get();
+        let mut next = self.free().get();
         let mut free = 0;
         while let Some(next_ref) = unsafe { next.as_mut() } {
             next = next_ref.next_free.get();
@@ -436,13 +875,14 @@ impl<T: Sized> Pool<T> {
     /// This is a slow function and it should not be called in a hot
     /// path.
     ///
-    /// The dedicated memory will be deallocated during this call.
+    /// The dedicated memory will be deallocated during this call.  
+    /// It returns a [`ShrinkReport`] of the pages freed.
     ///
     /// ## Example
     ///
     /// ```
     /// # use shared_arena::Pool;
-    /// let mut arena = Pool::with_capacity(2048);
+    /// let arena = Pool::with_capacity(2048);
     /// let mut values = Vec::new();
     ///
     /// assert_eq!(arena.stats(), (0, 2079));
@@ -457,17 +897,32 @@ impl<T: Sized> Pool<T> {
     /// assert!(used == 80, free == 46);
     ///
     /// ```
-    pub fn shrink_to_fit(&mut self) {
+    ///
+    /// [`ShrinkReport`]: ./struct.ShrinkReport.html
+    pub fn shrink_to_fit(&self) -> ShrinkReport {
+        self.shrink_pages(|_, free| free)
+    }
+
+    /// Drops the fully free pages of the free list for which `release`
+    /// returns `true`
+    ///
+    /// `release` is called on each page of the free list, with whether
+    /// it is fully free.
+    fn shrink_pages(&self, mut release: impl FnMut(&mut PagePool<T>, bool) -> bool) -> ShrinkReport {
+        self.drain_remote_frees();
 
-        let mut current: &Pointer<PagePool<T>> = &self.free;
+        let mut current: &Pointer<PagePool<T>> = self.free();
 
-        let mut to_drop = vec![];
+        let mut to_drop = Vec::new();
 
         while let Some(current_value) = unsafe { current.get().as_mut() } {
+            let free = current_value.bitfield == !0;
+            let release = release(current_value, free) && free;
+
             let next = &current_value.next_free;
             let next_value = next.get();
 
-            if current_value.bitfield == !0 {
+            if release {
                 current.set(next_value);
                 to_drop.push(current_value as *const _ as *mut PagePool<T>);
             } else {
@@ -475,25 +930,84 @@ impl<T: Sized> Pool<T> {
             }
         }
 
-        let mut current: &Pointer<PagePool<T>> = &self.page_list;
+        for page in &to_drop {
+            self.unlink_page(unsafe { &**page });
+            drop_page(*page)
+        }
 
-        // Loop on the full list
-        // We remove the pages from it
-        while let Some(current_value) = unsafe { current.get().as_mut() } {
-            let next = &current_value.next;
-            let next_value = next.get();
+        // The page might have been dropped
+        self.shrink_cursor.set(std::ptr::null_mut());
 
-            if to_drop.contains(&(current_value as *const _ as *mut PagePool<T>)) {
-                current.set(next_value);
+        self.remove_pages(to_drop.len());
+
+        ShrinkReport::pages::<PagePool<T>>(to_drop.len())
+    }
+
+    /// Drops the fully free pages of the free list for which `release`
+    /// returns `true`, examining at most `budget` pages
+    ///
+    /// It resumes after the page where the previous call stopped.
+    fn shrink_budget(&self, budget: usize, mut release: impl FnMut(&mut PagePool<T>, bool) -> bool) -> ShrinkReport {
+        self.drain_remote_frees();
+
+        let mut cursor = self.shrink_cursor.get();
+
+        let mut current: &Pointer<PagePool<T>> = match unsafe { cursor.as_ref() } {
+            Some(page) => &page.next_free,
+            None => self.free(),
+        };
+
+        let mut nfreed = 0;
+
+        for _ in 0..budget {
+            let page = match unsafe { current.get().as_mut() } {
+                Some(page) => page,
+                None => {
+                    // The next call starts from the beginning
+                    cursor = std::ptr::null_mut();
+                    break;
+                }
+            };
+
+            let free = page.bitfield == !0;
+
+            if release(page, free) && free {
+                current.set(page.next_free.get());
+                self.unlink_page(page);
+                drop_page(page);
+                nfreed += 1;
             } else {
-                current = next;
+                cursor = page;
+                current = &page.next_free;
             }
         }
 
-        self.npages.set(self.npages.get() - to_drop.len());
+        self.shrink_cursor.set(cursor);
 
-        for page in to_drop.iter().rev() {
-            drop_page(*page)
+        self.remove_pages(nfreed);
+
+        ShrinkReport::pages::<PagePool<T>>(nfreed)
+    }
+
+    /// Removes `page` from the list of all pages
+    fn unlink_page(&self, page: &PagePool<T>) {
+        let prev = page.prev.get();
+        let next = page.next.get();
+
+        match unsafe { prev.as_ref() } {
+            Some(prev) => prev.next.set(next),
+            None => self.page_list.set(next),
+        }
+
+        if let Some(next) = unsafe { next.as_ref() } {
+            next.prev.set(prev);
+        }
+    }
+
+    fn remove_pages(&self, npages: usize) {
+        self.npages.set(self.npages.get() - npages);
+        if let Some(state) = &self.shrink {
+            state.remove_pages(npages);
         }
     }
 
@@ -536,6 +1050,18 @@ impl<T> Drop for Pool<T> {
             drop_page(next);
             next = next_next;
         }
+
+        // The pages not merged are dropped once taken from the stack,
+        // here or by the threads pushing them after
+        if let Some(remote_frees) = self.remote_frees.get() {
+            let mut next = remote_frees.swap(closed_remote_frees(), Acquire);
+
+            while !next.is_null() {
+                let page = next;
+                next = unsafe { &*std::ptr::addr_of!((*page).next_remote) }.load(Relaxed);
+                drop_remote_page(page);
+            }
+        }
     }
 }
 
@@ -552,6 +1078,8 @@ impl<T> std::fmt::Debug for Pool<T> {
             }
         }
 
+        self.drain_remote_frees();
+
         let npages = self.npages.get();
 
         let mut vec = Vec::with_capacity(npages);
@@ -615,10 +1143,28 @@ mod tests {
     use std::mem::MaybeUninit;
     use std::ptr;
 
+    #[test]
+    fn pool_lazy() {
+        let pool = Pool::<usize>::new_lazy();
+        assert_eq!(pool.stats(), (0, 0));
+
+        pool.shrink_to_fit();
+        assert_eq!(pool.stats(), (0, 0));
+
+        let values: Vec<_> = (0..100).map(|n| pool.alloc(n)).collect();
+        assert_eq!(pool.stats(), (100, 26));
+
+        std::mem::drop(pool);
+        assert!(values.iter().enumerate().all(|(n, v)| **v == n));
+
+        // Never used
+        let _ = Pool::<usize>::new_lazy();
+    }
+
     #[cfg(target_pointer_width = "64") ]
     #[test]
     fn arena_shrink() {
-        let mut arena = Pool::<usize>::with_capacity(1000);
+        let arena = Pool::<usize>::with_capacity(1000);
         assert_eq!(arena.stats(), (0, 1008));
         arena.shrink_to_fit();
         assert_eq!(arena.stats(), (0, 0));
@@ -627,7 +1173,7 @@ mod tests {
     #[cfg(target_pointer_width = "64") ]
     #[test]
     fn arena_shrink2() {
-        let mut arena = Pool::<usize>::with_capacity(1000);
+        let arena = Pool::<usize>::with_capacity(1000);
 
         println!("A");
         let _a = arena.alloc(1);
@@ -666,7 +1212,7 @@ mod tests {
     #[cfg(target_pointer_width = "64") ]
     #[test]
     fn arena_size() {
-        let mut arena = Pool::<usize>::with_capacity(1000);
+        let arena = Pool::<usize>::with_capacity(1000);
 
         assert_eq!(arena.size_lists(), (16, 16));
         let a = arena.alloc(1);
@@ -832,13 +1378,236 @@ mod tests {
         assert_eq!((*a, *b, *c, *d), (101, 102, 103, 104))
     }
 
+    #[test]
+    fn pool_alloc_with_panic() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let pool = Pool::<Vec<u8>>::new();
+        let buffer = vec![1, 2, 3];
+
+        // The initializer can move its captures
+        let value = pool.alloc_with(move |place| place.write(buffer));
+        assert_eq!(*value, [1, 2, 3]);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            pool.alloc_rc_with(|_| panic!("initializer"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(pool.stats(), (1, 62));
+
+        let result = pool.try_alloc_with(|_| Err("error"));
+        assert_eq!(result.err(), Some("error"));
+        assert_eq!(pool.stats(), (1, 62));
+    }
+
+    #[test]
+    fn pool_sendable() {
+        use std::sync::mpsc::channel;
+        use super::SendablePoolBox;
+
+        let pool = Pool::<String>::with_capacity(63);
+        let (sender, receiver) = channel::<SendablePoolBox<String>>();
+
+        let consumer = std::thread::spawn(move || {
+            receiver.into_iter().map(|s| s.len()).sum::<usize>()
+        });
+
+        // Fill the page, it's removed from the free list
+        let values: Vec<_> = (0..63).map(|n| pool.alloc(n.to_string())).collect();
+        assert_eq!(pool.size_lists(), (1, 1));
+
+        for value in values {
+            sender.send(value.into_sendable()).unwrap();
+        }
+        std::mem::drop(sender);
+        assert_eq!(consumer.join().unwrap(), 10 + 53 * 2);
+
+        // The blocks released by the consumer are reused,
+        // without allocating a new page
+        let value = pool.alloc(String::from("a"));
+        assert_eq!(pool.stats(), (1, 62));
+        assert_eq!(pool.size_lists(), (1, 1));
+
+        let value = value.into_sendable();
+        assert_eq!(SendablePoolBox::into_inner(value), "a");
+        assert_eq!(pool.stats(), (0, 63));
+    }
+
+    #[test]
+    fn pool_sendable_outlive_pool() {
+        use std::sync::{Arc, Barrier};
+
+        let pool = Pool::with_capacity(200);
+        let barrier = Arc::new(Barrier::new(2));
+
+        let values: Vec<_> = (0..150).map(|n| pool.alloc(n).into_sendable()).collect();
+        let local = pool.alloc_rc(1);
+
+        let thread = {
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                values.into_iter().map(|v| *v).sum::<usize>()
+            })
+        };
+
+        pool.shrink_to_fit();
+        assert_eq!(pool.stats(), (151, 38));
+
+        // Released after the pool, in bitfield_atomic of the pages
+        std::mem::drop(pool);
+        barrier.wait();
+
+        assert_eq!(thread.join().unwrap(), 149 * 75);
+        assert_eq!(*local, 1);
+    }
+
+    #[test]
+    fn pool_remote_frees_stack() {
+        let pool = Pool::<usize>::with_capacity(63 * 8);
+        let mut values: Vec<_> = (0..63 * 8).map(|n| pool.alloc(n)).collect();
+
+        let mut remote: Vec<_> = values.drain(63 * 3..63 * 3 + 10).map(super::PoolBox::into_sendable).collect();
+        remote.extend(values.drain(..63).map(super::PoolBox::into_sendable));
+        std::thread::spawn(move || drop(remote)).join().unwrap();
+
+        // Each page is pushed once
+        let mut next = pool.remote_frees().load(std::sync::atomic::Ordering::Relaxed);
+        let mut pushed = 0;
+        while let Some(page) = unsafe { next.as_ref() } {
+            next = page.next_remote.load(std::sync::atomic::Ordering::Relaxed);
+            pushed += 1;
+        }
+        assert_eq!(pushed, 2);
+
+        assert_eq!(pool.stats(), (63 * 8 - 73, 73));
+        assert!(pool.remote_frees().load(std::sync::atomic::Ordering::Relaxed).is_null());
+
+        values.extend((0..73).map(|n| pool.alloc(n)));
+        assert_eq!(pool.npages.get(), 8);
+    }
+
+    #[test]
+    fn pool_npages_in_sync() {
+        use crate::AutoShrink;
+
+        let pool = Pool::<usize>::with_capacity_and_shrink(63 * 2, AutoShrink::new().free_ratio(50));
+        let lazy = Pool::<usize>::new_lazy();
+
+        // Only alloc_new_page and shrink_pages modify the page list,
+        // they both update the count
+        fn assert_in_sync(pool: &Pool<usize>) {
+            let mut next = pool.page_list.get();
+            let mut npages = 0;
+            while let Some(page) = unsafe { next.as_ref() } {
+                next = page.next.get();
+                npages += 1;
+            }
+            assert_eq!(npages, pool.npages.get());
+        }
+
+        for pool in &[pool, lazy] {
+            let mut values: Vec<_> = (0..1000).map(|n| pool.alloc(n)).collect();
+            let sendables: Vec<_> = values.drain(500..).map(super::PoolBox::into_sendable).collect();
+            std::thread::spawn(move || drop(sendables)).join().unwrap();
+            assert_in_sync(pool);
+
+            values.retain(|value| **value % 3 == 0);
+            pool.shrink_to_fit();
+            assert_in_sync(pool);
+            values.extend((0..200).map(|n| pool.alloc(n)));
+            values.clear();
+            assert_in_sync(pool);
+
+            assert_eq!(pool.stats().0, 0);
+            pool.shrink_to_fit();
+            assert_in_sync(pool);
+            assert_eq!(pool.stats(), (0, 0));
+            pool.alloc(1);
+            assert_in_sync(pool);
+        }
+    }
+
+    #[test]
+    fn pool_shrink_report() {
+        let pool = Pool::<u64>::with_capacity(63 * 4);
+        let value = pool.alloc(1);
+
+        // Shrinks with a shared reference, while a value is alive
+        let report = pool.shrink_to_fit();
+        assert!(report.completed);
+        assert_eq!(report.pages_freed, 3);
+        assert_eq!(report.bytes_released, 3 * std::mem::size_of::<super::PagePool<u64>>());
+        assert_eq!(pool.stats(), (1, 62));
+
+        // Pages released from other threads are merged before shrinking
+        let values: Vec<_> = (0..200).map(|n| pool.alloc(n).into_sendable()).collect();
+        std::thread::spawn(move || std::mem::drop(values)).join().unwrap();
+
+        let report = pool.shrink_to_fit();
+        assert!(report.pages_freed >= 3);
+        assert_eq!(pool.stats(), (1, 62));
+
+        assert_eq!(pool.shrink_to_fit().pages_freed, 0);
+        assert_eq!(*value, 1);
+    }
+
+    #[test]
+    fn pool_auto_shrink_ratio() {
+        use crate::AutoShrink;
+
+        let pool = Pool::<usize>::with_capacity_and_shrink(63, AutoShrink::new().free_ratio(25));
+        let mut values: Vec<_> = (0..63 * 40).map(|n| pool.alloc(n)).collect();
+        let npages = pool.npages.get();
+
+        // The ratio goes above the threshold, the next allocation
+        // releases a few pages
+        values.truncate(63 * 10);
+        assert_eq!(pool.npages.get(), npages);
+        let value = pool.alloc(1);
+        let freed = npages - pool.npages.get();
+        assert!(freed > 0 && freed <= 16, "freed={}", freed);
+
+        for _ in 0..10 {
+            pool.alloc(2);
+        }
+
+        // Until the ratio is below the threshold
+        let (used, free) = pool.stats();
+        assert_eq!(used, 63 * 10 + 1);
+        assert!(free * 100 <= (used + free) * 25, "free={}", free);
+        assert_eq!(*value, 1);
+    }
+
+    #[test]
+    fn pool_auto_shrink_idle() {
+        use crate::AutoShrink;
+
+        let pool = Pool::<usize>::with_capacity_and_shrink(63 * 8, AutoShrink::new().idle_allocations(10));
+
+        // The pages in use are not released
+        let mut values: Vec<_> = (0..63 * 4).map(|n| pool.alloc(n)).collect();
+        assert_eq!(pool.stats(), (63 * 4, 0));
+
+        // The pages released from other threads are idle too
+        let remote: Vec<_> = values.split_off(63).into_iter().map(|v| v.into_sendable()).collect();
+        std::thread::spawn(move || std::mem::drop(remote)).join().unwrap();
+
+        for n in 0..30 {
+            pool.alloc(n);
+        }
+        assert_eq!(pool.stats(), (63, 63));
+        assert_eq!(pool.size_lists(), (2, 1));
+        assert_eq!(values.len(), 63);
+    }
+
     #[test]
     #[should_panic]
     #[cfg(target_pointer_width = "64") ]
     fn invalid_block() {
         use std::cell::UnsafeCell;
         use std::ptr::NonNull;
-        use std::sync::atomic::AtomicUsize;
+        use crate::sync::atomic::AtomicUsize;
 
         let mut block = super::Block {
             value: UnsafeCell::new(1),
@@ -848,6 +1617,6 @@ mod tests {
             },
         };
 
-        super::Block::drop_block(NonNull::from(&mut block));
+        super::Block::drop_block_as::<crate::kind::DynamicKind>(NonNull::from(&mut block));
     } // grcov_ignore
 }
@@ -0,0 +1,314 @@
+//! Deferred reclamation of the pages of `SharedArena`
+//!
+//! A thread reading a page it doesn't own (a page of the free list,
+//! or the page of a block being released) holds a [`ReadGuard`]
+//! on the [`Readers`] of the arena during the access.
+//! A page removed from the lists is deallocated only once every
+//! thread which was holding a guard at that time has dropped it:
+//! no new reader can reach the page anymore.
+//!
+//! Nothing waits for the readers: the pages are deallocated by the
+//! next check finding no reader, or by the last guard dropped when
+//! they were deferred (see [`Readers::defer`]).
+//!
+//! The count of readers is split in stripes to reduce contention,
+//! each thread always uses the same stripe.
+
+use std::ptr::NonNull;
+
+use crate::cache_line::CacheAligned;
+use crate::sync::atomic::{fence, AtomicPtr, AtomicUsize, Ordering::*};
+
+#[cfg(not(loom))]
+const STRIPES: usize = 16;
+
+/// The atomics of loom are reset on each execution of a model: a
+/// single stripe is used
+#[cfg(loom)]
+const STRIPES: usize = 1;
+
+#[cfg(not(loom))]
+mod stripe {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+
+    /// Stripe of the next thread
+    static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static STRIPE: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    /// Index of the stripe of the current thread, the same for all
+    /// the arenas
+    pub(super) fn index() -> usize {
+        STRIPE.with(|stripe| {
+            *stripe.get().get_or_insert_with(|| {
+                let index = NEXT_STRIPE.fetch_add(1, Relaxed) % super::STRIPES;
+                stripe.set(Some(index));
+                index
+            })
+        })
+    }
+}
+
+#[cfg(loom)]
+mod stripe {
+    pub(super) fn index() -> usize {
+        0
+    }
+}
+
+/// A page to deallocate once its readers are gone
+pub(crate) struct Deferred {
+    pub(crate) page: NonNull<u8>,
+    /// Deallocates `page`, of the type it was erased from
+    pub(crate) dealloc: unsafe fn(NonNull<u8>),
+}
+
+/// Pages deferred by a call to [`Readers::defer`], in a stack
+struct DeferredList {
+    pages: Vec<Deferred>,
+    next: *mut DeferredList,
+}
+
+/// The readers of the pages of an arena
+///
+/// A `Readers` is owned by its arena and by the pages of the arena.
+/// It is never deallocated: a thread might still be dropping its
+/// guard once the last page is gone. It is reused by another arena
+/// once it has no owner.
+pub(crate) struct Readers {
+    /// Number of guards alive, by stripe
+    stripes: [CacheAligned<AtomicUsize>; STRIPES],
+    /// Pages of a dropped arena, deallocated by the first thread
+    /// finding no reader
+    deferred: AtomicPtr<DeferredList>,
+    /// The arena and its pages
+    owners: AtomicUsize,
+}
+
+/// `Readers` without owner, to be reused
+#[cfg(not(loom))]
+static POOL: std::sync::Mutex<Vec<&'static Readers>> = std::sync::Mutex::new(Vec::new());
+
+impl Readers {
+    fn new() -> Readers {
+        Readers {
+            stripes: std::array::from_fn(|_| CacheAligned::new(AtomicUsize::new(0))),
+            deferred: AtomicPtr::new(std::ptr::null_mut()),
+            owners: AtomicUsize::new(1),
+        }
+    }
+
+    /// Returns a `Readers` with a single owner, the caller
+    #[cfg(not(loom))]
+    pub(crate) fn acquire() -> &'static Readers {
+        let reused = POOL.lock().unwrap_or_else(|e| e.into_inner()).pop();
+
+        match reused {
+            Some(readers) => {
+                readers.owners.store(1, Relaxed);
+                readers
+            }
+            None => Box::leak(Box::new(Readers::new()))
+        }
+    }
+
+    /// Returns a `Readers` with a single owner, the caller
+    ///
+    /// The atomics of loom can't outlive the execution of a model,
+    /// they are not reused
+    #[cfg(loom)]
+    pub(crate) fn acquire() -> &'static Readers {
+        Box::leak(Box::new(Readers::new()))
+    }
+
+    /// Adds an owner, the caller must be or be kept alive by one
+    pub(crate) fn add_owner(&self) {
+        self.owners.fetch_add(1, Relaxed);
+    }
+
+    /// Removes an owner, the last one makes it available to another
+    /// arena
+    pub(crate) fn remove_owner(&'static self) {
+        if self.owners.fetch_sub(1, AcqRel) != 1 {
+            return;
+        }
+
+        debug_assert!(self.deferred.load(Relaxed).is_null());
+
+        #[cfg(not(loom))]
+        POOL.lock().unwrap_or_else(|e| e.into_inner()).push(self);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn owners(&self) -> usize {
+        self.owners.load(Relaxed)
+    }
+
+    /// Returns `true` when no guard taken before the call is alive
+    ///
+    /// The pages removed from the lists before the call can then be
+    /// deallocated. A stripe used by a new guard doesn't prevent it:
+    /// each stripe is observed at zero once, not all of them at the
+    /// same time.
+    pub(crate) fn is_quiescent(&self) -> bool {
+        // Paired with the fence in `ReadGuard::new`
+        fence(SeqCst);
+        self.stripes.iter().all(|stripe| stripe.load(Acquire) == 0)
+    }
+
+    /// Deallocates `pages` once no guard taken before the call is
+    /// alive
+    ///
+    /// They are deallocated now if there is no reader, otherwise by
+    /// the first guard dropped finding none. It's called by the arena
+    /// dropped, and by the thread releasing the last block of a page
+    /// once the arena is dropped.
+    pub(crate) fn defer(&self, pages: Vec<Deferred>) {
+        if pages.is_empty() {
+            return;
+        }
+
+        let list = Box::into_raw(Box::new(DeferredList { pages, next: std::ptr::null_mut() }));
+        self.push(list, list);
+
+        self.collect();
+    }
+
+    /// Pushes the lists from `first` to `last` on `deferred`
+    fn push(&self, first: *mut DeferredList, last: *mut DeferredList) {
+        let mut head = self.deferred.load(Relaxed);
+
+        loop {
+            unsafe { (*last).next = head };
+
+            match self.deferred.compare_exchange_weak(head, first, SeqCst, Relaxed) {
+                Ok(_) => return,
+                Err(current) => head = current
+            }
+        }
+    }
+
+    /// Deallocates the deferred pages, if there is no reader
+    fn collect(&self) {
+        loop {
+            let first = self.deferred.swap(std::ptr::null_mut(), SeqCst);
+
+            if first.is_null() {
+                return;
+            }
+
+            // Checked once the pages are taken: the guards of the
+            // threads which could still reach them are counted
+            if self.is_quiescent() {
+                let mut next = first;
+                while !next.is_null() {
+                    let list = unsafe { Box::from_raw(next) };
+                    next = list.next;
+                    for page in &list.pages {
+                        unsafe { (page.dealloc)(page.page) };
+                    }
+                }
+                return;
+            }
+
+            let mut last = first;
+            while let Some(next) = unsafe { (*last).next.as_mut() } {
+                last = next;
+            }
+
+            self.push(first, last);
+
+            // Either the guards dropped meanwhile see the pages, or
+            // they are taken again
+            if !self.is_quiescent() {
+                return;
+            }
+        }
+    }
+}
+
+/// Keeps the pages read by the current thread allocated
+///
+/// It must not be held while waiting on another thread.
+pub(crate) struct ReadGuard {
+    readers: &'static Readers,
+    stripe: usize,
+}
+
+impl ReadGuard {
+    pub(crate) fn new(readers: &'static Readers) -> ReadGuard {
+        let stripe = stripe::index();
+        readers.stripes[stripe].fetch_add(1, Relaxed);
+        // Orders the increment before the loads of the lists, paired
+        // with the fence in `is_quiescent`
+        fence(SeqCst);
+        ReadGuard { readers, stripe }
+    }
+}
+
+impl Drop for ReadGuard {
+    fn drop(&mut self) {
+        self.readers.stripes[self.stripe].fetch_sub(1, Release);
+        // Orders the decrement before the load of the deferred pages,
+        // paired with the fence in `is_quiescent`: either the thread
+        // deferring them sees no reader, or the guard sees its pages
+        fence(SeqCst);
+
+        if !self.readers.deferred.load(Acquire).is_null() {
+            self.readers.collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    use super::{Deferred, ReadGuard, Readers};
+
+    static DEALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn dealloc(page: NonNull<u8>) {
+        drop(Box::from_raw(page.as_ptr()));
+        DEALLOCATED.fetch_add(1, Relaxed);
+    }
+
+    fn page() -> Deferred {
+        let page = NonNull::from(Box::leak(Box::new(0u8)));
+        Deferred { page, dealloc }
+    }
+
+    #[test]
+    fn read_guard() {
+        let readers = Readers::acquire();
+        let other = Readers::acquire();
+
+        let guard = ReadGuard::new(readers);
+        assert!(!readers.is_quiescent());
+        // The readers of another arena don't delay it
+        assert!(other.is_quiescent());
+
+        readers.defer(vec![page(), page()]);
+        assert_eq!(DEALLOCATED.load(Relaxed), 0);
+
+        // The last reader deallocates the pages
+        let guard2 = ReadGuard::new(readers);
+        drop(guard);
+        readers.defer(vec![page()]);
+        assert_eq!(DEALLOCATED.load(Relaxed), 0);
+        drop(guard2);
+        assert_eq!(DEALLOCATED.load(Relaxed), 3);
+        assert!(readers.is_quiescent());
+
+        // Without reader, they are deallocated now
+        readers.defer(vec![page()]);
+        assert_eq!(DEALLOCATED.load(Relaxed), 4);
+
+        readers.remove_owner();
+        other.remove_owner();
+    }
+}
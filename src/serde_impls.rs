@@ -0,0 +1,296 @@
+//! Serde support, with the feature `serde`
+//!
+//! The handles serialize as their inner value.
+//! Deserializing a value with an [`ArenaSeed`] allocates it in the
+//! given arena, and the handles nested in that value are allocated
+//! in the same arena.
+//! A handle can't be deserialized outside of an `ArenaSeed`, it would
+//! need a new arena for each value.
+
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, DeserializeSeed, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+
+use crate::{Arena, ArenaArc, ArenaBox, ArenaRc, Kind, Pool, PoolBox, SharedArena, TypedAlloc};
+
+macro_rules! impl_serialize {
+    ($handle:ident $(, $kind:ident)?) => {
+        impl<T: ?Sized + Serialize $(, $kind: Kind<T>)?> Serialize for $handle<T $(, $kind)?> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                (**self).serialize(serializer)
+            }
+        }
+    };
+}
+
+impl_serialize!(ArenaBox, K);
+impl_serialize!(ArenaArc, K);
+impl_serialize!(ArenaRc, K);
+impl_serialize!(PoolBox);
+
+thread_local! {
+    /// Arenas of the `ArenaSeed` currently deserializing on this thread,
+    /// with the `TypeId` of the arena
+    static SCOPE: RefCell<Vec<(TypeId, *const ())>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Registers an arena in `SCOPE` until it's dropped
+struct Scope;
+
+impl Scope {
+    fn enter<A: 'static>(arena: &A) -> Scope {
+        SCOPE.with(|scope| {
+            scope.borrow_mut().push((TypeId::of::<A>(), arena as *const A as *const ()));
+        });
+        Scope
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        SCOPE.with(|scope| scope.borrow_mut().pop());
+    }
+}
+
+/// Calls `fun` with the innermost arena of type `A` in `SCOPE`, if any
+fn with_arena<A: 'static, R>(fun: impl FnOnce(Option<&A>) -> R) -> R {
+    let arena = SCOPE.with(|scope| {
+        scope.borrow()
+             .iter()
+             .rev()
+             .find(|(id, _)| *id == TypeId::of::<A>())
+             .map(|(_, arena)| *arena)
+    });
+
+    // The arena is borrowed by the ArenaSeed for the whole
+    // deserialization, and removed from SCOPE before it ends
+    fun(arena.map(|arena| unsafe { &*(arena as *const A) }))
+}
+
+/// Error of a handle deserialized without an arena to allocate it
+fn no_arena<E: Error>() -> E {
+    E::custom("no ArenaSeed to allocate the handle, or of a different type")
+}
+
+/// A [`DeserializeSeed`] allocating the deserialized value in an arena
+///
+/// It works with a [`SharedArena`], an [`Arena`] or a [`Pool`], and
+/// returns the same pointer as their method `alloc`.
+///
+/// The handles in the value are allocated in the same arena, when
+/// their type is the type of the arena: deserializing a tree of
+/// `ArenaBox<Node>` with an `ArenaSeed` of `SharedArena<Node>` puts
+/// every node in that arena.
+/// Outside of an `ArenaSeed`, or when the type differs, deserializing
+/// a handle returns an error.
+///
+/// Requires the feature `serde`.
+///
+/// ## Example
+///
+/// ```
+/// use serde::Deserialize;
+/// use serde::de::DeserializeSeed;
+/// use shared_arena::{ArenaBox, ArenaSeed, SharedArena};
+///
+/// #[derive(Deserialize)]
+/// struct Node {
+///     name: String,
+///     children: Vec<ArenaBox<Node>>,
+/// }
+///
+/// let arena = SharedArena::new();
+/// let json = r#"{ "name": "root", "children": [{ "name": "leaf", "children": [] }] }"#;
+///
+/// let mut de = serde_json::Deserializer::from_str(json);
+/// let root: ArenaBox<Node> = ArenaSeed::new(&arena).deserialize(&mut de).unwrap();
+///
+/// assert_eq!(root.children[0].name, "leaf");
+/// assert_eq!(arena.stats(), (2, 61));
+/// ```
+///
+/// [`DeserializeSeed`]: https://docs.rs/serde/1/serde/de/trait.DeserializeSeed.html
+/// [`SharedArena`]: ./struct.SharedArena.html
+/// [`Arena`]: ./struct.Arena.html
+/// [`Pool`]: ./struct.Pool.html
+pub struct ArenaSeed<'a, A, T> {
+    arena: &'a A,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'a, A: TypedAlloc<T>, T> ArenaSeed<'a, A, T> {
+    /// Makes a seed allocating in `arena`
+    pub fn new(arena: &'a A) -> ArenaSeed<'a, A, T> {
+        ArenaSeed { arena, _marker: PhantomData }
+    }
+}
+
+impl<'de, A, T> DeserializeSeed<'de> for ArenaSeed<'_, A, T>
+where
+    A: TypedAlloc<T> + 'static,
+    T: Deserialize<'de>,
+{
+    type Value = A::Box;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<A::Box, D::Error> {
+        let value = {
+            let _scope = Scope::enter(self.arena);
+            T::deserialize(deserializer)?
+        };
+
+        Ok(self.arena.alloc(value))
+    }
+}
+
+impl<'de, T: Deserialize<'de> + 'static> Deserialize<'de> for ArenaBox<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ArenaBox<T>, D::Error> {
+        let value = T::deserialize(deserializer)?;
+
+        with_arena(|shared: Option<&SharedArena<T>>| {
+            with_arena(|arena: Option<&Arena<T>>| match (shared, arena) {
+                (Some(shared), _) => Ok(shared.alloc(value)),
+                (_, Some(arena)) => Ok(arena.alloc(value)),
+                _ => Err(no_arena()),
+            })
+        })
+    }
+}
+
+impl<'de, T: Deserialize<'de> + 'static> Deserialize<'de> for ArenaArc<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ArenaArc<T>, D::Error> {
+        let value = T::deserialize(deserializer)?;
+
+        with_arena(|arena: Option<&SharedArena<T>>| match arena {
+            Some(arena) => Ok(arena.alloc_arc(value)),
+            _ => Err(no_arena()),
+        })
+    }
+}
+
+impl<'de, T: Deserialize<'de> + 'static> Deserialize<'de> for ArenaRc<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ArenaRc<T>, D::Error> {
+        let value = T::deserialize(deserializer)?;
+
+        with_arena(|arena: Option<&Arena<T>>| {
+            with_arena(|pool: Option<&Pool<T>>| match (arena, pool) {
+                (Some(arena), _) => Ok(arena.alloc_rc(value)),
+                (_, Some(pool)) => Ok(pool.alloc_rc(value)),
+                _ => Err(no_arena()),
+            })
+        })
+    }
+}
+
+impl<'de, T: Deserialize<'de> + 'static> Deserialize<'de> for PoolBox<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PoolBox<T>, D::Error> {
+        let value = T::deserialize(deserializer)?;
+
+        with_arena(|arena: Option<&Pool<T>>| match arena {
+            Some(arena) => Ok(arena.alloc(value)),
+            _ => Err(no_arena()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde::de::DeserializeSeed;
+
+    use super::ArenaSeed;
+    use crate::{Arena, ArenaArc, ArenaBox, ArenaRc, Pool, PoolBox, SharedArena};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Node {
+        value: u32,
+        children: Vec<ArenaBox<Node>>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct RcNode {
+        value: u32,
+        next: Option<ArenaRc<RcNode>>,
+    }
+
+    #[test]
+    fn serde_serialize() {
+        let arena = SharedArena::new();
+        let pool = Pool::new();
+
+        assert_eq!(serde_json::to_string(&arena.alloc("a")).unwrap(), "\"a\"");
+        assert_eq!(serde_json::to_string(&arena.alloc_arc("b")).unwrap(), "\"b\"");
+        assert_eq!(serde_json::to_string(&pool.alloc(vec![1, 2])).unwrap(), "[1,2]");
+        assert_eq!(serde_json::to_string(&pool.alloc_rc(vec![])).unwrap(), "[]");
+    }
+
+    #[test]
+    fn serde_tree_in_arena() {
+        let json = r#"{"value":1,"children":[{"value":2,"children":[]},{"value":3,"children":[{"value":4,"children":[]}]}]}"#;
+
+        let arena = SharedArena::new();
+        let mut de = serde_json::Deserializer::from_str(json);
+        let root: ArenaBox<Node> = ArenaSeed::new(&arena).deserialize(&mut de).unwrap();
+
+        assert_eq!(arena.stats(), (4, 59));
+        assert_eq!(root.children[1].children[0].value, 4);
+        assert_eq!(serde_json::to_string(&root).unwrap(), json);
+
+        let arena = Arena::new();
+        let mut de = serde_json::Deserializer::from_str(json);
+        let other: ArenaBox<Node> = ArenaSeed::new(&arena).deserialize(&mut de).unwrap();
+
+        assert_eq!(arena.stats(), (4, 59));
+        assert_eq!(*other, *root);
+
+        std::mem::drop(root);
+        std::mem::drop(other);
+        assert_eq!(arena.stats(), (0, 63));
+    }
+
+    #[test]
+    fn serde_rc_in_pool() {
+        let json = r#"{"value":1,"next":{"value":2,"next":null}}"#;
+
+        let pool = Pool::new();
+        let mut de = serde_json::Deserializer::from_str(json);
+        let first: PoolBox<RcNode> = ArenaSeed::new(&pool).deserialize(&mut de).unwrap();
+
+        assert_eq!(pool.stats(), (2, 61));
+        assert_eq!(first.next.as_ref().unwrap().value, 2);
+    }
+
+    #[test]
+    fn serde_without_seed() {
+        assert!(serde_json::from_str::<ArenaArc<Vec<u8>>>("[1,2,3]").is_err());
+        assert!(serde_json::from_str::<ArenaRc<u8>>("1").is_err());
+        assert!(serde_json::from_str::<PoolBox<u8>>("1").is_err());
+
+        let result = serde_json::from_str::<ArenaBox<Node>>(r#"{"value":1,"children":[]}"#);
+        assert!(result.unwrap_err().to_string().starts_with("no ArenaSeed"));
+
+        // The seed of another type doesn't apply to the handles
+        let arena = SharedArena::new();
+        let mut de = serde_json::Deserializer::from_str("[1,2]");
+        let result: Result<ArenaBox<Vec<ArenaBox<u8>>>, _> = ArenaSeed::new(&arena).deserialize(&mut de);
+        assert!(result.is_err());
+        assert_eq!(arena.stats().0, 0);
+    }
+
+    #[test]
+    fn serde_seed_error() {
+        let arena = SharedArena::new();
+        let mut de = serde_json::Deserializer::from_str(r#"{"value":1,"children":[{"value":"a"}]}"#);
+
+        let result: Result<ArenaBox<Node>, _> = ArenaSeed::new(&arena).deserialize(&mut de);
+        assert!(result.is_err());
+        assert_eq!(arena.stats().0, 0);
+
+        // The scope has been cleared by the error
+        let result = serde_json::from_str::<ArenaBox<Node>>(r#"{"value":1,"children":[]}"#);
+        assert!(result.is_err());
+        assert_eq!(arena.stats().0, 0);
+    }
+}
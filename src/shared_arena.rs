@@ -1,19 +1,31 @@
 
 use std::mem::MaybeUninit;
 use std::ptr::NonNull;
-use std::sync::atomic::Ordering::*;
-use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, AtomicU16};
-use std::sync::Arc;
-
-use crate::common::BLOCK_PER_PAGE;
-use crate::block::Block;
-use crate::page::shared_arena::{PageSharedArena, drop_page};
-use crate::{ArenaArc, ArenaBox, ArenaRc};
+use std::pin::Pin;
+use crate::sync::atomic::Ordering::*;
+use crate::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+use std::sync::{Arc, OnceLock};
+
+use crate::common::{BLOCK_PER_PAGE, WriterGuard};
+use crate::block::{Block, PageKind};
+use crate::page::shared_arena::{PageSharedArena, deallocate_page, deferred_page, drop_page};
+use crate::page_table::PageTable;
+use crate::free_list::FreeList;
+use crate::compact::{Compact32, MAX_PAGE_ID};
+use crate::{ArenaArc, ArenaBox, ArenaRc, AutoShrink, ShrinkReport};
+use crate::shrink::{AUTO_SHRINK_BUDGET, ShrinkAction, ShrinkState};
+use crate::reclaim::{ReadGuard, Readers};
+
+/// Number of times `reclaim_now` checks for the readers of the pages
+const RECLAIM_ATTEMPTS: usize = 64;
 
 /// An arena shareable across threads
 ///
 /// Pointers to the elements in the `SharedArena` are shareable as well.
 ///
+/// To share the arena itself, [`SharedArenaHandle`] is a cloneable
+/// handle to it, without the indirection of an `Arc<SharedArena<T>>`.
+///
 /// ## Example
 ///
 /// ```
@@ -36,60 +48,214 @@ use crate::{ArenaArc, ArenaBox, ArenaRc};
 /// // The value is still valid, even if the arena has been dropped
 /// assert_eq!(*item, 1);
 /// ```
+///
+/// [`SharedArenaHandle`]: ./struct.SharedArenaHandle.html
 pub struct SharedArena<T: Sized> {
-    free_list: AtomicPtr<PageSharedArena<T>>,
-    pending_free_list: Arc<AtomicPtr<PageSharedArena<T>>>,
+    /// Initialized on first use with `new_lazy`
+    inner: OnceLock<Arc<SharedArenaInner<T>>>,
+}
+
+/// State of a `SharedArena`
+///
+/// Its pages keep a weak reference to it, to put themselves back in
+/// `pending_free_list` or to allocate in the same arena.
+pub(crate) struct SharedArenaInner<T> {
+    /// Pages with free blocks, any thread removes its first page
+    /// when it is full
+    free_list: FreeList<T>,
+    /// Pages with free blocks, out of the free list. Pages are only
+    /// pushed, or all taken at once: it is not subject to ABA
+    pub(crate) pending_free_list: AtomicPtr<PageSharedArena<T>>,
     full_list: AtomicPtr<PageSharedArena<T>>,
     npages: AtomicUsize,
     writer: AtomicBool,
     shrinking: AtomicBool,
+    /// Pages removed by a shrink, sealed, to deallocate once no thread
+    /// reads them anymore
     to_free: AtomicPtr<Vec<NonNull<PageSharedArena<T>>>>,
-    to_free_delay: AtomicU16,
+    /// Page of `full_list` where `try_shrink` resumes, null to start
+    /// from the beginning
+    shrink_cursor: AtomicPtr<PageSharedArena<T>>,
+    /// State of the `AutoShrink` policy, shared with the pages
+    shrink: Option<Arc<ShrinkState>>,
+    /// Pages of the arena, indexed by their id.
+    /// Used to resolve `Compact32`
+    pages: PageTable<PageSharedArena<T>>,
+    /// Threads reading the pages, the pages removed are deallocated
+    /// once they are gone
+    pub(crate) readers: &'static Readers,
 }
 
 unsafe impl<T: Sized> Send for SharedArena<T> {}
 unsafe impl<T: Sized> Sync for SharedArena<T> {}
 
-const DELAY_DROP_SHRINK: u16 = 10;
+/// A cloneable handle to a [`SharedArena`]
+///
+/// It derefs to the `SharedArena`. Its clones share the same pages,
+/// without the indirection of an `Arc<SharedArena<T>>`.  
+/// The arena is dropped with its last handle.
+///
+/// It is also returned by [`ArenaBox::arena`] and [`ArenaArc::arena`],
+/// to allocate siblings of a value in the same arena.
+///
+/// ## Example
+///
+/// ```
+/// # use shared_arena::{ArenaBox, SharedArenaHandle};
+/// let arena = SharedArenaHandle::new();
+/// let arena2 = arena.clone();
+///
+/// let value = std::thread::spawn(move || {
+///     arena2.alloc(100)
+/// });
+///
+/// let item = arena.alloc(1);
+/// let sibling = ArenaBox::arena(&item).unwrap().alloc(2);
+/// let value = value.join().unwrap();
+///
+/// assert_eq!(*item + *sibling + *value, 103);
+/// assert_eq!(arena.stats(), (3, 60));
+/// ```
+///
+/// [`SharedArena`]: ./struct.SharedArena.html
+/// [`ArenaBox::arena`]: ./struct.ArenaBox.html#method.arena
+/// [`ArenaArc::arena`]: ./struct.ArenaArc.html#method.arena
+pub struct SharedArenaHandle<T: Sized> {
+    arena: SharedArena<T>,
+}
+
+impl<T: Sized> SharedArenaHandle<T> {
+    /// Constructs a new `SharedArenaHandle` capable of holding at
+    /// least `cap` elements
+    ///
+    /// See [`SharedArena::with_capacity`]
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArenaHandle;
+    /// let arena = SharedArenaHandle::with_capacity(2048);
+    /// # arena.alloc(1);
+    /// ```
+    ///
+    /// [`SharedArena::with_capacity`]: ./struct.SharedArena.html#method.with_capacity
+    pub fn with_capacity(cap: usize) -> SharedArenaHandle<T> {
+        SharedArena::with_capacity(cap).into()
+    }
+
+    /// Constructs a new `SharedArenaHandle` capable of holding
+    /// exactly 63 elements
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArenaHandle;
+    /// let arena = SharedArenaHandle::new();
+    /// # arena.alloc(1);
+    /// ```
+    pub fn new() -> SharedArenaHandle<T> {
+        SharedArena::new().into()
+    }
+}
 
-struct WriterGuard<'a> {
-    writer: &'a AtomicBool
+impl<T: Sized> Clone for SharedArenaHandle<T> {
+    /// Makes a new handle to the same arena
+    ///
+    /// ```
+    /// # use shared_arena::SharedArenaHandle;
+    /// let arena = SharedArenaHandle::new();
+    /// let item = arena.clone().alloc(1);
+    ///
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    fn clone(&self) -> SharedArenaHandle<T> {
+        self.arena.handle()
+    }
 }
 
-impl WriterGuard<'_> {
-    fn new(writer: &AtomicBool) -> Option<WriterGuard> {
-        if !writer.load(Relaxed) && !writer.swap(true, AcqRel) {
-            Some(WriterGuard { writer })
-        } else {
-            None
-        }
+impl<T: Sized> From<SharedArena<T>> for SharedArenaHandle<T> {
+    fn from(arena: SharedArena<T>) -> SharedArenaHandle<T> {
+        SharedArenaHandle { arena }
     }
+}
 
-    fn new_blocking(writer: &AtomicBool) -> WriterGuard {
-        loop {
-            if !writer.swap(true, AcqRel) {
-                return WriterGuard { writer }
+impl<T: Sized> Default for SharedArenaHandle<T> {
+    fn default() -> SharedArenaHandle<T> {
+        SharedArenaHandle::new()
+    }
+}
+
+impl<T: Sized> std::ops::Deref for SharedArenaHandle<T> {
+    type Target = SharedArena<T>;
+
+    fn deref(&self) -> &SharedArena<T> {
+        &self.arena
+    }
+}
+
+impl<T> std::fmt::Debug for SharedArenaHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("SharedArenaHandle")
+         .field(&self.arena)
+         .finish()
+    }
+}
+
+impl<T> SharedArenaInner<T> {
+    fn new(npages: usize, auto_shrink: AutoShrink) -> Arc<SharedArenaInner<T>> {
+        Arc::new_cyclic(|inner| {
+            let pages = PageTable::new();
+            let readers = Readers::acquire();
+            let shrink = ShrinkState::new(auto_shrink, npages);
+            let (first, _) = PageSharedArena::make_list(npages, inner, readers, &shrink, &pages);
+
+            SharedArenaInner {
+                npages: AtomicUsize::new(npages),
+                free_list: FreeList::new(first.as_ptr()),
+                pending_free_list: AtomicPtr::new(std::ptr::null_mut()),
+                full_list: AtomicPtr::new(first.as_ptr()),
+                writer: AtomicBool::new(false),
+                shrinking: AtomicBool::new(false),
+                to_free: AtomicPtr::new(std::ptr::null_mut()),
+                shrink_cursor: AtomicPtr::new(std::ptr::null_mut()),
+                shrink,
+                pages,
+                readers,
             }
-            std::thread::yield_now();
+        })
+    }
+
+    fn add_pages(&self, npages: usize) {
+        self.npages.fetch_add(npages, Relaxed);
+        if let Some(state) = &self.shrink {
+            state.add_pages(npages);
         }
     }
-}
 
-impl Drop for WriterGuard<'_> {
-    fn drop(&mut self) {
-        self.writer.store(false, Release);
+    fn remove_pages(&self, npages: usize) {
+        self.npages.fetch_sub(npages, Relaxed);
+        if let Some(state) = &self.shrink {
+            state.remove_pages(npages);
+        }
     }
 }
 
 impl<T: Sized> SharedArena<T> {
+    fn inner(&self) -> &Arc<SharedArenaInner<T>> {
+        self.inner.get_or_init(|| SharedArenaInner::new(1, AutoShrink::new()))
+    }
+
     fn put_pages_in_lists(
         &self,
         npages: usize,
         first: NonNull<PageSharedArena<T>>,
-        mut last: NonNull<PageSharedArena<T>>
+        last: NonNull<PageSharedArena<T>>
     ) {
+        let inner = self.inner();
         let first_ptr = first.as_ptr();
-        let last_ref = unsafe { last.as_mut() };
+        // The pages might have been removed by a shrink, a thread which
+        // read them before might still load their fields
+        let last_ref = unsafe { last.as_ref() };
 
         // We have to touch self.page_list before self.free because
         // shrink_to_fit might be running at the same time with another
@@ -97,58 +263,149 @@ impl<T: Sized> SharedArena<T> {
         // If we update self.page_list _after_ self.free shrink_to_fit
         // will try to remove pages that are not yet in self.page_list
 
-        let current = self.full_list.load(Relaxed);
-        last_ref.next = AtomicPtr::new(current);
-        let old = self.full_list.swap(first_ptr, AcqRel);
+        let current = inner.full_list.load(Relaxed);
+        last_ref.next.store(current, Relaxed);
+        let old = inner.full_list.swap(first_ptr, AcqRel);
         assert_eq!(current, old);
 
-        let current = self.free_list.load(Relaxed);
-        assert!(current.is_null(), "Arena.free isn't null");
+        assert!(inner.free_list.is_empty(), "Arena.free isn't null");
 
-        let old = self.free_list.swap(first_ptr, AcqRel);
+        let old = inner.free_list.swap(first_ptr, &inner.pages);
         assert!(old.is_null(), "Arena.free2 isn't null");
 
-        self.npages.fetch_add(npages, Relaxed);
+        inner.add_pages(npages);
     }
 
     fn alloc_new_page(&self) {
-        let to_allocate = self.npages
+        let inner = self.inner();
+        let to_allocate = inner.npages
                               .load(Relaxed)
-                              .max(1)
-                              .min(900_000);
+                              .clamp(1, 900_000);
 
-        let (first, last) = PageSharedArena::make_list(to_allocate, &self.pending_free_list);
+        let (first, last) = PageSharedArena::make_list(
+            to_allocate, &Arc::downgrade(inner), inner.readers, &inner.shrink, &inner.pages
+        );
         self.put_pages_in_lists(to_allocate, first, last);
     }
 
+    /// Returns the arena owning `block`, if it has been allocated by
+    /// a `SharedArena` still alive
+    pub(crate) fn handle_of(block: NonNull<Block<T>>) -> Option<SharedArenaHandle<T>> {
+        SharedArena::from_block(block).map(SharedArenaHandle::from)
+    }
+
+    fn from_block(block: NonNull<Block<T>>) -> Option<SharedArena<T>> {
+        let tagged = unsafe { block.as_ref() }.page;
+
+        match tagged.page_kind() {
+            PageKind::SharedArena => {
+                let page = Block::page_ptr::<PageSharedArena<T>>(block);
+                let inner = unsafe { page.as_ref() }.arena.upgrade()?;
+                Some(SharedArena { inner: OnceLock::from(inner) })
+            }
+            _ => None
+        }
+    }
+
+    /// Deallocates the pages removed by a shrink, if no thread can
+    /// read them anymore
+    ///
+    /// The writer lock must be held, and no `ReadGuard`
     fn maybe_free_pages(&self) {
-        if self.to_free_delay.load(Relaxed) < DELAY_DROP_SHRINK {
-            let old = self.to_free_delay.fetch_add(1, AcqRel);
-            if old == DELAY_DROP_SHRINK - 1 {
-                let to_free = self.to_free.swap(std::ptr::null_mut(), AcqRel);
-
-                if let Some(to_free) = unsafe { to_free.as_mut() } {
-                    let to_free = unsafe { Box::from_raw(to_free) };
-                    for page in &*to_free {
-                        drop_page(page.as_ptr());
-                    }
-                }
+        let inner = self.inner();
+
+        // The pages are added to `to_free` with the writer lock held,
+        // they have all been removed from the lists before this check
+        if inner.to_free.load(Relaxed).is_null() || !inner.readers.is_quiescent() {
+            return;
+        }
+
+        let to_free = inner.to_free.swap(std::ptr::null_mut(), AcqRel);
+
+        if let Some(to_free) = unsafe { to_free.as_mut() } {
+            let to_free = unsafe { Box::from_raw(to_free) };
+            for page in &*to_free {
+                deallocate_page(page.as_ptr());
             }
         }
     }
 
     fn find_place(&self) -> NonNull<Block<T>> {
+        let inner = self.inner();
+        let block = self.acquire_place(inner);
+
+        if let Some(action) = inner.shrink.as_ref().and_then(|state| state.allocated()) {
+            self.auto_shrink_step(action);
+        }
+
+        block
+    }
+
+    /// Takes a step of the `AutoShrink` policy, examining a few pages
+    fn auto_shrink_step(&self, action: ShrinkAction) {
+        let Some(state) = &self.inner().shrink else { return };
+
+        match action {
+            ShrinkAction::All => {
+                let report = self.shrink_budget(AUTO_SHRINK_BUDGET, |_, free| free);
+                state.shrunk(&report);
+            }
+            ShrinkAction::Idle => {
+                self.shrink_budget(AUTO_SHRINK_BUDGET, |page, free| page.idle.swap(free, Relaxed));
+            }
+        }
+    }
+
+    /// Takes a step of the `AutoShrink` policy of `inner`, after the
+    /// release of a block
+    pub(crate) fn release_step(inner: Arc<SharedArenaInner<T>>) {
+        SharedArena { inner: OnceLock::from(inner) }.auto_shrink_step(ShrinkAction::All);
+    }
+
+    /// Links the pages of `pending_free_list` before `list`
+    ///
+    /// Returns the first page of the list. The writer lock must be held
+    fn chain_pending_list(&self, list: *mut PageSharedArena<T>) -> *mut PageSharedArena<T> {
+        let pending = self.inner().pending_free_list.swap(std::ptr::null_mut(), AcqRel);
+
+        let mut last = match unsafe { pending.as_ref() } {
+            Some(page) => page,
+            None => return list
+        };
+
+        // The pages in the pending list are not modified by other
+        // threads, their in_free_list is true
+        while let Some(next) = unsafe { last.next_free.load(Acquire).as_ref() } {
+            last = next;
+        }
+
+        last.next_free.store(list, Release);
+        pending
+    }
+
+    fn acquire_place(&self, inner: &SharedArenaInner<T>) -> NonNull<Block<T>> {
         loop {
-            while let Some(page) = unsafe { self.free_list.load(Acquire).as_mut() } {
+            // The pages of the free list might be removed by a shrink
+            // in another thread, the guard keeps them allocated
+            let guard = ReadGuard::new(inner.readers);
+
+            loop {
+                let (head, page) = inner.free_list.load(&inner.pages, Acquire);
+                let page = match unsafe { page.as_ref() } {
+                    Some(page) => page,
+                    None => break
+                };
 
                 if let Some(block) = page.acquire_free_block() {
                     return block;
                 }
 
-                // No free block on the page, we remove it from the free list
+                // No free block on the page, we remove it from the free list.
+                // The versioned head makes it fail if the page has been
+                // removed and put back meanwhile, with another next_free
 
                 let next = page.next_free.load(Acquire);
-                if self.free_list.compare_exchange(page, next, AcqRel, Relaxed).is_ok() {
+                if inner.free_list.compare_exchange(head, next) {
                     // The page might be not full anymore since the call to
                     // acquire_free_block but that's fine because drops of
                     // an ArenaBox/Arc on that page will insert the page on
@@ -156,11 +413,14 @@ impl<T: Sized> SharedArena<T> {
                     // page.in_free_list.store(false, Release);
 
                     page.in_free_list.store(false, Release);
+                    page.idle.store(false, Relaxed);
                 }
             }
 
-            if let Some(_guard) = WriterGuard::new(&self.writer) {
-                if self.free_list.load(Acquire).is_null() {
+            drop(guard);
+
+            if let Some(_guard) = WriterGuard::new(&inner.writer) {
+                if inner.free_list.is_empty() {
                     // A single and only thread run this block at a time.
                     //
                     // 3 ways to get new pages:
@@ -168,17 +428,20 @@ impl<T: Sized> SharedArena<T> {
                     // - reuse pages that were removed with shrink()
                     // - allocate
 
-                    let pending = self.pending_free_list.load(Relaxed);
+                    let pending = inner.pending_free_list.load(Relaxed);
                     if !pending.is_null() {
                         // Move self.pending_free to self.free.
 
-                        let pending = self.pending_free_list.swap(std::ptr::null_mut(), AcqRel);
-                        let old = self.free_list.swap(pending, Release);
+                        let pending = inner.pending_free_list.swap(std::ptr::null_mut(), AcqRel);
+                        let old = inner.free_list.swap(pending, &inner.pages);
                         assert!(old.is_null());
 
                         self.maybe_free_pages();
-                    } else if !self.to_free.load(Relaxed).is_null() {
-                        // Take pages that were removed from shrink()
+                    } else if !inner.to_free.load(Relaxed).is_null()
+                        && inner.readers.is_quiescent()
+                    {
+                        // Take pages that were removed from shrink(), once
+                        // no thread which read them before is reading them
 
                         self.take_pages_to_be_freed();
                     } else {
@@ -191,10 +454,8 @@ impl<T: Sized> SharedArena<T> {
                 continue;
             };
 
-            if self.free_list.load(Relaxed).is_null() {
-                std::thread::yield_now();
-            } else {
-                self.maybe_free_pages();
+            if inner.free_list.is_empty() {
+                crate::sync::yield_now();
             }
 
             // // This block is reached if an another thread is allocating or replacing
@@ -204,16 +465,16 @@ impl<T: Sized> SharedArena<T> {
             // // So instead of looping on self.free (which will stay null until allocation
             // // is done), we check for pages on self.pending_free.
 
-            // let mut next = unsafe { self.pending_free_list.load(Acquire).as_mut() };
+            // let mut next = unsafe { inner.pending_free_list.load(Acquire).as_mut() };
 
             // while let Some(page) = next {
-            //     if self.shrinking.load(Acquire) {
+            //     if inner.shrinking.load(Acquire) {
             //         break;
             //     }
             //     if let Some(block) = page.acquire_free_block() {
             //         return block;
             //     }
-            //     if self.shrinking.load(Acquire) {
+            //     if inner.shrinking.load(Acquire) {
             //         break;
             //     }
             //     next = unsafe { page.next_free.load(Acquire).as_mut() };
@@ -222,25 +483,30 @@ impl<T: Sized> SharedArena<T> {
     }
 
     fn take_pages_to_be_freed(&self) {
+        let inner = self.inner();
         if let Some(to_free) = unsafe {
-            self.to_free.swap(std::ptr::null_mut(), AcqRel).as_mut()
+            inner.to_free.swap(std::ptr::null_mut(), AcqRel).as_mut()
         } {
             let mut to_free = unsafe { Box::from_raw(to_free) };
 
-            let npages = self.npages.load(Relaxed).max(1);
+            let npages = inner.npages.load(Relaxed).max(1);
             let truncate_at = to_free.len().saturating_sub(npages);
             let to_reinsert = &to_free[truncate_at..];
 
-            let (first, last) = PageSharedArena::make_list_from_slice(&to_reinsert);
+            for page in to_reinsert {
+                let page_ref = unsafe { page.as_ref() };
+                page_ref.unseal();
+                // Its previous id might have been given to another page
+                page_ref.id.store(inner.pages.push(*page), Relaxed);
+            }
+
+            let (first, last) = PageSharedArena::make_list_from_slice(to_reinsert);
             self.put_pages_in_lists(to_reinsert.len(), first, last);
 
             if truncate_at != 0 {
                 to_free.truncate(truncate_at);
-                let old = self.to_free.swap(Box::into_raw(to_free), Release);
+                let old = inner.to_free.swap(Box::into_raw(to_free), Release);
                 assert!(old.is_null());
-                self.to_free_delay.store(0, Relaxed);
-            } else {
-                self.to_free_delay.store(DELAY_DROP_SHRINK, Relaxed);
             }
         }
     }
@@ -261,20 +527,28 @@ impl<T: Sized> SharedArena<T> {
     /// # arena.alloc(1);
     /// ```
     pub fn with_capacity(cap: usize) -> SharedArena<T> {
-        let npages = ((cap.max(1) - 1) / BLOCK_PER_PAGE) + 1;
-        let pending_free = Arc::new(AtomicPtr::new(std::ptr::null_mut()));
+        SharedArena::with_capacity_and_shrink(cap, AutoShrink::new())
+    }
 
-        let (first, _) = PageSharedArena::make_list(npages, &pending_free);
+    /// Constructs a new `SharedArena` capable of holding at least `cap`
+    /// elements, and shrinking itself with the policy `auto_shrink`
+    ///
+    /// See [`AutoShrink`] for the policy.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{AutoShrink, SharedArena};
+    /// let arena = SharedArena::with_capacity_and_shrink(2048, AutoShrink::new().free_ratio(75));
+    /// # arena.alloc(1);
+    /// ```
+    ///
+    /// [`AutoShrink`]: ./struct.AutoShrink.html
+    pub fn with_capacity_and_shrink(cap: usize, auto_shrink: AutoShrink) -> SharedArena<T> {
+        let npages = ((cap.max(1) - 1) / BLOCK_PER_PAGE) + 1;
 
         SharedArena {
-            npages: AtomicUsize::new(npages),
-            free_list: AtomicPtr::new(first.as_ptr()),
-            pending_free_list: pending_free,
-            full_list: AtomicPtr::new(first.as_ptr()),
-            writer: AtomicBool::new(false),
-            shrinking: AtomicBool::new(false),
-            to_free: AtomicPtr::new(std::ptr::null_mut()),
-            to_free_delay: AtomicU16::new(DELAY_DROP_SHRINK)
+            inner: OnceLock::from(SharedArenaInner::new(npages, auto_shrink))
         }
     }
 
@@ -295,6 +569,52 @@ impl<T: Sized> SharedArena<T> {
         SharedArena::with_capacity(BLOCK_PER_PAGE)
     }
 
+    /// Constructs a new `SharedArena` without allocating
+    ///
+    /// The first page is allocated on the first call to one of the
+    /// alloc* functions.  
+    /// Because it is a `const fn`, it can be used to initialize a
+    /// `static`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// static NUMBERS: SharedArena<usize> = SharedArena::new_lazy();
+    ///
+    /// assert_eq!(NUMBERS.stats(), (0, 0));
+    ///
+    /// let num = NUMBERS.alloc(1);
+    /// assert_eq!(NUMBERS.stats(), (1, 62));
+    /// ```
+    pub const fn new_lazy() -> SharedArena<T> {
+        SharedArena {
+            inner: OnceLock::new()
+        }
+    }
+
+    /// Returns a [`SharedArenaHandle`] to this arena
+    ///
+    /// The handle shares the pages of this arena and keeps it alive.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// let arena = SharedArena::new();
+    /// let handle = arena.handle();
+    ///
+    /// let item = handle.alloc(1);
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`SharedArenaHandle`]: ./struct.SharedArenaHandle.html
+    pub fn handle(&self) -> SharedArenaHandle<T> {
+        SharedArenaHandle {
+            arena: SharedArena { inner: OnceLock::from(self.inner().clone()) }
+        }
+    }
+
     /// Writes a value in the arena, and returns an [`ArenaBox`]
     /// pointing to that value.
     ///
@@ -327,14 +647,16 @@ impl<T: Sized> SharedArena<T> {
     /// The difference with [`alloc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When the [`ArenaBox`] is dropped, the value is also
     /// dropped. If the value is not initialized correctly, it will
@@ -372,22 +694,60 @@ impl<T: Sized> SharedArena<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_with<F>(&self, initializer: F) -> ArenaBox<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = ArenaBox::new(block);
-
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
+        ArenaBox::new(Block::init(self.find_place(), initializer))
+    }
 
-        result
+    /// Finds an empty space in the arena and calls the function
+    /// `initializer` with its argument pointing to that space.
+    /// It returns a [`ArenaBox`] pointing to the newly initialized value,
+    /// or the error returned by `initializer`.
+    ///
+    /// On error, or if `initializer` panics, the space is given back
+    /// to the arena and the value is not dropped.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of `initializer`.
+    ///
+    /// ## Panics
+    ///
+    /// Like [`alloc_with`], if `initializer` returns a reference
+    /// different from its parameter.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// # use std::io::Read;
+    /// let arena = SharedArena::<[u8; 4]>::new();
+    ///
+    /// let mut source: &[u8] = b"abcd";
+    /// let data = arena.try_alloc_with(|uninit| {
+    ///     let buffer = uninit.write([0; 4]);
+    ///     source.read_exact(buffer)?;
+    ///     Ok::<_, std::io::Error>(&*buffer)
+    /// }).unwrap();
+    /// assert_eq!(&*data, b"abcd");
+    ///
+    /// // The source is empty, the space is given back to the arena
+    /// let result = arena.try_alloc_with(|uninit| {
+    ///     let buffer = uninit.write([0; 4]);
+    ///     source.read_exact(buffer)?;
+    ///     Ok::<_, std::io::Error>(&*buffer)
+    /// });
+    /// assert!(result.is_err());
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`ArenaBox`]: ./struct.ArenaBox.html
+    /// [`alloc_with`]: #method.alloc_with
+    pub fn try_alloc_with<F, E>(&self, initializer: F) -> Result<ArenaBox<T>, E>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> Result<&T, E>
+    {
+        Block::try_init(self.find_place(), initializer).map(ArenaBox::new)
     }
 
     /// Writes a value in the arena, and returns an [`ArenaArc`]
@@ -425,14 +785,16 @@ impl<T: Sized> SharedArena<T> {
     /// The difference with [`alloc_arc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When all [`ArenaArc`] pointing that value are dropped, the value
     /// is also dropped. If the value is not initialized correctly, it will
@@ -470,23 +832,114 @@ impl<T: Sized> SharedArena<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_arc_with<F>(&self, initializer: F) -> ArenaArc<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = ArenaArc::new(block);
+        ArenaArc::new(Block::init(self.find_place(), initializer))
+    }
 
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
+    /// Writes a value in the arena, and returns a pinned [`ArenaBox`]
+    /// pointing to that value.
+    ///
+    /// The value never moves in the arena, so it can be used for
+    /// self-referential values and futures.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// # use std::pin::Pin;
+    /// let arena = SharedArena::new();
+    /// let my_num: Pin<ArenaBox<u8>> = arena.alloc_pin(0xFF);
+    ///
+    /// assert_eq!(*my_num, 255);
+    /// ```
+    ///
+    /// [`ArenaBox`]: ./struct.ArenaBox.html
+    pub fn alloc_pin(&self, value: T) -> Pin<ArenaBox<T>> {
+        ArenaBox::into_pin(self.alloc(value))
+    }
 
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
+    /// Writes a value in the arena, and returns a pinned [`ArenaArc`]
+    /// pointing to that value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// # use std::pin::Pin;
+    /// let arena = SharedArena::new();
+    /// let my_num: Pin<ArenaArc<u8>> = arena.alloc_arc_pin(0xFF);
+    ///
+    /// assert_eq!(*my_num.clone(), 255);
+    /// ```
+    ///
+    /// [`ArenaArc`]: ./struct.ArenaArc.html
+    pub fn alloc_arc_pin(&self, value: T) -> Pin<ArenaArc<T>> {
+        ArenaArc::into_pin(self.alloc_arc(value))
+    }
+
+    /// Finds an empty space in the arena and returns an [`ArenaBox`]
+    /// pointing to it, without initializing the value.
+    ///
+    /// The value can be initialized in several steps through the
+    /// `MaybeUninit`, then converted to an `ArenaBox<T>` with
+    /// [`ArenaBox::assume_init`], without copy.  
+    /// Dropping the `ArenaBox<MaybeUninit<T>>` releases the space
+    /// without dropping the value.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaBox, SharedArena};
+    /// # use std::ptr::addr_of_mut;
+    /// struct Header {
+    ///     id: u32,
+    ///     len: u16,
+    /// }
+    ///
+    /// let arena = SharedArena::<Header>::new();
+    /// let mut header = arena.alloc_uninit();
+    ///
+    /// unsafe {
+    ///     let ptr = header.as_mut_ptr();
+    ///     addr_of_mut!((*ptr).id).write(1);
+    ///     addr_of_mut!((*ptr).len).write(64);
+    /// }
+    ///
+    /// let header: ArenaBox<Header> = unsafe { header.assume_init() };
+    /// assert_eq!((header.id, header.len), (1, 64));
+    /// ```
+    ///
+    /// [`ArenaBox`]: ./struct.ArenaBox.html
+    /// [`ArenaBox::assume_init`]: ./struct.ArenaBox.html#method.assume_init
+    pub fn alloc_uninit(&self) -> ArenaBox<MaybeUninit<T>> {
+        // MaybeUninit<T> has the same layout as T
+        ArenaBox::new(self.find_place().cast())
+    }
 
-        result
+    /// Finds an empty space in the arena and returns an [`ArenaArc`]
+    /// pointing to it, without initializing the value.
+    ///
+    /// See [`alloc_uninit`] and [`ArenaArc::assume_init`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let mut my_num = arena.alloc_arc_uninit();
+    ///
+    /// ArenaArc::get_mut(&mut my_num).unwrap().write(10);
+    ///
+    /// let my_num: ArenaArc<i32> = unsafe { my_num.assume_init() };
+    /// assert_eq!(*my_num.clone(), 10);
+    /// ```
+    ///
+    /// [`ArenaArc`]: ./struct.ArenaArc.html
+    /// [`alloc_uninit`]: #method.alloc_uninit
+    /// [`ArenaArc::assume_init`]: ./struct.ArenaArc.html#method.assume_init
+    pub fn alloc_arc_uninit(&self) -> ArenaArc<MaybeUninit<T>> {
+        ArenaArc::new(self.find_place().cast())
     }
 
     /// Writes a value in the arena, and returns an [`ArenaRc`]
@@ -521,14 +974,16 @@ impl<T: Sized> SharedArena<T> {
     /// The difference with [`alloc_rc`] is that it has the benefit of
     /// avoiding intermediate copies of the value.
     ///
-    /// ## Safety
+    /// ## Panics
     ///
     /// It is the caller responsability to initialize properly the value.  
     /// `initializer` must return `&T`, this is a way to ensure that
     /// its parameter `&mut MaybeUninit<T>` has been "consumed".
     ///
     /// If `initializer` returns a different reference than its parameter,
-    /// the function will panic.
+    /// the function will panic.  
+    /// If `initializer` panics, the space is given back to the arena
+    /// and the value is not dropped.
     ///
     /// When all [`ArenaRc`] pointing that value are dropped, the value
     /// is also dropped. If the value is not initialized correctly, it will
@@ -566,50 +1021,265 @@ impl<T: Sized> SharedArena<T> {
     /// [`MaybeUninit`]: https://doc.rust-lang.org/std/mem/union.MaybeUninit.html
     pub fn alloc_rc_with<F>(&self, initializer: F) -> ArenaRc<T>
     where
-        F: Fn(&mut MaybeUninit<T>) -> &T
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
     {
-        let block = self.find_place();
-        let result = ArenaRc::new(block);
-
-        unsafe {
-            let ptr = block.as_ref().value.get();
-            let reference = initializer(&mut *(ptr as *mut std::mem::MaybeUninit<T>));
-            assert_eq!(
-                ptr as * const T,
-                reference as * const T,
-                "`initializer` must return a reference of its parameter"
-            );
-        }
-
-        result
+        ArenaRc::new(Block::init(self.find_place(), initializer))
     }
 
-    /// Shrinks the capacity of the arena as much as possible.
-    ///
-    /// It will drop all pages that are unused (no Arena{Box,Arc,Rc}
-    /// points to it).  
-    /// If there is still one or more references to a page, the page
-    /// won't be dropped.
+    /// Converts an [`ArenaArc`] to a [`Compact32`], a handle of 32 bits
     ///
-    /// This is a slow function and it should not be called in a hot
-    /// path.
+    /// The reference owned by `arc` is moved to the `Compact32`.
+    /// The `Compact32` has to be given back to this arena, with
+    /// [`from_compact`] or [`drop_compact`], otherwise the value is leaked.
     ///
-    /// The dedicated memory will be deallocated in an
-    /// undetermined time in the future, not during the function call.
-    /// While the time is not determined, it's guarantee that it will
-    /// be deallocated.  
-    /// `shrink_to_fit` on `Arena` and `Pool` don't have this behavior.
+    /// ## Panics
     ///
-    /// Note that if `SharedArena` becomes full and one of the alloc_*
-    /// function is called, it might reuses the pages freed by this
-    /// function, if it has not be deallocated yet.
+    /// Panics if `arc` has not been allocated by this arena, or if
+    /// the arena has more than 2^26 pages. The ids of the pages
+    /// removed by a shrink are reused.
     ///
     /// ## Example
     ///
     /// ```
-    /// # use shared_arena::SharedArena;
-    /// let mut arena = SharedArena::with_capacity(2048);
-    /// let mut values = Vec::new();
+    /// # use shared_arena::{Compact32, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let compact: Compact32<i32> = arena.to_compact(arena.alloc_arc(1));
+    ///
+    /// assert_eq!(unsafe { *arena.get_compact(&compact) }, 1);
+    /// # unsafe { arena.drop_compact(compact) };
+    /// ```
+    ///
+    /// [`ArenaArc`]: ./struct.ArenaArc.html
+    /// [`Compact32`]: ./struct.Compact32.html
+    /// [`from_compact`]: #method.from_compact
+    /// [`drop_compact`]: #method.drop_compact
+    pub fn to_compact(&self, arc: ArenaArc<T>) -> Compact32<T> {
+        let block = ArenaArc::into_block(arc);
+        let tagged = unsafe { block.as_ref() }.page;
+
+        let page_id = match tagged.page_kind() {
+            PageKind::SharedArena => {
+                let page = Block::page_ptr::<PageSharedArena<T>>(block);
+                let id = unsafe { page.as_ref() }.id.load(Relaxed);
+                self.inner().pages.get(id).filter(|p| *p == page).map(|_| id)
+            }
+            _ => None
+        };
+
+        let page_id = match page_id {
+            Some(id) if id <= MAX_PAGE_ID => id,
+            Some(_) => {
+                drop(unsafe { ArenaArc::from_block(block) });
+                panic!("Compact32: too many pages in the arena");
+            }
+            None => {
+                drop(unsafe { ArenaArc::from_block(block) });
+                panic!("Compact32: the ArenaArc doesn't come from this arena");
+            }
+        };
+
+        Compact32::new(page_id, tagged.index_block())
+    }
+
+    /// Returns the block pointed by `compact`
+    ///
+    /// Panics if the block is not in this arena, or is unused.
+    /// These checks can't detect every `Compact32` of another arena
+    fn compact_block(&self, compact: &Compact32<T>) -> NonNull<Block<T>> {
+        let page = self.inner().pages
+                       .get(compact.page_id())
+                       .expect("Compact32: unknown page in this arena");
+        let page = unsafe { page.as_ref() };
+
+        assert!(compact.index_block() < BLOCK_PER_PAGE, "Compact32: invalid block index");
+        let block = page.block_ptr(compact.index_block());
+
+        assert!(
+            unsafe { block.as_ref() }.counter.load(Acquire) != 0,
+            "Compact32: the value is not used, the Compact32 comes from another arena"
+        );
+
+        block
+    }
+
+    /// Converts a [`Compact32`] back to an [`ArenaArc`]
+    ///
+    /// The reference owned by `compact` is moved to the `ArenaArc`.
+    ///
+    /// ## Safety
+    ///
+    /// `compact` must have been made by [`to_compact`] or
+    /// [`clone_compact`] of this arena.  
+    /// A `Compact32` doesn't know its arena: with a `Compact32` of
+    /// another arena, its page id might point to an unrelated value
+    /// of this arena, whose reference would be taken.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `compact` doesn't point to a value in use in this
+    /// arena.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{ArenaArc, SharedArena};
+    /// let arena = SharedArena::new();
+    /// let compact = arena.to_compact(arena.alloc_arc(1));
+    ///
+    /// let arc: ArenaArc<i32> = unsafe { arena.from_compact(compact) };
+    /// assert_eq!(*arc, 1);
+    /// ```
+    ///
+    /// [`ArenaArc`]: ./struct.ArenaArc.html
+    /// [`Compact32`]: ./struct.Compact32.html
+    /// [`to_compact`]: #method.to_compact
+    /// [`clone_compact`]: #method.clone_compact
+    pub unsafe fn from_compact(&self, compact: Compact32<T>) -> ArenaArc<T> {
+        let block = self.compact_block(&compact);
+        ArenaArc::from_block(block)
+    }
+
+    /// Returns a reference to the value pointed by a [`Compact32`]
+    ///
+    /// ## Safety
+    ///
+    /// `compact` must have been made by [`to_compact`] or
+    /// [`clone_compact`] of this arena, see [`from_compact`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `compact` doesn't point to a value in use in this
+    /// arena.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// let arena = SharedArena::new();
+    /// let compact = arena.to_compact(arena.alloc_arc(String::from("a")));
+    ///
+    /// assert_eq!(unsafe { arena.get_compact(&compact) }, "a");
+    /// # unsafe { arena.drop_compact(compact) };
+    /// ```
+    ///
+    /// [`Compact32`]: ./struct.Compact32.html
+    /// [`to_compact`]: #method.to_compact
+    /// [`clone_compact`]: #method.clone_compact
+    /// [`from_compact`]: #method.from_compact
+    pub unsafe fn get_compact<'a>(&'a self, compact: &'a Compact32<T>) -> &'a T {
+        let block = self.compact_block(compact);
+        &*block.as_ref().value.get()
+    }
+
+    /// Makes a clone of a [`Compact32`]
+    ///
+    /// This increase the reference counter, like [`ArenaArc::clone`].
+    ///
+    /// ## Safety
+    ///
+    /// `compact` must have been made by [`to_compact`] or
+    /// [`clone_compact`] of this arena, see [`from_compact`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `compact` doesn't point to a value in use in this
+    /// arena.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// let arena = SharedArena::new();
+    /// let compact = arena.to_compact(arena.alloc_arc(1));
+    ///
+    /// unsafe {
+    ///     let compact2 = arena.clone_compact(&compact);
+    ///
+    ///     arena.drop_compact(compact);
+    ///     assert_eq!(*arena.get_compact(&compact2), 1);
+    ///     # arena.drop_compact(compact2);
+    /// }
+    /// ```
+    ///
+    /// [`Compact32`]: ./struct.Compact32.html
+    /// [`ArenaArc::clone`]: ./struct.ArenaArc.html#impl-Clone
+    /// [`to_compact`]: #method.to_compact
+    /// [`clone_compact`]: #method.clone_compact
+    /// [`from_compact`]: #method.from_compact
+    pub unsafe fn clone_compact(&self, compact: &Compact32<T>) -> Compact32<T> {
+        let block = self.compact_block(compact);
+
+        let old = block.as_ref().counter.fetch_add(1, Relaxed);
+        assert!(old < isize::MAX as usize);
+
+        Compact32::new(compact.page_id(), compact.index_block())
+    }
+
+    /// Releases the reference owned by a [`Compact32`]
+    ///
+    /// If it was the last reference to the value, the value is dropped.
+    ///
+    /// ## Safety
+    ///
+    /// `compact` must have been made by [`to_compact`] or
+    /// [`clone_compact`] of this arena, see [`from_compact`].
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `compact` doesn't point to a value in use in this
+    /// arena.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// let arena = SharedArena::new();
+    /// let compact = arena.to_compact(arena.alloc_arc(1));
+    ///
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// unsafe { arena.drop_compact(compact) };
+    /// assert_eq!(arena.stats(), (0, 63));
+    /// ```
+    ///
+    /// [`Compact32`]: ./struct.Compact32.html
+    /// [`to_compact`]: #method.to_compact
+    /// [`clone_compact`]: #method.clone_compact
+    /// [`from_compact`]: #method.from_compact
+    pub unsafe fn drop_compact(&self, compact: Compact32<T>) {
+        drop(self.from_compact(compact));
+    }
+
+    /// Shrinks the capacity of the arena as much as possible.
+    ///
+    /// It will drop all pages that are unused (no Arena{Box,Arc,Rc}
+    /// points to it).  
+    /// If there is still one or more references to a page, the page
+    /// won't be dropped.
+    ///
+    /// This is a slow function and it should not be called in a hot
+    /// path.
+    ///
+    /// The pages are deallocated once no other thread is reading
+    /// them: during the call if no thread is using the arena,
+    /// otherwise during a following allocation or shrink, or with
+    /// [`reclaim_now`].  
+    /// `shrink_to_fit` on `Arena` and `Pool` don't have this behavior.
+    ///
+    /// Note that if `SharedArena` becomes full and one of the alloc_*
+    /// function is called, it might reuses the pages freed by this
+    /// function, if it has not be deallocated yet.
+    ///
+    /// It returns a [`ShrinkReport`] of the pages freed, they are
+    /// counted even if they are not deallocated yet.  
+    /// If another thread is already shrinking the arena, nothing is
+    /// done and [`ShrinkReport::completed`] is `false`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// let mut arena = SharedArena::with_capacity(2048);
+    /// let mut values = Vec::new();
     ///
     /// let (used, free) = arena.stats();
     /// assert!(used == 0, free == 2048);
@@ -624,23 +1294,36 @@ impl<T: Sized> SharedArena<T> {
     /// assert!(used == 80, free == 46);
     ///
     /// ```
-    pub fn shrink_to_fit(&self) -> bool {
-        if self.shrinking.swap(true, AcqRel) {
-            return false;
-        }
+    ///
+    /// [`reclaim_now`]: #method.reclaim_now
+    /// [`ShrinkReport`]: ./struct.ShrinkReport.html
+    /// [`ShrinkReport::completed`]: ./struct.ShrinkReport.html#structfield.completed
+    pub fn shrink_to_fit(&self) -> ShrinkReport {
+        self.shrink_pages(|_, free| free)
+    }
 
-        let _guard = WriterGuard::new_blocking(&self.writer);
+    /// Drops the fully free pages of the free list for which `release`
+    /// returns `true`
+    ///
+    /// `release` is called on each page of the free list, with whether
+    /// it is fully free.
+    fn shrink_pages(&self, mut release: impl FnMut(&PageSharedArena<T>, bool) -> bool) -> ShrinkReport {
+        // Nothing has been allocated yet with `new_lazy`
+        let inner = match self.inner.get() {
+            Some(inner) => inner,
+            None => return ShrinkReport::pages::<PageSharedArena<T>>(0),
+        };
 
-        let mut current: &AtomicPtr<PageSharedArena<T>> = &AtomicPtr::new(self.free_list.swap(std::ptr::null_mut(), AcqRel));
-        let start = current;
+        if inner.shrinking.swap(true, AcqRel) {
+            return ShrinkReport::skipped();
+        }
 
-        // let narenas = Arc::strong_count(&self.pending_free_list);
+        let _guard = WriterGuard::new_blocking(&inner.writer);
 
-        // for _ in 0..narenas {
-        //     std::thread::yield_now();
-        // }
+        let free_list = inner.free_list.swap(std::ptr::null_mut(), &inner.pages);
 
-        let mut to_drop = Vec::with_capacity(self.npages.load(Relaxed));
+        let mut current: &AtomicPtr<PageSharedArena<T>> = &AtomicPtr::new(free_list);
+        let start = current;
 
         // We loop on the free list to get all pages that have 0 reference to
         // them and remove them from the free list
@@ -648,66 +1331,310 @@ impl<T: Sized> SharedArena<T> {
             let next = &current_value.next_free;
             let next_value = next.load(Acquire);
 
-            if current_value.bitfield.load(Acquire) == !0 {
+            let free = current_value.bitfield.load(Acquire) == !0;
+
+            if release(current_value, free) && free {
                 if current.compare_exchange(
                     current_value as *const _ as *mut _, next_value, AcqRel, Relaxed
                 ).is_ok() {
-                    // current_value.in_free_list.store(false, Release);
-                    let ptr = current_value as *const _ as *mut PageSharedArena<T>;
-                    to_drop.push(NonNull::new(ptr).unwrap());
+                    current_value.released.store(true, Release);
+                    inner.remove_pages(1);
                 }
             } else {
                 current = next;
             }
         }
 
-        // Check that the page hasn't been used by another thread
-        to_drop.retain(|page| {
-            let page_ref = unsafe { page.as_ref() };
-            page_ref.bitfield.load(Acquire) == !0
-        });
+        // Loop on the whole full list, from its beginning
+        inner.shrink_cursor.store(std::ptr::null_mut(), Relaxed);
+        let nfreed = self.unlink_released_pages(usize::MAX);
+        self.maybe_free_pages();
 
-        let mut current: &AtomicPtr<PageSharedArena<T>> = &self.full_list;
+        let old = inner.free_list.swap(start.load(Relaxed), &inner.pages);
+        assert!(old.is_null(), "OLD NOT NULL");
 
-        // Loop on the full list
-        // We remove the pages from it
-        while let Some(current_value) = unsafe { current.load(Relaxed).as_mut() } {
-            let ptr = unsafe { NonNull::new_unchecked(current_value) };
-            let next = &current_value.next;
-            let next_value = next.load(Relaxed);
+        inner.shrinking.store(false, Release);
 
-            if to_drop.contains(&ptr) {
-                current.compare_exchange(
-                    current_value as *const _ as *mut _, next_value, AcqRel, Relaxed
-                ).expect("Something went wrong in shrinking.");
-            } else {
-                current = next;
+        ShrinkReport::pages::<PageSharedArena<T>>(nfreed)
+    }
+
+    /// Shrinks the capacity of the arena, examining at most `budget` pages
+    ///
+    /// Unlike [`shrink_to_fit`], it never blocks: if another thread
+    /// is allocating new pages or shrinking the arena, nothing is done
+    /// and [`ShrinkReport::completed`] is `false`.
+    ///
+    /// Each call examines at most `budget` pages of the free list, and
+    /// at most `budget` pages of the list of all pages to remove the
+    /// unused ones. The next call resumes where the previous one
+    /// stopped, so calling it regularly eventually releases all the
+    /// unused pages.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// let arena = SharedArena::<usize>::with_capacity(63 * 100);
+    /// let value = arena.alloc(1);
+    ///
+    /// let mut pages_freed = 0;
+    /// while pages_freed < 99 {
+    ///     let report = arena.try_shrink(16);
+    ///     assert!(report.pages_freed <= 16);
+    ///     pages_freed += report.pages_freed;
+    /// }
+    ///
+    /// assert_eq!(arena.stats(), (1, 62));
+    /// ```
+    ///
+    /// [`shrink_to_fit`]: #method.shrink_to_fit
+    /// [`ShrinkReport::completed`]: ./struct.ShrinkReport.html#structfield.completed
+    pub fn try_shrink(&self, budget: usize) -> ShrinkReport {
+        self.shrink_budget(budget, |_, free| free)
+    }
+
+    /// Drops the fully free pages for which `release` returns `true`,
+    /// examining at most `budget` pages, see `try_shrink`
+    ///
+    /// `release` is called on each page examined, with whether it is
+    /// fully free.
+    fn shrink_budget(
+        &self,
+        budget: usize,
+        mut release: impl FnMut(&PageSharedArena<T>, bool) -> bool
+    ) -> ShrinkReport
+    {
+        // Nothing has been allocated yet with `new_lazy`
+        let inner = match self.inner.get() {
+            Some(inner) => inner,
+            None => return ShrinkReport::pages::<PageSharedArena<T>>(0),
+        };
+
+        if inner.shrinking.swap(true, AcqRel) {
+            return ShrinkReport::skipped();
+        }
+
+        let _guard = match WriterGuard::new(&inner.writer) {
+            Some(guard) => guard,
+            None => {
+                inner.shrinking.store(false, Release);
+                return ShrinkReport::skipped();
+            }
+        };
+
+        let mut current = inner.free_list.swap(std::ptr::null_mut(), &inner.pages);
+
+        // The pages examined and kept, linked with next_free
+        let mut kept_first: *mut PageSharedArena<T> = std::ptr::null_mut();
+        let mut kept_last: Option<&PageSharedArena<T>> = None;
+
+        for _ in 0..budget {
+            let page = match unsafe { current.as_ref() } {
+                Some(page) => page,
+                None => break
+            };
+
+            current = page.next_free.load(Acquire);
+
+            let free = page.bitfield.load(Acquire) == !0;
+
+            if release(page, free) && free {
+                page.released.store(true, Release);
+                // Not counted in the arena anymore, it might stay in the
+                // full list for a few calls
+                inner.remove_pages(1);
+                continue;
             }
+
+            page.next_free.store(std::ptr::null_mut(), Relaxed);
+            match kept_last {
+                Some(last) => last.next_free.store(page as *const _ as *mut _, Relaxed),
+                None => kept_first = page as *const _ as *mut _,
+            }
+            kept_last = Some(page);
+        }
+
+        if current.is_null() {
+            // The whole free list was examined, the next call starts
+            // with the pages of the pending list and the pages kept
+            current = self.chain_pending_list(kept_first);
+        } else if let Some(last) = kept_last {
+            // The next call resumes with the pages not examined, the
+            // pages kept are examined again once the free list has
+            // been examined or used
+            self.push_pending_list(kept_first, last);
+        }
+
+        let nfreed = self.unlink_released_pages(budget);
+        self.maybe_free_pages();
+
+        let old = inner.free_list.swap(current, &inner.pages);
+        assert!(old.is_null(), "OLD NOT NULL");
+
+        inner.shrinking.store(false, Release);
+
+        ShrinkReport::pages::<PageSharedArena<T>>(nfreed)
+    }
+
+    /// Deallocates the pages removed by a shrink, if no thread is
+    /// reading them
+    ///
+    /// The pages removed by [`shrink_to_fit`] and [`try_shrink`] are
+    /// deallocated once no other thread can be reading them, which
+    /// is checked during the shrink and the following allocations.
+    /// `reclaim_now` checks it a bounded number of times, yielding to
+    /// the threads reading the pages of this arena between the
+    /// checks. It doesn't wait for them: when they are still reading,
+    /// the pages are kept for a later call.
+    ///
+    /// Returns the number of pages deallocated.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedArena;
+    /// let arena = SharedArena::<usize>::with_capacity(63 * 10);
+    /// let value = arena.alloc(1);
+    ///
+    /// assert_eq!(arena.shrink_to_fit().pages_freed, 9);
+    ///
+    /// // After this call, the memory of the 9 pages is deallocated
+    /// arena.reclaim_now();
+    /// assert_eq!(arena.reclaim_now(), 0);
+    /// ```
+    ///
+    /// [`shrink_to_fit`]: #method.shrink_to_fit
+    /// [`try_shrink`]: #method.try_shrink
+    pub fn reclaim_now(&self) -> usize {
+        // Nothing has been allocated yet with `new_lazy`
+        let inner = match self.inner.get() {
+            Some(inner) => inner,
+            None => return 0,
+        };
+
+        // The pages are added to `to_free` with the writer lock held,
+        // they have all been removed from the lists before the checks
+        let _guard = WriterGuard::new_blocking(&inner.writer);
+
+        if inner.to_free.load(Relaxed).is_null() {
+            return 0;
+        }
+
+        for _ in 0..RECLAIM_ATTEMPTS {
+            if inner.readers.is_quiescent() {
+                let to_free = inner.to_free.swap(std::ptr::null_mut(), AcqRel);
+                let to_free = unsafe { Box::from_raw(to_free) };
+
+                for page in &*to_free {
+                    deallocate_page(page.as_ptr());
+                }
+
+                return to_free.len();
+            }
+
+            crate::sync::yield_now();
+        }
+
+        0
+    }
+
+    /// Pushes the pages from `first` to `last`, linked with `next_free`,
+    /// on `pending_free_list`
+    fn push_pending_list(&self, first: *mut PageSharedArena<T>, last: &PageSharedArena<T>) {
+        let pending_free_list = &self.inner().pending_free_list;
+
+        loop {
+            let current = pending_free_list.load(Relaxed);
+            last.next_free.store(current, Relaxed);
+
+            if pending_free_list.compare_exchange(
+                current, first, AcqRel, Relaxed
+            ).is_ok() {
+                break;
+            }
+        }
+    }
+
+    /// Removes the pages marked `released` from the full list, and
+    /// deallocates them later
+    ///
+    /// The pages marked `released` are already not counted in `npages`
+    ///
+    /// It examines at most `budget` pages, from `shrink_cursor`.  
+    /// Returns the number of pages removed. The writer lock must be held
+    fn unlink_released_pages(&self, budget: usize) -> usize {
+        let inner = self.inner();
+
+        let mut cursor = inner.shrink_cursor.load(Relaxed);
+        let mut current: &AtomicPtr<PageSharedArena<T>> = match unsafe { cursor.as_ref() } {
+            Some(page) => &page.next,
+            None => &inner.full_list,
+        };
+
+        let mut to_drop = Vec::new();
+
+        for _ in 0..budget {
+            let current_value = match unsafe { current.load(Relaxed).as_ref() } {
+                Some(page) => page,
+                None => {
+                    // End of the list, the next call starts from its beginning
+                    cursor = std::ptr::null_mut();
+                    break;
+                }
+            };
+
+            if !current_value.released.load(Acquire) {
+                cursor = current_value as *const _ as *mut _;
+                current = &current_value.next;
+                continue;
+            }
+
+            current_value.released.store(false, Relaxed);
+
+            // Check that the page hasn't been used by another thread
+            // since it has been removed from the free list. A thread
+            // which read it before can't acquire a block once sealed
+            if !current_value.seal() {
+                let ptr = current_value as *const _ as *mut _;
+                self.push_pending_list(ptr, current_value);
+                inner.add_pages(1);
+
+                cursor = ptr;
+                current = &current_value.next;
+                continue;
+            }
+
+            let next_value = current_value.next.load(Relaxed);
+            current.compare_exchange(
+                current_value as *const _ as *mut _, next_value, AcqRel, Relaxed
+            ).expect("Something went wrong in shrinking.");
+
+            to_drop.push(NonNull::from(current_value));
+        }
+
+        inner.shrink_cursor.store(cursor, Relaxed);
+
+        // The pages are not reachable with a Compact32 anymore, their
+        // ids are given to the next pages
+        for page in &to_drop {
+            inner.pages.remove(unsafe { page.as_ref() }.id.load(Relaxed));
         }
 
         let nfreed = to_drop.len();
 
         if nfreed != 0 {
-            self.to_free_delay.store(0, Release);
-            if let Some(to_free) = unsafe { self.to_free.swap(std::ptr::null_mut(), AcqRel).as_mut() } {
+            if let Some(to_free) = unsafe { inner.to_free.swap(std::ptr::null_mut(), AcqRel).as_mut() } {
                 to_free.append(&mut to_drop);
-                let old = self.to_free.swap(to_free, AcqRel);
+                let old = inner.to_free.swap(to_free, AcqRel);
                 assert!(old.is_null());
             } else {
                 let ptr = Box::new(to_drop);
-                let old = self.to_free.swap(Box::into_raw(ptr), AcqRel);
+                let old = inner.to_free.swap(Box::into_raw(ptr), AcqRel);
                 assert!(old.is_null());
             }
         }
 
-        let old = self.free_list.swap(start.load(Relaxed), Release);
-        assert!(old.is_null(), "OLD NOT NULL");
-
-        self.npages.fetch_sub(nfreed, Release);
-
-        self.shrinking.store(false, Release);
-
-        true
+        nfreed
     }
 
     /// Returns a tuple of non-free and free spaces in the arena
@@ -725,7 +1652,15 @@ impl<T: Sized> SharedArena<T> {
     /// assert!(used == 1 && free == 62);
     /// ```
     pub fn stats(&self) -> (usize, usize) {
-        let mut next = self.free_list.load(Relaxed);
+        // Nothing has been allocated yet with `new_lazy`
+        let inner = match self.inner.get() {
+            Some(inner) => inner,
+            None => return (0, 0),
+        };
+
+        let _guard = ReadGuard::new(inner.readers);
+
+        let (_, mut next) = inner.free_list.load(&inner.pages, Relaxed);
 
         let mut free = 0;
 
@@ -735,7 +1670,7 @@ impl<T: Sized> SharedArena<T> {
             next = next_next;
         }
 
-        let mut next = self.pending_free_list.load(Relaxed);
+        let mut next = inner.pending_free_list.load(Relaxed);
 
         while let Some(next_ref) = unsafe { next.as_mut() } {
             let next_next = next_ref.next_free.load(Relaxed);
@@ -743,7 +1678,7 @@ impl<T: Sized> SharedArena<T> {
             next = next_next;
         }
 
-        let used = (self.npages.load(Relaxed) * BLOCK_PER_PAGE) - free;
+        let used = (inner.npages.load(Relaxed) * BLOCK_PER_PAGE) - free;
 
         (used, free)
     }
@@ -751,21 +1686,24 @@ impl<T: Sized> SharedArena<T> {
     #[cfg(target_pointer_width = "64") ]
     #[cfg(test)]
     pub(crate) fn size_lists(&self) -> (usize, usize, usize) {
-        let mut next = self.full_list.load(Relaxed);
+        let inner = self.inner();
+        let _guard = ReadGuard::new(inner.readers);
+
+        let mut next = inner.full_list.load(Relaxed);
         let mut size = 0;
         while let Some(next_ref) = unsafe { next.as_mut() } {
             next = next_ref.next.load(Relaxed);
             size += 1;
         }
 
-        let mut next = self.free_list.load(Relaxed);
+        let (_, mut next) = inner.free_list.load(&inner.pages, Relaxed);
         let mut free = 0;
         while let Some(next_ref) = unsafe { next.as_mut() } {
             next = next_ref.next_free.load(Relaxed);
             free += 1;
         }
 
-        let mut next = self.pending_free_list.load(Relaxed);
+        let mut next = inner.pending_free_list.load(Relaxed);
         let mut pending = 0;
         while let Some(next_ref) = unsafe { next.as_mut() } {
             next = next_ref.next_free.load(Relaxed);
@@ -775,12 +1713,52 @@ impl<T: Sized> SharedArena<T> {
         (size, free, pending)
     }
 
+    #[cfg(all(test, loom))]
+    pub(crate) fn readers(&self) -> &'static crate::reclaim::Readers {
+        self.inner().readers
+    }
+
+    /// Panics if the lists of pages are corrupted
+    #[cfg(test)]
+    pub(crate) fn check_lists(&self) {
+        let inner = self.inner();
+        use std::collections::HashSet;
+
+        let _guard = ReadGuard::new(inner.readers);
+
+        let mut full = HashSet::new();
+        let mut released = 0;
+        let mut next = inner.full_list.load(Acquire);
+        while let Some(next_ref) = unsafe { next.as_ref() } {
+            assert!(full.insert(next), "Cycle in the full list");
+            released += next_ref.released.load(Acquire) as usize;
+            next = next_ref.next.load(Acquire);
+        }
+        assert_eq!(full.len() - released, inner.npages.load(Acquire));
+
+        let (_, free_list) = inner.free_list.load(&inner.pages, Acquire);
+        let pending_list = inner.pending_free_list.load(Acquire);
+
+        let mut free = HashSet::new();
+        for mut next in [free_list, pending_list] {
+            while let Some(next_ref) = unsafe { next.as_ref() } {
+                assert!(full.contains(&next), "Page of the free lists not in the arena");
+                assert!(free.insert(next), "Page twice in the free lists");
+                assert!(next_ref.in_free_list.load(Acquire), "Page in a free list without in_free_list");
+                next = next_ref.next_free.load(Acquire);
+            }
+        }
+    }
+
     #[allow(dead_code)]
     #[cfg(test)]
     pub(crate) fn display_list(&self) {
+        let inner = self.inner();
+        let _guard = ReadGuard::new(inner.readers);
+
         let mut full = vec![];
 
-        let mut next = self.full_list.load(Relaxed);
+        let mut next = inner.full_list.load(Relaxed);
         while let Some(next_ref) = unsafe { next.as_mut() } {
             full.push(next);
             next = next_ref.next.load(Relaxed);
@@ -788,7 +1766,7 @@ impl<T: Sized> SharedArena<T> {
 
         let mut list_free = vec![];
 
-        let mut next = self.free_list.load(Relaxed);
+        let (_, mut next) = inner.free_list.load(&inner.pages, Relaxed);
         while let Some(next_ref) = unsafe { next.as_mut() } {
             list_free.push(next);
             next = next_ref.next_free.load(Relaxed);
@@ -799,26 +1777,28 @@ impl<T: Sized> SharedArena<T> {
     }
 }
 
-impl<T> Drop for SharedArena<T> {
+impl<T> Drop for SharedArenaInner<T> {
     fn drop(&mut self) {
-        if let Some(to_free) = unsafe { self.to_free.load(Relaxed).as_mut() } {
-            let to_free = unsafe { Box::from_raw(to_free) };
-            for page in &*to_free {
-                drop_page(page.as_ptr());
-            }
-        }
+        // The pages sealed by a shrink
+        let mut to_free = match unsafe { self.to_free.load(Relaxed).as_mut() } {
+            Some(to_free) => unsafe { Box::from_raw(to_free) }.into_iter().map(deferred_page).collect(),
+            None => Vec::new(),
+        };
 
         let mut next = self.full_list.load(Relaxed);
 
         while let Some(next_ref) = unsafe { next.as_mut() } {
             let next_next = next_ref.next.load(Relaxed);
-            // unsafe {
-            // Invoke Page::drop
-            drop_page(next);
-            // std::ptr::drop_in_place(next);
-            // }
+            if drop_page(next) {
+                to_free.push(deferred_page(NonNull::from(next_ref)));
+            }
             next = next_next;
         }
+
+        // A thread releasing the last block of a page might still be
+        // reading it: the last of them deallocates the pages
+        self.readers.defer(to_free);
+        self.readers.remove_owner();
     }
 }
 
@@ -841,18 +1821,25 @@ impl<T> std::fmt::Debug for SharedArena<T> {
             }
         }
 
-        let npages = self.npages.load(Relaxed);
+        // The list might be modified by a shrink in another thread
+        let _guard = self.inner.get().map(|inner| ReadGuard::new(inner.readers));
 
-        let mut vec = Vec::with_capacity(npages);
+        let (npages, mut next) = match self.inner.get() {
+            Some(inner) => (inner.npages.load(Relaxed), inner.full_list.load(Relaxed)),
+            None => (0, std::ptr::null_mut()),
+        };
 
-        let mut next = self.full_list.load(Relaxed);
+        let mut vec = Vec::with_capacity(npages);
 
         while let Some(next_ref) = unsafe { next.as_mut() } {
-            let used = next_ref.bitfield.load(Relaxed).count_zeros() as usize;
-            vec.push(Page {
-                used,
-                free: BLOCK_PER_PAGE - used
-            });
+            // Pages removed by `try_shrink`, not unlinked yet
+            if !next_ref.released.load(Relaxed) {
+                let used = next_ref.bitfield.load(Relaxed).count_zeros() as usize;
+                vec.push(Page {
+                    used,
+                    free: BLOCK_PER_PAGE - used
+                });
+            }
 
             next = next_ref.next.load(Relaxed);
         }
@@ -940,6 +1927,652 @@ mod tests {
         assert_eq!(arena.stats(), (2, 61));
     }
 
+    #[test]
+    fn arena_compact() {
+        let arena = SharedArena::<usize>::with_capacity(1000);
+
+        let compacts: Vec<_> = (0..1000).map(|n| arena.to_compact(arena.alloc_arc(n))).collect();
+
+        for (n, compact) in compacts.iter().enumerate() {
+            assert_eq!(unsafe { *arena.get_compact(compact) }, n);
+        }
+
+        let clone = unsafe { arena.clone_compact(&compacts[10]) };
+        let arcs: Vec<_> = compacts.into_iter().map(|c| unsafe { arena.from_compact(c) }).collect();
+
+        for (n, arc) in arcs.iter().enumerate() {
+            assert_eq!(**arc, n);
+        }
+
+        std::mem::drop(arcs);
+        assert_eq!(arena.stats().0, 1);
+
+        // The page of `clone` is still used, it is not removed
+        arena.shrink_to_fit();
+        assert_eq!(unsafe { *arena.get_compact(&clone) }, 10);
+
+        // The pages removed by shrink_to_fit are reachable again
+        // once they are reused
+        let compacts: Vec<_> = (0..1000).map(|n| arena.to_compact(arena.alloc_arc(n))).collect();
+        for (n, compact) in compacts.iter().enumerate() {
+            assert_eq!(unsafe { *arena.get_compact(compact) }, n);
+        }
+        for compact in compacts {
+            unsafe { arena.drop_compact(compact) };
+        }
+
+        unsafe { arena.drop_compact(clone) };
+        assert_eq!(arena.stats().0, 0);
+    }
+
+    #[test]
+    fn arena_compact_page_ids_reused() {
+        let arena = SharedArena::<usize>::with_capacity(630);
+        let mut npages = Vec::new();
+
+        for _ in 0..5 {
+            let compacts: Vec<_> = (0..630).map(|n| arena.to_compact(arena.alloc_arc(n))).collect();
+            for (n, compact) in compacts.iter().enumerate() {
+                assert_eq!(unsafe { *arena.get_compact(compact) }, n);
+            }
+            for compact in compacts {
+                unsafe { arena.drop_compact(compact) };
+            }
+
+            assert!(arena.shrink_to_fit().completed);
+            assert_eq!(arena.stats().0, 0);
+            npages.push(arena.inner().pages.len());
+        }
+
+        // The ids of the removed pages are given to the new pages,
+        // the table doesn't grow once the pending pages are taken
+        assert!(npages[1..].iter().all(|n| *n == npages[1]), "{:?}", npages);
+    }
+
+    #[test]
+    #[should_panic]
+    fn arena_compact_other_arena() {
+        let arena = SharedArena::<usize>::new();
+        let other = SharedArena::<usize>::new();
+
+        let _compact = arena.to_compact(other.alloc_arc(1));
+    } // grcov_ignore
+
+    #[test]
+    #[should_panic]
+    fn arena_compact_other_kind() {
+        let arena = SharedArena::<usize>::new();
+        let other = crate::Arena::<usize>::new();
+
+        let _compact = arena.to_compact(other.alloc_arc(1));
+    } // grcov_ignore
+
+    #[test]
+    #[should_panic]
+    fn arena_compact_unused() {
+        let arena = SharedArena::<usize>::new();
+        let other = SharedArena::<usize>::new();
+
+        // Not allowed, but the page id is not used in `arena`
+        let compact = other.to_compact(other.alloc_arc(1));
+        let _ = unsafe { arena.get_compact(&compact) };
+    } // grcov_ignore
+
+    #[test]
+    fn arena_raw_pointers() {
+        use crate::{ArenaArc, ArenaBox};
+
+        let arena = SharedArena::<usize>::new();
+
+        let boxed = ArenaBox::into_raw(arena.alloc(1)) as usize;
+        let arc = ArenaArc::into_raw(arena.alloc_arc(2)) as usize;
+
+        unsafe { ArenaArc::increment_strong_count(arc as *const usize) };
+        assert_eq!(arena.stats(), (2, 61));
+
+        std::thread::spawn(move || {
+            let boxed = unsafe { ArenaBox::from_raw(boxed as *mut usize) };
+            assert_eq!(*boxed, 1);
+
+            unsafe { ArenaArc::decrement_strong_count(arc as *const usize) };
+        }).join().unwrap();
+
+        assert_eq!(arena.stats(), (1, 62));
+
+        let arc = unsafe { ArenaArc::from_raw(arc as *const usize) };
+        assert_eq!(*arc, 2);
+
+        std::mem::drop(arc);
+        assert_eq!(arena.stats(), (0, 63));
+    }
+
+    #[test]
+    fn arena_into_inner_threads() {
+        use crate::ArenaArc;
+        use std::sync::Arc;
+
+        let arena = SharedArena::<Arc<usize>>::new();
+        let value = Arc::new(1);
+
+        for _ in 0..100 {
+            let arc = arena.alloc_arc(value.clone());
+
+            let handles: Vec<_> = (0..4).map(|_| {
+                let arc = arc.clone();
+                std::thread::spawn(move || ArenaArc::into_inner(arc))
+            }).collect();
+
+            let mut inners: Vec<_> = handles.into_iter()
+                                            .filter_map(|h| h.join().unwrap())
+                                            .collect();
+            inners.extend(ArenaArc::into_inner(arc));
+
+            assert_eq!(inners.len(), 1);
+            assert_eq!(Arc::strong_count(&value), 2);
+        }
+
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(arena.stats(), (0, 63));
+    }
+
+    #[test]
+    fn arena_make_mut() {
+        use crate::{Arena, ArenaArc};
+
+        let arena = SharedArena::<String>::new();
+
+        let mut value = arena.alloc_arc(String::from("a"));
+        let shared = value.clone();
+
+        ArenaArc::make_mut(&mut value).unwrap().push('b');
+        assert_eq!((value.as_str(), shared.as_str()), ("ab", "a"));
+        assert_eq!(arena.stats(), (2, 61));
+
+        // Not shared anymore, the value is not cloned
+        ArenaArc::make_mut(&mut value).unwrap().push('c');
+        assert_eq!(arena.stats(), (2, 61));
+
+        // The arena has been dropped, the value can't be cloned
+        std::mem::drop(arena);
+        let mut other = shared.clone();
+        assert!(ArenaArc::make_mut(&mut other).is_none());
+        assert_eq!(other.as_str(), "a");
+
+        // The last reference doesn't need its arena
+        std::mem::drop(other);
+        ArenaArc::make_mut(&mut value).unwrap().push('d');
+        assert_eq!(value.as_str(), "abcd");
+
+        // Values allocated in an Arena are not cloned
+        let arena = Arena::<String>::new();
+        let mut value = arena.alloc_arc(String::from("a"));
+        let shared = value.clone();
+        assert!(ArenaArc::make_mut(&mut value).is_none());
+        drop(shared);
+        ArenaArc::make_mut(&mut value).unwrap().push('b');
+        assert_eq!(value.as_str(), "ab");
+        assert_eq!(arena.stats(), (1, 62));
+    }
+
+    #[test]
+    fn arena_box_try_clone() {
+        use crate::{Arena, ArenaBox};
+
+        let arena = SharedArena::<String>::new();
+        let value = arena.alloc(String::from("a"));
+
+        let mut other = ArenaBox::try_clone(&value).unwrap();
+        other.push('b');
+        assert_eq!((value.as_str(), other.as_str()), ("a", "ab"));
+        assert_eq!(arena.stats(), (2, 61));
+
+        std::mem::drop(arena);
+        assert!(ArenaBox::try_clone(&value).is_none());
+
+        let arena = Arena::<String>::new();
+        let value = arena.alloc(String::from("a"));
+        assert!(ArenaBox::try_clone(&value).is_none());
+        assert_eq!(arena.stats(), (1, 62));
+    }
+
+    #[test]
+    fn arena_handle_clones() {
+        use super::SharedArenaHandle;
+        use std::sync::Arc;
+
+        let value = Arc::new(1);
+        let arena = SharedArenaHandle::<Arc<usize>>::with_capacity(1000);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let arena = arena.clone();
+            let value = value.clone();
+            std::thread::spawn(move || {
+                (0..500).map(|_| arena.alloc(value.clone())).collect::<Vec<_>>()
+            })
+        }).collect();
+
+        let values: Vec<_> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+
+        assert_eq!(arena.stats().0, 2000);
+        assert_eq!(Arc::strong_count(&value), 2001);
+
+        // The last handle is dropped, the values are still valid
+        std::mem::drop(arena);
+        assert!(values.iter().all(|v| ***v == 1));
+
+        std::mem::drop(values);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn arena_lazy() {
+        static ARENA: SharedArena<usize> = SharedArena::new_lazy();
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            std::thread::spawn(|| (0..100).map(|n| ARENA.alloc_arc(n)).collect::<Vec<_>>())
+        }).collect();
+
+        let values: Vec<_> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+        assert_eq!(ARENA.stats().0, 400);
+
+        std::mem::drop(values);
+        assert_eq!(ARENA.stats().0, 0);
+
+        let arena = SharedArena::<usize>::new_lazy();
+        assert!(arena.shrink_to_fit().completed);
+        assert_eq!(arena.stats(), (0, 0));
+        println!("{:?}", arena);
+    }
+
+    #[test]
+    fn arena_leak() {
+        use crate::{Arena, ArenaBox};
+
+        let arena = SharedArena::<Vec<usize>>::with_capacity(2);
+        let leaked: Vec<&'static mut Vec<usize>> = (0..10).map(|n| {
+            ArenaBox::leak(arena.alloc(vec![n]))
+        }).collect();
+
+        // The pages with a leaked value are not freed
+        assert!(arena.shrink_to_fit().completed);
+        assert_eq!(arena.stats(), (10, 53));
+
+        let values: Vec<_> = (0..100).map(|n| arena.alloc(vec![n])).collect();
+        std::mem::drop(values);
+        assert!(arena.shrink_to_fit().completed);
+        assert_eq!(arena.stats(), (10, 53));
+
+        std::mem::drop(arena);
+        for (n, value) in leaked.into_iter().enumerate() {
+            value.push(n);
+            assert_eq!(value[..], [n, n]);
+        }
+
+        let arena = Arena::<usize>::new();
+        let leaked = ArenaBox::leak(arena.alloc(1));
+        arena.shrink_to_fit();
+        *leaked += 1;
+        assert_eq!(arena.stats(), (1, 62));
+        assert_eq!(*leaked, 2);
+    }
+
+    #[test]
+    fn arena_uninit() {
+        use crate::Arena;
+        use std::sync::Arc;
+
+        let value = Arc::new(1);
+        let arena = SharedArena::<(Arc<usize>, [u8; 64])>::new();
+
+        // Not initialized, the value is not dropped
+        std::mem::drop(arena.alloc_uninit());
+        std::mem::drop(arena.alloc_arc_uninit());
+        assert_eq!(arena.stats(), (0, 63));
+
+        let mut boxed = arena.alloc_uninit();
+        let ptr = boxed.as_mut_ptr();
+        unsafe {
+            std::ptr::addr_of_mut!((*ptr).0).write(value.clone());
+            for (index, byte) in b"data".iter().cycle().take(64).enumerate() {
+                std::ptr::addr_of_mut!((*ptr).1[index]).write(*byte);
+            }
+        }
+        let boxed = unsafe { boxed.assume_init() };
+        assert_eq!(&boxed.1[60..], b"data");
+        assert_eq!(Arc::strong_count(&value), 2);
+
+        let mut arc = arena.alloc_arc_uninit();
+        crate::ArenaArc::get_mut(&mut arc).unwrap().write((value.clone(), [0; 64]));
+        let arc = unsafe { arc.assume_init() };
+        let clone = arc.clone();
+        assert_eq!(arena.stats(), (2, 61));
+
+        std::mem::drop((boxed, arc, clone));
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(arena.stats(), (0, 63));
+
+        let arena = Arena::<String>::new();
+        let mut boxed = arena.alloc_uninit();
+        boxed.write(String::from("a"));
+        assert_eq!(*unsafe { boxed.assume_init() }, "a");
+        assert_eq!(arena.stats(), (0, 63));
+    }
+
+    #[test]
+    fn arena_alloc_with_panic() {
+        use crate::Arena;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        use std::sync::Arc;
+
+        // Dropping an uninitialized value would decrement the counter
+        // of a random Arc
+        let value = Arc::new(1);
+        let arena = SharedArena::<Arc<usize>>::new();
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            arena.alloc_with(|_| panic!("initializer"))
+        }));
+        assert!(result.is_err());
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            arena.alloc_arc_with(|_| panic!("initializer"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(arena.stats(), (0, 63));
+
+        // The initializer can move its captures
+        let clone = value.clone();
+        let arc = arena.alloc_arc_with(move |place| place.write(clone));
+        assert_eq!(Arc::strong_count(&value), 2);
+
+        let result = arena.try_alloc_with(|place| {
+            place.write(value.clone());
+            Err(())
+        });
+        assert!(result.is_err());
+        // The value written before the error is leaked, not dropped
+        assert_eq!(Arc::strong_count(&value), 3);
+        assert_eq!(arena.stats(), (1, 62));
+
+        std::mem::drop(arc);
+        assert_eq!(arena.stats(), (0, 63));
+
+        let arena = Arena::<String>::new();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            arena.alloc_rc_with(|_| panic!("initializer"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(arena.stats(), (0, 63));
+
+        let value = arena.try_alloc_with(|place| Ok::<_, ()>(place.write(String::from("a"))));
+        assert_eq!(value.as_deref().map(String::as_str), Ok("a"));
+    }
+
+    #[test]
+    fn arena_auto_shrink() {
+        use crate::AutoShrink;
+
+        let arena = SharedArena::<usize>::with_capacity_and_shrink(63, AutoShrink::new().free_ratio(50));
+
+        // A spike of traffic on several threads
+        let values: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4).map(|_| {
+                scope.spawn(|| (0..500).map(|n| arena.alloc_arc(n)).collect::<Vec<_>>())
+            }).collect();
+            threads.into_iter().flat_map(|t| t.join().unwrap()).collect()
+        });
+        assert_eq!(arena.stats().0, 2000);
+
+        // The pages are released as the values are dropped, a page of
+        // free blocks is kept
+        std::mem::drop(values);
+        assert_eq!(arena.stats(), (0, 63));
+
+        let kept: Vec<_> = (0..1024).map(|n| arena.alloc(n)).collect();
+        let (used, free) = arena.stats();
+        assert_eq!(used, 1024);
+        assert!(free < 1024, "free={}", free);
+
+        // Never with the default policy
+        let arena = SharedArena::<usize>::with_capacity(63 * 10);
+        for n in 0..5000 {
+            arena.alloc(n);
+        }
+        assert_eq!(arena.stats(), (0, 63 * 10));
+        assert_eq!(*kept[1023], 1023);
+    }
+
+    #[test]
+    fn arena_try_shrink() {
+        use crate::common::WriterGuard;
+
+        let arena = SharedArena::<usize>::with_capacity(63 * 50);
+        let values: Vec<_> = (0..63 * 3).map(|n| arena.alloc(n)).collect();
+
+        // Never blocks
+        {
+            let _guard = WriterGuard::new(&arena.inner().writer).unwrap();
+            assert!(!arena.try_shrink(10).completed);
+        }
+
+        // The arena is usable between the calls
+        for n in 0..3 {
+            let report = arena.try_shrink(10);
+            assert!(report.completed && report.pages_freed <= 10);
+            assert_eq!(*arena.alloc(n), n);
+        }
+
+        let mut calls = 3;
+        while arena.stats().1 != 0 {
+            let report = arena.try_shrink(10);
+            assert!(report.completed && report.pages_freed <= 10);
+            calls += 1;
+            assert!(calls < 100);
+        }
+
+        // 47 pages to free, 10 per call at most
+        assert!(calls >= 5);
+        assert_eq!(arena.stats(), (189, 0));
+        assert_eq!(arena.try_shrink(100).pages_freed, 0);
+
+        // The pages released when they were full are in the pending
+        // list, they are examined after the free list
+        std::mem::drop(values);
+        let pages_freed: usize = (0..3).map(|_| arena.try_shrink(100).pages_freed).sum();
+        assert_eq!(pages_freed, 3);
+        assert_eq!(arena.stats(), (0, 0));
+
+        // It still allocates after all pages were freed
+        assert_eq!(*arena.alloc(5), 5);
+    }
+
+    #[test]
+    fn arena_try_shrink_concurrent() {
+        let arena = SharedArena::<usize>::with_capacity(63 * 20);
+
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4).map(|t| {
+                let arena = &arena;
+                scope.spawn(move || {
+                    for round in 0..50 {
+                        let values: Vec<_> = (0..100).map(|n| arena.alloc_arc(t * n + round)).collect();
+                        assert!(values.iter().enumerate().all(|(n, v)| **v == t * n + round));
+                    }
+                })
+            }).collect();
+
+            while !threads.iter().all(|t| t.is_finished()) {
+                arena.try_shrink(4);
+            }
+        });
+
+        assert_eq!(arena.stats().0, 0);
+
+        let mut calls = 0;
+        while arena.stats().1 != 0 {
+            arena.try_shrink(4);
+            calls += 1;
+            assert!(calls < 10_000);
+        }
+    }
+
+    #[test]
+    fn arena_free_list_stress() {
+        use crate::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+        let arena = SharedArena::<(usize, usize)>::with_capacity(63);
+        let stop = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            let threads: Vec<_> = (0..8).map(|thread| {
+                let arena = &arena;
+                s.spawn(move || {
+                    let mut kept = Vec::new();
+
+                    for round in 0..1_000 {
+                        // Fill and empty pages quickly, so they move between
+                        // the free list and the pending list
+                        let burst = (round * 7 + thread * 13) % 130;
+                        let mut values: Vec<_> = (0..burst).map(|n| arena.alloc((thread, n))).collect();
+
+                        for (n, value) in values.iter().enumerate() {
+                            // A block given to 2 threads would be overwritten
+                            assert_eq!(**value, (thread, n));
+                        }
+
+                        kept.extend(values.drain(..).step_by(5));
+                        if kept.len() > 200 {
+                            kept.drain(..100);
+                        }
+                    }
+                })
+            }).collect();
+
+            s.spawn(|| {
+                let mut round = 0;
+                while !stop.load(Relaxed) {
+                    if round % 16 == 0 {
+                        arena.shrink_to_fit();
+                    } else {
+                        arena.try_shrink(8);
+                    }
+                    round += 1;
+                }
+            });
+
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            stop.store(true, Relaxed);
+        });
+
+        arena.check_lists();
+
+        // Every page is back in a free list once all values are dropped
+        let (used, free) = arena.stats();
+        assert_eq!(used, 0);
+        assert_eq!(free % 63, 0);
+
+        arena.shrink_to_fit();
+        arena.check_lists();
+        assert_eq!(arena.stats().0, 0);
+
+        let value = arena.alloc((0, 0));
+        arena.check_lists();
+        assert_eq!(arena.stats().0, 1);
+        assert_eq!(*value, (0, 0));
+    }
+
+    #[test]
+    fn arena_reclaim_with_readers() {
+        use crate::reclaim::ReadGuard;
+        use crate::sync::atomic::Ordering::Relaxed;
+
+        let arena = SharedArena::<usize>::with_capacity(63 * 5);
+        let _value = arena.alloc(1);
+
+        // A thread reading the pages, while they are removed
+        let guard = ReadGuard::new(arena.inner().readers);
+        assert_eq!(arena.shrink_to_fit().pages_freed, 4);
+        assert!(!arena.inner().to_free.load(Relaxed).is_null());
+
+        // A page removed can't be used by a thread which read it before
+        let to_free = unsafe { &*arena.inner().to_free.load(Relaxed) };
+        let page = unsafe { to_free[0].as_ref() };
+        assert!(page.acquire_free_block().is_none());
+
+        // It doesn't wait for the reader
+        assert_eq!(arena.reclaim_now(), 0);
+        assert!(!arena.inner().to_free.load(Relaxed).is_null());
+
+        // The readers of another arena don't delay it
+        let other = SharedArena::<usize>::with_capacity(63 * 2);
+        assert_eq!(other.shrink_to_fit().pages_freed, 2);
+        assert!(other.inner().to_free.load(Relaxed).is_null());
+
+        // The pages removed are not reused while they are read
+        let values: Vec<_> = (0..63 * 2).map(|n| arena.alloc(n)).collect();
+        assert!(!arena.inner().to_free.load(Relaxed).is_null());
+
+        drop(guard);
+        assert_eq!(arena.reclaim_now(), 4);
+        assert!(arena.inner().to_free.load(Relaxed).is_null());
+        assert_eq!(arena.reclaim_now(), 0);
+        assert_eq!(arena.stats().0, 127);
+        drop(values);
+    }
+
+    #[test]
+    fn arena_drop_with_readers() {
+        use crate::reclaim::ReadGuard;
+
+        let arena = SharedArena::<usize>::with_capacity(63 * 3);
+        let value = arena.alloc(1);
+        let readers = arena.inner().readers;
+        // The arena and its pages
+        assert_eq!(readers.owners(), 4);
+
+        // A thread reading the pages while the arena is dropped
+        let guard = ReadGuard::new(readers);
+        drop(arena);
+        assert_eq!(readers.owners(), 3);
+
+        // The reader deallocates the pages fully free, the page of
+        // the value is deallocated by its release
+        drop(guard);
+        assert_eq!(readers.owners(), 1);
+        assert_eq!(*value, 1);
+        drop(value);
+    }
+
+    #[test]
+    fn arena_drop_concurrent_release() {
+        use std::sync::{Arc, Barrier};
+
+        // The blocks of a page are released by two threads once the
+        // arena is dropped: the last one defers the deallocation of
+        // the page while the other might still read it
+        for _ in 0..200 {
+            let arena = SharedArena::<usize>::new();
+            let barrier = Arc::new(Barrier::new(3));
+
+            let threads: Vec<_> = (0..2).map(|n| {
+                let values: Vec<_> = (0..31).map(|i| arena.alloc_arc(n * 31 + i)).collect();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    values.iter().map(|v| **v).sum::<usize>()
+                })
+            }).collect();
+
+            drop(arena);
+            barrier.wait();
+
+            let sum: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+            assert_eq!(sum, 61 * 31);
+        }
+    }
+
     #[cfg(target_pointer_width = "64") ]
     #[test]
     fn arena_size() {
@@ -1245,6 +2878,7 @@ mod tests {
         test_with_threads(12, 64, true);
     }
 
+    #[allow(clippy::collapsible_if, clippy::manual_is_multiple_of)]
     fn test_with_threads(nthreads: usize, nallocs: usize, with_shrink: bool) {
         use std::sync::{Arc, Barrier};
         use std::thread;
@@ -1287,7 +2921,7 @@ mod tests {
                         values.remove(rand);
                     }
                     if with_shrink && rand % 200 == 0 {
-                        if arena.shrink_to_fit() {
+                        if arena.shrink_to_fit().completed {
                             // nshrink += 1;
                         }
                     }
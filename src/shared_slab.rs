@@ -0,0 +1,483 @@
+
+use crate::sync::atomic::Ordering::*;
+use crate::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+
+use crate::common::{BLOCK_PER_PAGE, WriterGuard};
+use crate::page::slab::{PageSlab, Slot};
+use crate::page_table::PageTable;
+
+/// A key to a value in a [`SharedSlab`]
+///
+/// It's made of the index of the page in the slab, the index of
+/// the slot in that page and the generation of the slot.
+///
+/// When the value is removed from the slab, the generation of its
+/// slot changes: the key becomes stale and won't give access to
+/// another value inserted at the same place.
+///
+/// [`SharedSlab`]: ./struct.SharedSlab.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlabKey {
+    page: u32,
+    generation: u32,
+    index: u8,
+}
+
+/// A slab shareable across threads, accessed with generational keys
+///
+/// Unlike the arenas, `SharedSlab` doesn't return pointers: it returns
+/// a [`SlabKey`] which is `Copy` and must be given back to the slab
+/// to access the value.
+///
+/// Values can be inserted and read concurrently by many threads.
+/// Removing a value requires an exclusive access to the slab.
+///
+/// Like the arenas, the slab allocates by pages of 63 elements.
+///
+/// ## Example
+///
+/// ```
+/// use shared_arena::SharedSlab;
+///
+/// let mut slab = SharedSlab::new();
+///
+/// let key = slab.insert("hello");
+/// assert_eq!(slab.get(key), Some(&"hello"));
+///
+/// assert_eq!(slab.remove(key), Some("hello"));
+///
+/// // The key is now stale
+/// assert_eq!(slab.get(key), None);
+///
+/// // Even if the slot is reused
+/// let key2 = slab.insert("world");
+/// assert_eq!(slab.get(key), None);
+/// assert_eq!(slab.get(key2), Some(&"world"));
+/// ```
+///
+/// [`SlabKey`]: ./struct.SlabKey.html
+pub struct SharedSlab<T: Sized> {
+    free_list: AtomicPtr<PageSlab<T>>,
+    pages: PageTable<PageSlab<T>>,
+    npages: AtomicUsize,
+    writer: AtomicBool,
+}
+
+unsafe impl<T: Send> Send for SharedSlab<T> {}
+unsafe impl<T: Send + Sync> Sync for SharedSlab<T> {}
+
+impl<T: Sized> SharedSlab<T> {
+    /// Constructs a new `SharedSlab` capable of holding at least `cap` elements
+    ///
+    /// Because the slab allocate by page of 63 elements, it might be able to
+    /// hold more elements than `cap`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let slab = SharedSlab::with_capacity(2048);
+    /// # slab.insert(1);
+    /// ```
+    pub fn with_capacity(cap: usize) -> SharedSlab<T> {
+        let npages = ((cap.max(1) - 1) / BLOCK_PER_PAGE) + 1;
+
+        let slab = SharedSlab {
+            free_list: AtomicPtr::new(std::ptr::null_mut()),
+            pages: PageTable::new(),
+            npages: AtomicUsize::new(0),
+            writer: AtomicBool::new(false),
+        };
+
+        slab.alloc_new_pages(npages);
+        slab
+    }
+
+    /// Constructs a new `SharedSlab` capable of holding exactly 63 elements
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let slab = SharedSlab::new();
+    /// # slab.insert(1);
+    /// ```
+    pub fn new() -> SharedSlab<T> {
+        SharedSlab::with_capacity(BLOCK_PER_PAGE)
+    }
+
+    /// Must be called with the writer lock held, or with an exclusive
+    /// access to the slab
+    fn alloc_new_pages(&self, npages: usize) {
+        let mut first = std::ptr::null_mut();
+
+        for _ in 0..npages {
+            let id = self.pages.len() as u32;
+            let page = PageSlab::new(id, first);
+            let pushed = self.pages.push(page);
+            debug_assert_eq!(id, pushed);
+            first = page.as_ptr();
+        }
+
+        self.npages.fetch_add(npages, Relaxed);
+
+        let old = self.free_list.swap(first, AcqRel);
+        assert!(old.is_null(), "SharedSlab.free_list isn't null");
+    }
+
+    fn find_slot(&self) -> (&PageSlab<T>, usize) {
+        loop {
+            while let Some(page) = unsafe { self.free_list.load(Acquire).as_ref() } {
+
+                if let Some(index) = page.acquire_free_slot() {
+                    return (page, index);
+                }
+
+                // No free slot on the page, we remove it from the free list
+
+                let next = page.next_free.load(Acquire);
+                if self.free_list.compare_exchange(
+                    page as *const _ as *mut _, next, AcqRel, Relaxed
+                ).is_ok() {
+                    page.in_free_list.store(false, Release);
+                }
+            }
+
+            if let Some(_guard) = WriterGuard::new(&self.writer) {
+                if self.free_list.load(Acquire).is_null() {
+                    let to_allocate = self.npages
+                                          .load(Relaxed)
+                                          .clamp(1, 900_000);
+
+                    self.alloc_new_pages(to_allocate);
+                }
+                continue;
+            }
+
+            crate::sync::yield_now();
+        }
+    }
+
+    fn slot(&self, key: SlabKey) -> Option<&Slot<T>> {
+        let page = self.pages.get(key.page)?;
+        let page = unsafe { &*page.as_ptr() };
+
+        page.slots.get(key.index as usize)
+    }
+
+    /// Inserts a value in the slab, and returns its key
+    ///
+    /// It can be called concurrently by many threads.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let slab = SharedSlab::new();
+    /// let key = slab.insert(0xFF);
+    ///
+    /// assert_eq!(slab.get(key), Some(&255));
+    /// ```
+    pub fn insert(&self, value: T) -> SlabKey {
+        let (page, index) = self.find_slot();
+        let slot = &page.slots[index];
+
+        unsafe {
+            (*slot.value.get()).as_mut_ptr().write(value);
+        }
+
+        // The slot is vacant (even), the new generation is odd.
+        // Release: the value must be visible to the threads that
+        // read this generation
+        let generation = slot.generation.load(Relaxed).wrapping_add(1);
+        slot.generation.store(generation, Release);
+
+        SlabKey {
+            page: page.id,
+            generation,
+            index: index as u8,
+        }
+    }
+
+    /// Returns a reference to the value of `key`
+    ///
+    /// Returns `None` if the value has been removed from the slab.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let slab = SharedSlab::new();
+    /// let key = slab.insert(Some(10));
+    ///
+    /// assert_eq!(slab.get(key), Some(&Some(10)));
+    /// ```
+    pub fn get(&self, key: SlabKey) -> Option<&T> {
+        let slot = self.slot(key)?;
+
+        if slot.generation.load(Acquire) != key.generation {
+            return None;
+        }
+
+        Some(unsafe { &*(*slot.value.get()).as_ptr() })
+    }
+
+    /// Returns a mutable reference to the value of `key`
+    ///
+    /// Returns `None` if the value has been removed from the slab.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let mut slab = SharedSlab::new();
+    /// let key = slab.insert(10);
+    ///
+    /// *slab.get_mut(key).unwrap() += 1;
+    /// assert_eq!(slab.get(key), Some(&11));
+    /// ```
+    pub fn get_mut(&mut self, key: SlabKey) -> Option<&mut T> {
+        let slot = self.slot(key)?;
+
+        if slot.generation.load(Relaxed) != key.generation {
+            return None;
+        }
+
+        Some(unsafe { &mut *(*slot.value.get()).as_mut_ptr() })
+    }
+
+    /// Returns `true` if the slab contains a value for `key`
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let mut slab = SharedSlab::new();
+    /// let key = slab.insert(10);
+    ///
+    /// assert!(slab.contains_key(key));
+    /// slab.remove(key);
+    /// assert!(!slab.contains_key(key));
+    /// ```
+    pub fn contains_key(&self, key: SlabKey) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Removes the value of `key` from the slab and returns it
+    ///
+    /// Returns `None` if the value has already been removed.
+    /// The slot can then be reused by another value, but `key` will
+    /// never give access to it.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let mut slab = SharedSlab::new();
+    /// let key = slab.insert(10);
+    ///
+    /// assert_eq!(slab.remove(key), Some(10));
+    /// assert_eq!(slab.remove(key), None);
+    /// ```
+    pub fn remove(&mut self, key: SlabKey) -> Option<T> {
+        let page = self.pages.get(key.page)?;
+        let page = unsafe { &*page.as_ptr() };
+        let slot = page.slots.get(key.index as usize)?;
+
+        if slot.generation.load(Relaxed) != key.generation {
+            return None;
+        }
+
+        let value = unsafe { (*slot.value.get()).as_ptr().read() };
+
+        // The slot is now vacant (even)
+        slot.generation.store(key.generation.wrapping_add(1), Release);
+
+        page.release_slot(key.index as usize);
+
+        // We have an exclusive access: no other thread is touching
+        // the free list
+        if !page.in_free_list.load(Relaxed) {
+            page.in_free_list.store(true, Relaxed);
+            page.next_free.store(self.free_list.load(Relaxed), Relaxed);
+            self.free_list.store(page as *const _ as *mut _, Relaxed);
+        }
+
+        Some(value)
+    }
+
+    /// Returns a tuple of non-free and free spaces in the slab
+    ///
+    /// This is a slow function and it should not be called in a hot
+    /// path.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::SharedSlab;
+    /// let slab = SharedSlab::new();
+    /// let key = slab.insert(1);
+    /// let (used, free) = slab.stats();
+    /// assert!(used == 1 && free == 62);
+    /// ```
+    pub fn stats(&self) -> (usize, usize) {
+        let mut used = 0;
+        let mut npages = 0;
+
+        for page in self.pages.iter() {
+            let page = unsafe { page.as_ref() };
+            used += page.bitfield.load(Relaxed).count_zeros() as usize;
+            npages += 1;
+        }
+
+        let free = (npages * BLOCK_PER_PAGE) - used;
+
+        (used, free)
+    }
+}
+
+impl<T> Drop for SharedSlab<T> {
+    fn drop(&mut self) {
+        for page in self.pages.iter() {
+            PageSlab::drop_page(page);
+        }
+    }
+}
+
+impl<T: Sized> Default for SharedSlab<T> {
+    fn default() -> SharedSlab<T> {
+        SharedSlab::new()
+    }
+}
+
+impl<T> std::fmt::Debug for SharedSlab<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let npages = self.npages.load(Relaxed);
+        let mut blocks_used = 0;
+
+        for page in self.pages.iter() {
+            let page = unsafe { page.as_ref() };
+            blocks_used += page.bitfield.load(Relaxed).count_zeros() as usize;
+        }
+
+        f.debug_struct("SharedSlab")
+         .field("blocks_free", &(npages * BLOCK_PER_PAGE - blocks_used))
+         .field("blocks_used", &blocks_used)
+         .field("npages", &npages)
+         .finish()
+    }
+}
+
+/// Code that should fail to compile.
+/// compile_fail is supported on doc only
+///
+/// Fails because the key is used while a reference from `get` is alive
+/// ```compile_fail
+/// use shared_arena::SharedSlab;
+///
+/// let mut slab = SharedSlab::new();
+/// let key = slab.insert(1);
+/// let value = slab.get(key).unwrap();
+/// slab.remove(key);
+/// println!("{}", value);
+/// ```
+#[allow(dead_code)]
+fn slab_fail() {} // grcov_ignore
+
+#[cfg(test)]
+mod tests {
+    use super::SharedSlab;
+
+    #[test]
+    fn slab_stale_key() {
+        let mut slab = SharedSlab::<usize>::new();
+
+        let key = slab.insert(1);
+        assert_eq!(slab.remove(key), Some(1));
+
+        // The slot is reused with another generation
+        let key2 = slab.insert(2);
+        assert_eq!(key.page, key2.page);
+        assert_eq!(key.index, key2.index);
+        assert_ne!(key.generation, key2.generation);
+
+        assert_eq!(slab.get(key), None);
+        assert_eq!(slab.get_mut(key), None);
+        assert_eq!(slab.remove(key), None);
+        assert_eq!(slab.get(key2), Some(&2));
+    }
+
+    #[test]
+    fn slab_grow() {
+        let mut slab = SharedSlab::<usize>::new();
+
+        let keys: Vec<_> = (0..1000).map(|i| slab.insert(i)).collect();
+
+        assert_eq!(slab.stats(), (1000, 8));
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(slab.get(*key), Some(&i));
+        }
+
+        for (i, key) in keys.iter().enumerate().step_by(2) {
+            assert_eq!(slab.remove(*key), Some(i));
+        }
+
+        assert_eq!(slab.stats(), (500, 508));
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(slab.contains_key(*key), i % 2 == 1);
+        }
+
+        let _keys: Vec<_> = (0..508).map(|i| slab.insert(i)).collect();
+        assert_eq!(slab.stats(), (1008, 0));
+    }
+
+    #[test]
+    fn slab_drop_values() {
+        use std::rc::Rc;
+
+        let value = Rc::new(1);
+
+        {
+            let mut slab = SharedSlab::new();
+            let key = slab.insert(value.clone());
+            slab.insert(value.clone());
+            assert_eq!(Rc::strong_count(&value), 3);
+
+            slab.remove(key);
+            assert_eq!(Rc::strong_count(&value), 2);
+        }
+
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn slab_with_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let slab = Arc::new(SharedSlab::<usize>::new());
+
+        let handles: Vec<_> = (0..8).map(|n| {
+            let slab = slab.clone();
+            thread::spawn(move || {
+                let keys: Vec<_> = (0..500).map(|i| (slab.insert(n * 1000 + i), n * 1000 + i)).collect();
+                for (key, value) in &keys {
+                    assert_eq!(slab.get(*key), Some(value));
+                }
+                keys
+            })
+        }).collect();
+
+        let keys: Vec<_> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+
+        let mut slab = Arc::try_unwrap(slab).unwrap();
+
+        for (key, value) in keys {
+            assert_eq!(slab.remove(key), Some(value));
+        }
+
+        assert_eq!(slab.stats().0, 0);
+    }
+}
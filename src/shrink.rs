@@ -0,0 +1,383 @@
+//! Shrinking of the arenas: reports of `shrink_to_fit` and the
+//! automatic policy
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use crate::common::BLOCK_PER_PAGE;
+use crate::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
+
+/// What a call to `shrink_to_fit` released
+///
+/// Returned by [`SharedArena::shrink_to_fit`], [`SharedArena::try_shrink`],
+/// [`Arena::shrink_to_fit`] and [`Pool::shrink_to_fit`].
+///
+/// ## Example
+///
+/// ```
+/// # use shared_arena::{Arena, ShrinkReport};
+/// let arena = Arena::<usize>::with_capacity(630);
+/// let value = arena.alloc(1);
+///
+/// let report: ShrinkReport = arena.shrink_to_fit();
+///
+/// assert!(report.completed);
+/// assert_eq!(report.pages_freed, 9);
+/// assert!(report.bytes_released > 9 * 63 * std::mem::size_of::<usize>());
+/// ```
+///
+/// [`SharedArena::shrink_to_fit`]: ./struct.SharedArena.html#method.shrink_to_fit
+/// [`SharedArena::try_shrink`]: ./struct.SharedArena.html#method.try_shrink
+/// [`Arena::shrink_to_fit`]: ./struct.Arena.html#method.shrink_to_fit
+/// [`Pool::shrink_to_fit`]: ./struct.Pool.html#method.shrink_to_fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShrinkReport {
+    /// Number of pages removed from the arena
+    pub pages_freed: usize,
+    /// Size in bytes of the pages removed
+    pub bytes_released: usize,
+    /// `false` when nothing was done because the arena was already
+    /// shrinking in another thread, or, with `try_shrink`, because
+    /// it would have blocked (`SharedArena` only)
+    pub completed: bool,
+}
+
+impl ShrinkReport {
+    /// Report of `npages` pages of type `P` removed
+    pub(crate) fn pages<P>(npages: usize) -> ShrinkReport {
+        ShrinkReport {
+            pages_freed: npages,
+            bytes_released: npages * std::mem::size_of::<P>(),
+            completed: true,
+        }
+    }
+
+    /// Report of a shrink that didn't run
+    pub(crate) fn skipped() -> ShrinkReport {
+        ShrinkReport {
+            pages_freed: 0,
+            bytes_released: 0,
+            completed: false,
+        }
+    }
+}
+
+/// Checks between two evaluations of an `AutoShrink` without
+/// `idle_allocations`
+const DEFAULT_INTERVAL: usize = 1024;
+
+/// Pages examined by each step of an automatic shrink
+pub(crate) const AUTO_SHRINK_BUDGET: usize = 16;
+
+/// A policy to shrink an arena automatically
+///
+/// It's set at construction, with `with_capacity_and_shrink` on
+/// [`SharedArena`], [`Arena`] and [`Pool`].
+///
+/// The arena counts its blocks in use as they are allocated and
+/// released, the policy doesn't walk the pages. The pages with no
+/// value in use are released in small steps, each one examining a
+/// few pages, like with [`SharedArena::try_shrink`]:
+///
+/// - With [`free_ratio`], while the percentage of free blocks in the
+///   arena is above the threshold, and more than a page of blocks is
+///   free. A `SharedArena` takes a step when a value is dropped and
+///   its page becomes fully free. `Arena` and `Pool`, whose values
+///   might be dropped from other threads, take it at their next
+///   allocation.
+/// - With [`idle_allocations`], every `idle_allocations` allocations,
+///   the pages which were already fully free at their previous step.
+///
+/// The ratio is also checked every `idle_allocations` allocations, or
+/// every 1024 allocations without it.  
+/// The default policy never shrinks the arena.
+///
+/// ## Example
+///
+/// ```
+/// # use shared_arena::{AutoShrink, Pool};
+/// let policy = AutoShrink::new().idle_allocations(100);
+/// let pool = Pool::with_capacity_and_shrink(63, policy);
+///
+/// // A spike of traffic
+/// let values: Vec<_> = (0..63 * 10).map(|n| pool.alloc(n)).collect();
+/// std::mem::drop(values);
+/// assert!(pool.stats().1 >= 63 * 10);
+///
+/// for n in 0..200 {
+///     pool.alloc(n);
+/// }
+///
+/// // The pages idle since the spike have been released
+/// assert_eq!(pool.stats(), (0, 63));
+/// ```
+///
+/// [`SharedArena`]: ./struct.SharedArena.html
+/// [`Arena`]: ./struct.Arena.html
+/// [`Pool`]: ./struct.Pool.html
+/// [`SharedArena::try_shrink`]: ./struct.SharedArena.html#method.try_shrink
+/// [`free_ratio`]: #method.free_ratio
+/// [`idle_allocations`]: #method.idle_allocations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AutoShrink {
+    free_ratio: Option<u8>,
+    idle_allocations: Option<NonZeroUsize>,
+}
+
+/// Pages to release after an evaluation of `AutoShrink`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShrinkAction {
+    /// All fully free pages
+    All,
+    /// Fully free pages which were already fully free at the
+    /// previous evaluation
+    Idle,
+}
+
+impl AutoShrink {
+    /// A policy which never shrinks the arena
+    pub const fn new() -> AutoShrink {
+        AutoShrink { free_ratio: None, idle_allocations: None }
+    }
+
+    /// Releases the fully free pages while more than `percent`% of the
+    /// blocks in the arena are free
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `percent` is greater than 100
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use shared_arena::{AutoShrink, Arena};
+    /// let arena = Arena::with_capacity_and_shrink(63, AutoShrink::new().free_ratio(50));
+    ///
+    /// let values: Vec<_> = (0..63 * 4).map(|n| arena.alloc(n)).collect();
+    /// std::mem::drop(values);
+    ///
+    /// for _ in 0..1024 {
+    ///     arena.alloc(1);
+    /// }
+    ///
+    /// assert_eq!(arena.stats(), (0, 63));
+    /// ```
+    pub const fn free_ratio(self, percent: u8) -> AutoShrink {
+        assert!(percent <= 100, "AutoShrink: free ratio above 100%");
+        AutoShrink { free_ratio: Some(percent), ..self }
+    }
+
+    /// Releases the fully free pages which stayed unused for
+    /// `allocations` allocations
+    ///
+    /// A page is considered unused when it is fully free at two
+    /// steps of the shrink, at least `allocations` allocations apart.
+    /// Zero disables it.
+    pub const fn idle_allocations(self, allocations: usize) -> AutoShrink {
+        AutoShrink { idle_allocations: NonZeroUsize::new(allocations), ..self }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.free_ratio.is_some() || self.idle_allocations.is_some()
+    }
+
+    /// Returns `true` when more than `free_ratio` percent of the
+    /// blocks are free, and more than a page of blocks
+    pub(crate) fn is_above_ratio(&self, used: usize, npages: usize) -> bool {
+        let capacity = npages * BLOCK_PER_PAGE;
+        let free = capacity.saturating_sub(used);
+
+        match self.free_ratio {
+            Some(percent) => free > BLOCK_PER_PAGE && free * 100 > capacity * percent as usize,
+            None => false
+        }
+    }
+
+    /// Evaluates the policy after the `allocations`th allocation, with
+    /// `used` blocks in use in `npages` pages
+    #[allow(clippy::manual_is_multiple_of)]
+    pub(crate) fn evaluate(
+        &self,
+        allocations: usize,
+        used: usize,
+        npages: usize
+    ) -> Option<ShrinkAction>
+    {
+        let interval = self.idle_allocations.map(NonZeroUsize::get).unwrap_or(DEFAULT_INTERVAL);
+
+        if !self.is_enabled() || allocations % interval != 0 {
+            return None;
+        }
+
+        if self.is_above_ratio(used, npages) {
+            return Some(ShrinkAction::All);
+        }
+
+        self.idle_allocations.map(|_| ShrinkAction::Idle)
+    }
+}
+
+/// State of the `AutoShrink` policy of an arena
+///
+/// It's shared by the arena and its pages: the pages count the
+/// blocks released, from any thread, the arena the blocks allocated
+/// and its pages. The free ratio is known without walking the pages.
+pub(crate) struct ShrinkState {
+    policy: AutoShrink,
+    /// Blocks in use
+    used: AtomicUsize,
+    /// Pages of the arena
+    npages: AtomicUsize,
+    allocations: AtomicUsize,
+    /// Blocks in use at or below which a release requests a step: a
+    /// page of blocks below the previous request, or below the blocks
+    /// in use while they grow
+    watermark: AtomicUsize,
+    /// The free ratio is above the threshold, the arena releases its
+    /// fully free pages at its next allocation
+    requested: AtomicBool,
+}
+
+impl ShrinkState {
+    /// Returns the state of `policy`, `None` when it never shrinks
+    pub(crate) fn new(policy: AutoShrink, npages: usize) -> Option<Arc<ShrinkState>> {
+        if !policy.is_enabled() {
+            return None;
+        }
+
+        Some(Arc::new(ShrinkState {
+            policy,
+            used: AtomicUsize::new(0),
+            npages: AtomicUsize::new(npages),
+            allocations: AtomicUsize::new(0),
+            watermark: AtomicUsize::new(0),
+            requested: AtomicBool::new(false),
+        }))
+    }
+
+    pub(crate) fn add_pages(&self, npages: usize) {
+        self.npages.fetch_add(npages, Relaxed);
+    }
+
+    pub(crate) fn remove_pages(&self, npages: usize) {
+        self.npages.fetch_sub(npages, Relaxed);
+    }
+
+    pub(crate) fn is_above_ratio(&self) -> bool {
+        self.policy.is_above_ratio(self.used.load(Relaxed), self.npages.load(Relaxed))
+    }
+
+    /// Counts an allocation
+    ///
+    /// Returns the pages to release, if the policy is evaluated or a
+    /// release requested it
+    pub(crate) fn allocated(&self) -> Option<ShrinkAction> {
+        let used = self.used.fetch_add(1, Relaxed).wrapping_add(1);
+        let allocations = self.allocations.fetch_add(1, Relaxed).wrapping_add(1);
+
+        // Written once by page of blocks allocated
+        if used >= self.watermark.load(Relaxed) + 2 * BLOCK_PER_PAGE {
+            self.watermark.store(used - BLOCK_PER_PAGE, Relaxed);
+        }
+
+        // Loaded first to not write its cache line on each call
+        if self.requested.load(Relaxed) && self.requested.swap(false, Relaxed) {
+            return Some(ShrinkAction::All);
+        }
+
+        self.policy.evaluate(allocations, used, self.npages.load(Relaxed))
+    }
+
+    /// Counts a release, from any thread
+    ///
+    /// Returns `true` when the free ratio is above the threshold and a
+    /// page of blocks was released since the previous request: the
+    /// fully free pages are requested to be released
+    pub(crate) fn released(&self) -> bool {
+        let used = self.used.fetch_sub(1, Relaxed).wrapping_sub(1);
+
+        if used > self.watermark.load(Relaxed) || !self.policy.is_above_ratio(used, self.npages.load(Relaxed)) {
+            return false;
+        }
+
+        self.watermark.store(used.saturating_sub(BLOCK_PER_PAGE), Relaxed);
+        self.requested.store(true, Relaxed);
+        true
+    }
+
+    /// Requests another step after a step releasing all the fully free
+    /// pages, if it didn't run or if the ratio is still above the
+    /// threshold
+    pub(crate) fn shrunk(&self, report: &ShrinkReport) {
+        let again = !report.completed || (report.pages_freed != 0 && self.is_above_ratio());
+        self.requested.store(again, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoShrink, ShrinkAction, ShrinkReport, ShrinkState};
+
+    #[test]
+    fn auto_shrink_evaluate() {
+        let never = AutoShrink::default();
+        assert!(!never.is_enabled());
+        assert_eq!(never.evaluate(1024, 0, 10), None);
+        assert!(ShrinkState::new(never, 1).is_none());
+
+        let ratio = AutoShrink::new().free_ratio(50);
+        assert_eq!(ratio.evaluate(1000, 0, 10), None);
+        assert_eq!(ratio.evaluate(1024, 63 * 4, 10), Some(ShrinkAction::All));
+        assert_eq!(ratio.evaluate(2048, 63 * 5, 10), None);
+        // A single page free is kept
+        assert_eq!(ratio.evaluate(1024, 0, 1), None);
+        assert!(!AutoShrink::new().free_ratio(0).is_above_ratio(63, 2));
+        assert!(AutoShrink::new().free_ratio(0).is_above_ratio(62, 2));
+
+        let idle = AutoShrink::new().idle_allocations(10);
+        assert_eq!(idle.evaluate(5, 0, 10), None);
+        assert_eq!(idle.evaluate(20, 0, 10), Some(ShrinkAction::Idle));
+
+        let both = idle.free_ratio(0);
+        assert_eq!(both.evaluate(10, 63 * 2, 2), Some(ShrinkAction::Idle));
+        assert_eq!(both.evaluate(10, 0, 2), Some(ShrinkAction::All));
+
+        assert_eq!(AutoShrink::new().idle_allocations(0), AutoShrink::new());
+    }
+
+    #[test]
+    fn shrink_state() {
+        let state = ShrinkState::new(AutoShrink::new().free_ratio(50), 4).unwrap();
+
+        for _ in 0..63 * 4 {
+            assert_eq!(state.allocated(), None);
+        }
+
+        // Once the ratio is above the threshold, a request by page of
+        // blocks released
+        let requests = (0..63 * 4).filter(|_| state.released()).count();
+        assert_eq!(requests, 3);
+        assert!(state.is_above_ratio());
+        assert_eq!(state.allocated(), Some(ShrinkAction::All));
+        assert_eq!(state.allocated(), None);
+
+        // Another step while pages are released
+        state.remove_pages(2);
+        state.shrunk(&ShrinkReport::pages::<u8>(2));
+        assert_eq!(state.allocated(), Some(ShrinkAction::All));
+        state.remove_pages(1);
+        state.shrunk(&ShrinkReport::pages::<u8>(1));
+        assert!(!state.is_above_ratio());
+        assert_eq!(state.allocated(), None);
+
+        // Or if the step didn't run
+        state.shrunk(&ShrinkReport::skipped());
+        assert_eq!(state.allocated(), Some(ShrinkAction::All));
+    }
+
+    #[test]
+    #[should_panic]
+    fn auto_shrink_invalid_ratio() {
+        let _ = AutoShrink::new().free_ratio(101);
+    }
+}
@@ -0,0 +1,322 @@
+//! Implementations of std traits for the handles, forwarding to the
+//! inner value like `Box`, `Rc` and `Arc` do.
+
+use std::borrow::{Borrow, BorrowMut};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::iter::FusedIterator;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{ArenaArc, ArenaBox, ArenaRc, Kind, PoolBox};
+
+/// Traits implemented by all the handles
+///
+/// `$kind` names the parameter of the [`Kind`] of the handle, if it
+/// has one.
+macro_rules! impl_shared_traits {
+    ($handle:ident $(, $kind:ident)?) => {
+        impl<T: ?Sized + PartialEq $(, $kind: Kind<T>)?> PartialEq for $handle<T $(, $kind)?> {
+            #[inline]
+            fn eq(&self, other: &Self) -> bool {
+                PartialEq::eq(&**self, &**other)
+            }
+        }
+
+        impl<T: ?Sized + Eq $(, $kind: Kind<T>)?> Eq for $handle<T $(, $kind)?> {}
+
+        impl<T: ?Sized + PartialOrd $(, $kind: Kind<T>)?> PartialOrd for $handle<T $(, $kind)?> {
+            #[inline]
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                PartialOrd::partial_cmp(&**self, &**other)
+            }
+            #[inline]
+            fn lt(&self, other: &Self) -> bool {
+                PartialOrd::lt(&**self, &**other)
+            }
+            #[inline]
+            fn le(&self, other: &Self) -> bool {
+                PartialOrd::le(&**self, &**other)
+            }
+            #[inline]
+            fn gt(&self, other: &Self) -> bool {
+                PartialOrd::gt(&**self, &**other)
+            }
+            #[inline]
+            fn ge(&self, other: &Self) -> bool {
+                PartialOrd::ge(&**self, &**other)
+            }
+        }
+
+        impl<T: ?Sized + Ord $(, $kind: Kind<T>)?> Ord for $handle<T $(, $kind)?> {
+            #[inline]
+            fn cmp(&self, other: &Self) -> Ordering {
+                Ord::cmp(&**self, &**other)
+            }
+        }
+
+        impl<T: ?Sized + Hash $(, $kind: Kind<T>)?> Hash for $handle<T $(, $kind)?> {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                (**self).hash(state)
+            }
+        }
+
+        impl<T: ?Sized $(, $kind: Kind<T>)?> Borrow<T> for $handle<T $(, $kind)?> {
+            fn borrow(&self) -> &T {
+                self
+            }
+        }
+
+        impl<T: ?Sized $(, $kind: Kind<T>)?> AsRef<T> for $handle<T $(, $kind)?> {
+            fn as_ref(&self) -> &T {
+                self
+            }
+        }
+
+        impl<T: ?Sized + Error $(, $kind: Kind<T>)?> Error for $handle<T $(, $kind)?> {
+            fn source(&self) -> Option<&(dyn Error + 'static)> {
+                Error::source(&**self)
+            }
+        }
+
+        // The value is never moved by the handle
+        impl<T: ?Sized $(, $kind: Kind<T>)?> Unpin for $handle<T $(, $kind)?> {}
+    };
+}
+
+/// Traits implemented by the handles with a unique access to the
+/// value, like `Box`
+macro_rules! impl_unique_traits {
+    ($handle:ident $(, $kind:ident)?) => {
+        impl<T: ?Sized $(, $kind: Kind<T>)?> BorrowMut<T> for $handle<T $(, $kind)?> {
+            fn borrow_mut(&mut self) -> &mut T {
+                self
+            }
+        }
+
+        impl<T: ?Sized $(, $kind: Kind<T>)?> AsMut<T> for $handle<T $(, $kind)?> {
+            fn as_mut(&mut self) -> &mut T {
+                self
+            }
+        }
+
+        impl<I: ?Sized + Iterator $(, $kind: Kind<I>)?> Iterator for $handle<I $(, $kind)?> {
+            type Item = I::Item;
+
+            fn next(&mut self) -> Option<I::Item> {
+                (**self).next()
+            }
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                (**self).size_hint()
+            }
+            fn nth(&mut self, n: usize) -> Option<I::Item> {
+                (**self).nth(n)
+            }
+        }
+
+        impl<I: ?Sized + DoubleEndedIterator $(, $kind: Kind<I>)?> DoubleEndedIterator for $handle<I $(, $kind)?> {
+            fn next_back(&mut self) -> Option<I::Item> {
+                (**self).next_back()
+            }
+            fn nth_back(&mut self, n: usize) -> Option<I::Item> {
+                (**self).nth_back(n)
+            }
+        }
+
+        impl<I: ?Sized + ExactSizeIterator $(, $kind: Kind<I>)?> ExactSizeIterator for $handle<I $(, $kind)?> {
+            fn len(&self) -> usize {
+                (**self).len()
+            }
+        }
+
+        impl<I: ?Sized + FusedIterator $(, $kind: Kind<I>)?> FusedIterator for $handle<I $(, $kind)?> {}
+
+        impl<R: ?Sized + io::Read $(, $kind: Kind<R>)?> io::Read for $handle<R $(, $kind)?> {
+            #[inline]
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                (**self).read(buf)
+            }
+            #[inline]
+            fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+                (**self).read_vectored(bufs)
+            }
+            #[inline]
+            fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+                (**self).read_to_end(buf)
+            }
+            #[inline]
+            fn read_to_string(&mut self, buf: &mut String) -> io::Result<usize> {
+                (**self).read_to_string(buf)
+            }
+            #[inline]
+            fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+                (**self).read_exact(buf)
+            }
+        }
+
+        impl<B: ?Sized + io::BufRead $(, $kind: Kind<B>)?> io::BufRead for $handle<B $(, $kind)?> {
+            #[inline]
+            fn fill_buf(&mut self) -> io::Result<&[u8]> {
+                (**self).fill_buf()
+            }
+            #[inline]
+            fn consume(&mut self, amt: usize) {
+                (**self).consume(amt)
+            }
+            #[inline]
+            fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> io::Result<usize> {
+                (**self).read_until(byte, buf)
+            }
+            #[inline]
+            fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+                (**self).read_line(buf)
+            }
+        }
+
+        impl<W: ?Sized + io::Write $(, $kind: Kind<W>)?> io::Write for $handle<W $(, $kind)?> {
+            #[inline]
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                (**self).write(buf)
+            }
+            #[inline]
+            fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+                (**self).write_vectored(bufs)
+            }
+            #[inline]
+            fn flush(&mut self) -> io::Result<()> {
+                (**self).flush()
+            }
+            #[inline]
+            fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+                (**self).write_all(buf)
+            }
+            #[inline]
+            fn write_fmt(&mut self, fmt: std::fmt::Arguments<'_>) -> io::Result<()> {
+                (**self).write_fmt(fmt)
+            }
+        }
+
+        impl<S: ?Sized + io::Seek $(, $kind: Kind<S>)?> io::Seek for $handle<S $(, $kind)?> {
+            #[inline]
+            fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+                (**self).seek(pos)
+            }
+        }
+
+        // `Pin<$handle<F>>` is a `Future` for any `F: Future`, with the
+        // implementation of std for `Pin<P>`
+        impl<F: ?Sized + Future + Unpin $(, $kind: Kind<F>)?> Future for $handle<F $(, $kind)?> {
+            type Output = F::Output;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+                F::poll(Pin::new(&mut **self), cx)
+            }
+        }
+    };
+}
+
+impl_shared_traits!(ArenaBox, K);
+impl_shared_traits!(ArenaArc, K);
+impl_shared_traits!(ArenaRc, K);
+impl_shared_traits!(PoolBox);
+
+impl_unique_traits!(ArenaBox, K);
+impl_unique_traits!(PoolBox);
+
+#[cfg(test)]
+mod tests {
+    use crate::{ArenaArc, Pool, SharedArena};
+    use std::collections::{BTreeSet, HashSet};
+    use std::io::{Read, Write, BufRead};
+
+    #[test]
+    fn std_impls_cmp_hash() {
+        let arena = SharedArena::new();
+        let pool = Pool::new();
+
+        let arcs: HashSet<_> = [1, 2, 1].iter().map(|n| arena.alloc_arc(*n)).collect();
+        assert_eq!(arcs.len(), 2);
+        assert!(arcs.contains(&1));
+
+        let boxes: BTreeSet<_> = [3, 1, 2].iter().map(|n| pool.alloc(*n)).collect();
+        assert_eq!(boxes.iter().map(|b| **b).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        assert!(pool.alloc_rc(1) < pool.alloc_rc(2));
+        assert_eq!(arena.alloc(1), arena.alloc(1));
+
+        let a = arena.alloc_arc(1);
+        assert!(ArenaArc::ptr_eq(&a, &a.clone()));
+        assert!(!ArenaArc::ptr_eq(&a, &arena.alloc_arc(1)));
+    }
+
+    #[test]
+    fn std_impls_iter_io() {
+        let arena = SharedArena::new();
+        let mut iter = arena.alloc(vec![1, 2, 3, 4].into_iter());
+
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next_back(), Some(4));
+        assert_eq!(iter.by_ref().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let pool = Pool::new();
+        let mut writer = pool.alloc(Vec::new());
+        write!(writer, "line1\nline2").unwrap();
+        assert_eq!(writer.as_slice(), b"line1\nline2");
+
+        let cursors = Pool::new();
+        let mut reader = cursors.alloc(std::io::Cursor::new(writer.clone()));
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "line1\n");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "line2");
+    }
+
+    #[test]
+    fn std_impls_future() {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+        fn noop_raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker { noop_raw_waker() }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+
+        // Unpin future, polled through the handle
+        let pool = Pool::new();
+        let mut ready = pool.alloc(std::future::ready(1));
+        assert_eq!(Pin::new(&mut ready).poll(&mut cx), Poll::Ready(1));
+
+        // !Unpin future holding a reference to its own state
+        let arena = SharedArena::new();
+        let mut fut = arena.alloc_pin(async {
+            let values = [1, 2, 3];
+            let first = &values[0];
+            std::future::ready(()).await;
+            *first + values[2]
+        });
+        assert_eq!(fut.as_mut().poll(&mut cx), Poll::Ready(4));
+    }
+
+    #[test]
+    fn std_impls_error() {
+        use std::error::Error;
+
+        let arena = SharedArena::new();
+        let error = arena.alloc(std::io::Error::other("oops"));
+
+        let error: &dyn Error = &error;
+        assert_eq!(error.to_string(), "oops");
+    }
+}
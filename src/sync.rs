@@ -0,0 +1,63 @@
+//! Synchronization primitives used by the crate
+//!
+//! All the atomics go through this module: with `--cfg loom`, they
+//! are replaced by the ones of [`loom`], which explores all the
+//! interleavings of the threads in the models of `loom_models`.
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom_
+//! ```
+//!
+//! `Arc` and `Weak` are not replaced: loom doesn't support weak
+//! references. The reference counting of the handles uses the
+//! counter of their block, which is replaced.
+//!
+//! [`loom`]: https://docs.rs/loom
+
+#[cfg(not(loom))]
+pub(crate) mod atomic {
+    pub(crate) use std::sync::atomic::{
+        fence, AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering,
+    };
+    #[cfg(target_has_atomic = "64")]
+    pub(crate) use std::sync::atomic::AtomicU64;
+}
+
+#[cfg(loom)]
+pub(crate) mod atomic {
+    pub(crate) use loom::sync::atomic::{
+        fence, AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering,
+    };
+    #[cfg(target_has_atomic = "64")]
+    pub(crate) use loom::sync::atomic::AtomicU64;
+}
+
+#[cfg(not(loom))]
+pub(crate) use std::thread::yield_now;
+
+#[cfg(loom)]
+pub(crate) use loom::thread::yield_now;
+
+/// Access to the value of an `AtomicUsize` through a `&mut`
+///
+/// `get_mut` doesn't exist with loom, which needs to know when the
+/// value is accessed.
+pub(crate) trait WithMut {
+    fn with_value_mut<R>(&mut self, f: impl FnOnce(&mut usize) -> R) -> R;
+}
+
+#[cfg(not(loom))]
+impl WithMut for atomic::AtomicUsize {
+    #[inline]
+    fn with_value_mut<R>(&mut self, f: impl FnOnce(&mut usize) -> R) -> R {
+        f(self.get_mut())
+    }
+}
+
+#[cfg(loom)]
+impl WithMut for atomic::AtomicUsize {
+    #[inline]
+    fn with_value_mut<R>(&mut self, f: impl FnOnce(&mut usize) -> R) -> R {
+        loom::sync::atomic::AtomicUsize::with_mut(self, f)
+    }
+}
@@ -0,0 +1,185 @@
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+
+use crate::{Arena, ArenaBox, ArenaArc, ArenaRc, Pool, PoolBox, SharedArena, ShrinkReport};
+
+/// Common interface of [`SharedArena`], [`Arena`] and [`Pool`]
+///
+/// Code generic over `TypedAlloc` can switch from one memory pool to
+/// another by changing a single type.
+///
+/// ## Example
+///
+/// ```
+/// use shared_arena::{Arena, Pool, SharedArena, TypedAlloc};
+///
+/// struct Stack<T, A: TypedAlloc<T>> {
+///     arena: A,
+///     values: Vec<A::Box>,
+/// }
+///
+/// impl<T, A: TypedAlloc<T>> Stack<T, A> {
+///     fn push(&mut self, value: T) {
+///         let value = self.arena.alloc(value);
+///         self.values.push(value);
+///     }
+/// }
+///
+/// let mut stack = Stack { arena: Pool::new(), values: Vec::new() };
+/// stack.push(1);
+/// assert_eq!(stack.arena.stats(), (1, 62));
+///
+/// let mut stack = Stack { arena: SharedArena::new(), values: Vec::new() };
+/// stack.push(1);
+/// assert_eq!(stack.arena.stats(), (1, 62));
+/// ```
+///
+/// [`SharedArena`]: ./struct.SharedArena.html
+/// [`Arena`]: ./struct.Arena.html
+/// [`Pool`]: ./struct.Pool.html
+pub trait TypedAlloc<T> {
+    /// Unique pointer returned by [`alloc`](#tymethod.alloc)
+    type Box: Deref<Target = T> + DerefMut;
+    /// Reference-counting pointer returned by [`alloc_rc`](#tymethod.alloc_rc)
+    type Rc: Deref<Target = T> + Clone;
+
+    /// Writes a value in the arena, and returns a `Self::Box`
+    /// pointing to that value.
+    fn alloc(&self, value: T) -> Self::Box;
+
+    /// Finds an empty space in the arena and calls the function
+    /// `initializer` with its argument pointing to that space.
+    ///
+    /// See [`SharedArena::alloc_with`] for the requirements on
+    /// `initializer`.
+    ///
+    /// [`SharedArena::alloc_with`]: ./struct.SharedArena.html#method.alloc_with
+    fn alloc_with<F>(&self, initializer: F) -> Self::Box
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> &T;
+
+    /// Writes a value in the arena, and returns a `Self::Rc`
+    /// pointing to that value.
+    fn alloc_rc(&self, value: T) -> Self::Rc;
+
+    /// Returns a tuple of non-free and free spaces in the arena
+    fn stats(&self) -> (usize, usize);
+
+    /// Shrinks the capacity of the arena as much as possible.
+    fn shrink_to_fit(&self) -> ShrinkReport;
+}
+
+impl<T> TypedAlloc<T> for SharedArena<T> {
+    type Box = ArenaBox<T>;
+    type Rc = ArenaArc<T>;
+
+    fn alloc(&self, value: T) -> ArenaBox<T> {
+        SharedArena::alloc(self, value)
+    }
+
+    fn alloc_with<F>(&self, initializer: F) -> ArenaBox<T>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
+    {
+        SharedArena::alloc_with(self, initializer)
+    }
+
+    fn alloc_rc(&self, value: T) -> ArenaArc<T> {
+        SharedArena::alloc_arc(self, value)
+    }
+
+    fn stats(&self) -> (usize, usize) {
+        SharedArena::stats(self)
+    }
+
+    fn shrink_to_fit(&self) -> ShrinkReport {
+        SharedArena::shrink_to_fit(self)
+    }
+}
+
+impl<T> TypedAlloc<T> for Arena<T> {
+    type Box = ArenaBox<T>;
+    type Rc = ArenaRc<T>;
+
+    fn alloc(&self, value: T) -> ArenaBox<T> {
+        Arena::alloc(self, value)
+    }
+
+    fn alloc_with<F>(&self, initializer: F) -> ArenaBox<T>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
+    {
+        Arena::alloc_with(self, initializer)
+    }
+
+    fn alloc_rc(&self, value: T) -> ArenaRc<T> {
+        Arena::alloc_rc(self, value)
+    }
+
+    fn stats(&self) -> (usize, usize) {
+        Arena::stats(self)
+    }
+
+    fn shrink_to_fit(&self) -> ShrinkReport {
+        Arena::shrink_to_fit(self)
+    }
+}
+
+impl<T> TypedAlloc<T> for Pool<T> {
+    type Box = PoolBox<T>;
+    type Rc = ArenaRc<T>;
+
+    fn alloc(&self, value: T) -> PoolBox<T> {
+        Pool::alloc(self, value)
+    }
+
+    fn alloc_with<F>(&self, initializer: F) -> PoolBox<T>
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> &T
+    {
+        Pool::alloc_with(self, initializer)
+    }
+
+    fn alloc_rc(&self, value: T) -> ArenaRc<T> {
+        Pool::alloc_rc(self, value)
+    }
+
+    fn stats(&self) -> (usize, usize) {
+        Pool::stats(self)
+    }
+
+    fn shrink_to_fit(&self) -> ShrinkReport {
+        Pool::shrink_to_fit(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypedAlloc;
+    use crate::{Arena, Pool, SharedArena};
+
+    fn fill_and_shrink<A: TypedAlloc<usize>>(arena: A) {
+        let boxes: Vec<_> = (0..100).map(|n| arena.alloc(n)).collect();
+        let rc = arena.alloc_rc(100);
+        let rc2 = rc.clone();
+
+        assert_eq!(arena.stats().0, 101);
+        assert!(boxes.iter().enumerate().all(|(n, b)| **b == n));
+        assert_eq!(*rc2, 100);
+
+        std::mem::drop(boxes);
+        std::mem::drop((rc, rc2));
+        let report = arena.shrink_to_fit();
+
+        assert!(report.completed && report.pages_freed >= 1);
+        assert!(report.bytes_released > report.pages_freed * 63 * std::mem::size_of::<usize>());
+        assert_eq!(arena.stats().0, 0);
+    }
+
+    #[test]
+    fn typed_alloc_all() {
+        fill_and_shrink(SharedArena::new());
+        fill_and_shrink(Arena::new());
+        fill_and_shrink(Pool::new());
+    }
+}